@@ -0,0 +1,12 @@
+use std::process::Command;
+
+/// Records version of compiler, plugins have to be built with the same one
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let version = Command::new(rustc).arg("--version").output().ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=PCP_RUSTC_VERSION={}", version);
+    println!("cargo:rerun-if-changed=build.rs");
+}
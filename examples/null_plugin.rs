@@ -0,0 +1,68 @@
+//!
+//! Sample plugin with writer which discards everything written to `null://` URLs.
+//! Copy `libnull_plugin.so` to `~/.local/lib/pcp/plugins` to try it out:
+//!
+//! ```shell
+//! pcp -r photos null://
+//! ```
+//!
+
+use std::path::Path;
+
+use async_trait::async_trait;
+use pcp::copy::DynBuffer;
+use pcp::plugin::PluginRegistrar;
+use pcp::utils::stat::ObjectStat;
+use pcp::{Location, PcpResult, Writer};
+
+/// Writer which only counts bytes
+struct NullWriter {}
+
+#[async_trait]
+impl Writer for NullWriter {
+    fn new(_url: &Location) -> PcpResult<Self> where Self: Sized {
+        Ok(NullWriter {})
+    }
+
+    fn can_write(url: &Location) -> bool where Self: Sized {
+        url.scheme() == Some("null")
+    }
+
+    fn is_directory(url: &Location) -> bool where Self: Sized {
+        url.path().is_empty() || url.has_trailing_separator()
+    }
+
+    fn make_directory(_url: &Location) -> PcpResult<()> where Self: Sized {
+        Ok(())
+    }
+
+    fn stat(_url: &Location) -> Option<ObjectStat> where Self: Sized {
+        None
+    }
+
+    fn rename(_from: &Location, _to: &Location) -> std::io::Result<()> where Self: Sized {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    fn remove(_url: &Location) -> std::io::Result<()> where Self: Sized {
+        Ok(())
+    }
+
+    fn set_metadata(_url: &Location, _stat: &ObjectStat) -> std::io::Result<()> where Self: Sized {
+        Ok(())
+    }
+
+    fn join_path(base: &Location, path: &Path) -> Location where Self: Sized {
+        base.join(path)
+    }
+
+    async fn write_chunk(&mut self, _chunk: &DynBuffer, size: usize) -> PcpResult<usize> {
+        Ok(size)
+    }
+}
+
+fn register(registrar: &mut PluginRegistrar) {
+    registrar.add_writer::<NullWriter>("null", 0);
+}
+
+pcp::declare_plugin!("null", register);
@@ -1,18 +1,257 @@
-use clap::Parser;
+use std::ffi::OsString;
+
+use clap::{Parser, ValueEnum};
+
+use crate::error::ErrorCallback;
+use crate::plan::PlanCallback;
+use crate::progress::callback::ProgressCallback;
+use crate::utils::parse_size;
+
+/// Which existing destination files may be replaced
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum UpdateMode {
+    /// Replace all existing files
+    All,
+    /// Do not replace existing files
+    None,
+    /// Replace only files older than source
+    Older,
+}
+
+/// How backups of replaced destination files are named
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum BackupMode {
+    /// Never make backups
+    #[value(alias = "off")]
+    None,
+    /// Make numbered backups
+    #[value(alias = "t")]
+    Numbered,
+    /// Numbered if numbered backups exist, simple otherwise
+    #[value(alias = "nil")]
+    Existing,
+    /// Always make simple backups
+    #[value(alias = "never")]
+    Simple,
+}
+
+/// How report is printed after copying
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum SummaryFormat {
+    /// Human-readable summary
+    Text,
+    /// Single JSON object for scripts
+    Json,
+    /// Do not print summary
+    None,
+}
+
+/// Format which copied files are compressed with
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum CompressionFormat {
+    #[value(alias = "gz")]
+    Gzip,
+    #[value(alias = "zst")]
+    Zstd,
+    Xz,
+    #[value(alias = "bz2")]
+    Bzip2,
+}
 
 #[derive(Parser, Debug, Clone)]
-pub(crate) struct Args {
+#[command(allow_missing_positional = true)]
+pub struct Args {
     #[arg(short = 'r', long = "recursive", help = "Copy directories recursively")]
     pub recursive: bool,
     #[arg(short = 's', long = "no-progress", help = "Do not show progress")]
     pub no_progress: bool,
-    #[arg(help = "Source file/directories", required = true)]
-    pub srcs: Vec<String>,
-    #[arg(help = "Destination file/directory")]
-    pub dest: String,
+    #[arg(help = "Source file/directories", required_unless_present = "check_manifest")]
+    pub srcs: Vec<OsString>,
+    // Default value only lets exclusive options like --list-backends go without destination
+    #[arg(help = "Destination file/directory", required = true, default_value = "", hide_default_value = true)]
+    pub dest: OsString,
     #[arg(long = "fail-fast", help = "Fail on first error")]
     pub fail_fast: bool,
     #[arg(long = "max-chunks-number", default_value = "1024", 
           help = "Maximum number of cached chunks of file stored in memory")]
     pub max_chunks_number: u64,
-}
\ No newline at end of file
+    #[arg(long = "manifest", value_name = "FILE",
+          help = "Write sha256sum-compatible manifest of copied files to FILE")]
+    pub manifest: Option<OsString>,
+    #[arg(long = "check-manifest", value_name = "FILE", conflicts_with_all = ["srcs", "manifest"],
+          help = "Verify files in destination against sha256sum-compatible manifest FILE")]
+    pub check_manifest: Option<OsString>,
+    #[arg(short = 'n', long = "no-clobber", overrides_with_all = ["interactive", "force"],
+          help = "Do not overwrite an existing file")]
+    pub no_clobber: bool,
+    #[arg(short = 'i', long = "interactive", overrides_with_all = ["no_clobber", "force"],
+          help = "Prompt before overwrite")]
+    pub interactive: bool,
+    #[arg(long = "force", overrides_with_all = ["no_clobber", "interactive"],
+          help = "Remove existing destination file which can not be opened and try again")]
+    pub force: bool,
+    #[arg(short = 'u', long = "update", value_enum, num_args = 0..=1, require_equals = true,
+          default_missing_value = "older", value_name = "CONTROL",
+          help = "Control which existing files are replaced [default: all, if no value: older]")]
+    pub update: Option<UpdateMode>,
+    #[arg(short = 'b', long = "backup", value_enum, num_args = 0..=1, require_equals = true,
+          value_name = "CONTROL",
+          help = "Make a backup of each existing destination file [if no value: $VERSION_CONTROL or existing]")]
+    pub backup: Option<Option<BackupMode>>,
+    #[arg(short = 'S', long = "suffix", value_name = "SUFFIX",
+          help = "Override the usual backup suffix [default: $SIMPLE_BACKUP_SUFFIX or ~]")]
+    pub suffix: Option<String>,
+    #[arg(long = "move", help = "Move sources instead of copying them(default when invoked as pmv)")]
+    pub move_sources: bool,
+    #[arg(long = "include", value_name = "PATTERN",
+          help = "Copy only files matching gitignore-style PATTERN unless they are excluded, may be repeated")]
+    pub include: Vec<String>,
+    #[arg(long = "exclude", value_name = "PATTERN",
+          help = "Skip files and directories matching gitignore-style PATTERN, may be repeated")]
+    pub exclude: Vec<String>,
+    #[arg(long = "exclude-from", value_name = "FILE", help = "Read exclude patterns from FILE")]
+    pub exclude_from: Vec<OsString>,
+    #[arg(long = "ignore-files", help = "Honour .gitignore and .ignore files in source directories")]
+    pub ignore_files: bool,
+    #[arg(long = "sync",
+          help = "Copy only files which differ from destination by size or modification time")]
+    pub sync: bool,
+    #[arg(long = "checksum", requires = "sync",
+          help = "Compare files by checksum instead of modification time when syncing")]
+    pub checksum: bool,
+    #[arg(long = "delete", requires = "sync",
+          help = "Delete destination files which are absent in source when syncing")]
+    pub delete: bool,
+    // -n is taken by --no-clobber, as in cp
+    #[arg(long = "dry-run", help = "Print what would be copied without changing anything")]
+    pub dry_run: bool,
+    #[arg(long = "summary", value_enum, num_args = 0..=1, require_equals = true,
+          default_missing_value = "text", value_name = "FORMAT",
+          help = "Print summary after copying [default: text for recursive copies and failures]")]
+    pub summary: Option<SummaryFormat>,
+    #[arg(long = "src-backend", value_name = "BACKEND",
+          help = "Read sources with given backend instead of choosing it by URL")]
+    pub src_backend: Option<String>,
+    #[arg(long = "dst-backend", value_name = "BACKEND",
+          help = "Write destination with given backend instead of choosing it by URL")]
+    pub dst_backend: Option<String>,
+    #[arg(long = "progress", value_name = "DISPLAY",
+          help = "Show progress with given display, e.g. one added by plugin [default: console]")]
+    pub progress: Option<String>,
+    #[arg(long = "compress", value_enum, require_equals = true, value_name = "FORMAT",
+          conflicts_with = "decompress", help = "Compress copied files, adding extension of FORMAT to their names")]
+    pub compress: Option<CompressionFormat>,
+    #[arg(long = "decompress",
+          help = "Decompress copied files of formats detected by their contents, dropping extension of format")]
+    pub decompress: bool,
+    #[arg(long = "encrypt-to", value_name = "RECIPIENT", conflicts_with = "decrypt",
+          help = "Encrypt copied files in age format to `age1...` key, file of such keys or `passphrase`, may be repeated")]
+    pub encrypt_to: Vec<String>,
+    #[arg(long = "decrypt", value_name = "IDENTITY",
+          help = "Decrypt copied files in age format with IDENTITY file or `passphrase`, dropping `.age` extension")]
+    pub decrypt: Option<String>,
+    #[arg(long = "skip", value_name = "SIZE", value_parser = parse_size, conflicts_with = "move_sources",
+          help = "Skip SIZE bytes at the beginning of source, e.g. 1M")]
+    pub skip: Option<usize>,
+    // Backup renames destination away, so there would be nothing to keep
+    #[arg(long = "seek", value_name = "SIZE", value_parser = parse_size,
+          conflicts_with_all = ["move_sources", "backup", "suffix"],
+          help = "Write at offset SIZE of destination, keeping the rest of it instead of truncating")]
+    pub seek: Option<usize>,
+    #[arg(long = "count", value_name = "SIZE", value_parser = parse_size, conflicts_with = "move_sources",
+          help = "Copy at most SIZE bytes of source")]
+    pub count: Option<usize>,
+    #[arg(long = "bwlimit", value_name = "RATE", value_parser = parse_size,
+          help = "Limit bandwidth of writing to RATE bytes per second, e.g. 50M, shared by all files")]
+    pub bwlimit: Option<usize>,
+    #[arg(long = "bwlimit-socket", value_name = "PATH",
+          help = "Listen on Unix socket PATH for new bandwidth limits while copying, e.g. `10M` or `none`")]
+    pub bwlimit_socket: Option<OsString>,
+    #[arg(long = "list-backends", exclusive = true, help = "List readers, writers and progress displays, including plugins")]
+    pub list_backends: bool,
+    #[arg(short = 'V', long = "version", help = "Print version, with --verbose also compiled-in features and backends")]
+    pub version: bool,
+    #[arg(short = 'v', long = "verbose", help = "Print more details, e.g. compiled-in backends with --version")]
+    pub verbose: bool,
+    /// Receives progress instead of terminal when program is used as library
+    #[arg(skip)]
+    pub progress_callback: Option<ProgressCallback>,
+    /// Receives errors as they happen, they are collected in report anyway
+    #[arg(skip)]
+    pub error_callback: Option<ErrorCallback>,
+    /// Receives plan of dry run and of each synchronized directory before it is copied
+    #[arg(skip)]
+    pub plan_callback: Option<PlanCallback>,
+}
+
+/// Default values are the same as when no options are given on command line
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            recursive: false,
+            no_progress: false,
+            srcs: Vec::new(),
+            dest: OsString::new(),
+            fail_fast: false,
+            max_chunks_number: 1024,
+            manifest: None,
+            check_manifest: None,
+            no_clobber: false,
+            interactive: false,
+            force: false,
+            update: None,
+            backup: None,
+            suffix: None,
+            move_sources: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            exclude_from: Vec::new(),
+            ignore_files: false,
+            sync: false,
+            checksum: false,
+            delete: false,
+            dry_run: false,
+            summary: None,
+            src_backend: None,
+            dst_backend: None,
+            progress: None,
+            compress: None,
+            decompress: false,
+            encrypt_to: Vec::new(),
+            decrypt: None,
+            skip: None,
+            seek: None,
+            count: None,
+            bwlimit: None,
+            bwlimit_socket: None,
+            list_backends: false,
+            version: false,
+            verbose: false,
+            progress_callback: None,
+            error_callback: None,
+            plan_callback: None,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_matches_empty_command_line() {
+        let parsed = Args::parse_from(["pcp", "source", "destination"]);
+        let expected = Args {
+            srcs: vec![OsString::from("source")],
+            dest: OsString::from("destination"),
+            ..Args::default()
+        };
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", expected));
+    }
+
+    #[test]
+    fn test_seek_conflicts_with_backup() {
+        assert!(Args::try_parse_from(["pcp", "--seek", "4", "--backup=simple", "source", "destination"]).is_err());
+        assert!(Args::try_parse_from(["pcp", "--seek", "4", "-S", ".old", "source", "destination"]).is_err());
+        assert!(Args::try_parse_from(["pcp", "--seek", "4", "source", "destination"]).is_ok());
+    }
+}
@@ -0,0 +1,195 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use crate::arguments::{Args, BackupMode};
+use crate::error::{PcpError, PcpResult};
+use crate::factories::WriterProxy;
+use crate::location::Location;
+
+const DEFAULT_SUFFIX: &str = "~";
+
+///
+/// Keeps replaced destination files by renaming them with GNU-style names:
+/// `name~` for simple backups and `name.~N~` for numbered ones
+///
+#[derive(Debug, Clone)]
+pub struct BackupPolicy {
+    mode: BackupMode,
+    suffix: String,
+}
+
+impl FromStr for BackupMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <BackupMode as ValueEnum>::from_str(s, false)
+    }
+}
+
+impl BackupPolicy {
+    ///
+    /// Creates backup policy from arguments
+    ///
+    /// # Arguments
+    ///
+    /// * `args`: program-wide arguments
+    ///
+    /// returns: Option<BackupPolicy>: None if backups are disabled
+    ///
+    pub fn from_args(args: &Args) -> Option<BackupPolicy> {
+        let mode = match (args.backup, &args.suffix) {
+            (Some(Some(mode)), _) => mode,
+            // Like in GNU cp, giving only suffix enables backups as well
+            (Some(None), _) | (None, Some(_)) => std::env::var("VERSION_CONTROL").ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(BackupMode::Existing),
+            (None, None) => return None,
+        };
+        if mode == BackupMode::None {
+            return None;
+        }
+        let suffix = args.suffix.clone()
+            .or_else(|| std::env::var("SIMPLE_BACKUP_SUFFIX").ok())
+            .filter(|suffix| !suffix.is_empty() && !suffix.contains('/'))
+            .unwrap_or(DEFAULT_SUFFIX.to_string());
+        Some(BackupPolicy { mode, suffix })
+    }
+
+    ///
+    /// Chooses name for backup of destination. Like in GNU cp, numbered backup gets number
+    /// which follows the highest existing one.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination`: URL of file which is going to be replaced
+    /// * `siblings`: names of objects in directory of destination
+    ///
+    /// returns: Location: URL of backup
+    ///
+    pub fn backup_name<S: AsRef<OsStr>>(&self, destination: &Location,
+                                        siblings: impl IntoIterator<Item=S>) -> Location {
+        let name = destination.file_name().unwrap_or_default().as_bytes();
+        let last_number = siblings.into_iter()
+            .filter_map(|sibling| backup_number(name, sibling.as_ref().as_bytes()))
+            .max();
+        let next_number = match (self.mode, last_number) {
+            (BackupMode::Numbered, _) => last_number.unwrap_or(0) + 1,
+            (BackupMode::Existing, Some(number)) => number + 1,
+            _ => return destination.with_suffix(&self.suffix),
+        };
+        destination.with_suffix(&format!(".~{}~", next_number))
+    }
+
+    ///
+    /// Chooses name for backup of destination, looking for numbered backups next to it
+    ///
+    /// # Arguments
+    ///
+    /// * `destination`: URL of file which is going to be replaced
+    /// * `writer_proxy`: writer which owns destination
+    ///
+    /// returns: PcpResult<Location>: URL of backup, error if directory could not be listed
+    ///
+    pub fn backup_location(&self, destination: &Location, writer_proxy: &WriterProxy) -> PcpResult<Location> {
+        let siblings = if self.mode == BackupMode::Simple {
+            Vec::new()
+        } else {
+            let parent = Path::new(destination.path()).parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            writer_proxy.list_directory(&destination.with_path(parent.as_os_str())).map_err(|error| {
+                PcpError::other(destination, format!("Can not look for numbered backups: {}", error))
+            })?
+        };
+        Ok(self.backup_name(destination, siblings))
+    }
+
+    ///
+    /// Renames existing destination to its backup name
+    ///
+    /// # Arguments
+    ///
+    /// * `destination`: URL of file which is going to be replaced
+    /// * `writer_proxy`: writer which owns destination
+    ///
+    /// returns: PcpResult<()>: error if backup could not be made
+    ///
+    pub fn make_backup(&self, destination: &Location, writer_proxy: &WriterProxy) -> PcpResult<()> {
+        let backup = self.backup_location(destination, writer_proxy)?;
+        writer_proxy.rename(destination, &backup).map_err(|error| {
+            PcpError::other(destination, format!("Can not make backup {}: {}", backup, error))
+        })
+    }
+}
+
+///
+/// Gets number of backup `name.~N~`
+///
+/// # Arguments
+///
+/// * `name`: name of backed up file
+/// * `sibling`: name of another file in the same directory
+///
+/// returns: Option<usize>: None if sibling is not numbered backup of file
+///
+fn backup_number(name: &[u8], sibling: &[u8]) -> Option<usize> {
+    let number = sibling.strip_prefix(name)?.strip_prefix(b".~")?.strip_suffix(b"~")?;
+    // GNU cp does not count numbers with leading zeros
+    if number.first() == Some(&b'0') || !number.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(number).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(mode: BackupMode) -> BackupPolicy {
+        BackupPolicy {
+            mode,
+            suffix: DEFAULT_SUFFIX.to_string(),
+        }
+    }
+
+    fn backup_name(mode: BackupMode, destination: &str, siblings: &[&str]) -> String {
+        policy(mode).backup_name(&Location::parse(destination), siblings).to_string()
+    }
+
+    #[test]
+    fn test_simple_backup_name() {
+        assert_eq!(backup_name(BackupMode::Simple, "dir/file", &["file.~1~"]), "dir/file~");
+    }
+
+    #[test]
+    fn test_numbered_backup_name() {
+        let siblings = ["file", "file.~1~", "file.~2~", "file.~x~", "other~"];
+        assert_eq!(backup_name(BackupMode::Numbered, "dir/file", &siblings), "dir/file.~3~");
+        assert_eq!(backup_name(BackupMode::Numbered, "dir/other", &siblings), "dir/other.~1~");
+    }
+
+    #[test]
+    fn test_numbered_backup_name_with_gap() {
+        let siblings = ["file.~3~", "file.~10~", "file.~010~", "file.~4~.~1~"];
+        assert_eq!(backup_name(BackupMode::Numbered, "dir/file", &siblings), "dir/file.~11~");
+        assert_eq!(backup_name(BackupMode::Existing, "dir/file", &["file.~3~"]), "dir/file.~4~");
+    }
+
+    #[test]
+    fn test_existing_backup_name() {
+        let siblings = ["file.~1~"];
+        assert_eq!(backup_name(BackupMode::Existing, "dir/file", &siblings), "dir/file.~2~");
+        assert_eq!(backup_name(BackupMode::Existing, "dir/other", &siblings), "dir/other~");
+    }
+
+    #[test]
+    fn test_mode_aliases() {
+        assert_eq!("t".parse::<BackupMode>(), Ok(BackupMode::Numbered));
+        assert_eq!("nil".parse::<BackupMode>(), Ok(BackupMode::Existing));
+        assert_eq!("never".parse::<BackupMode>(), Ok(BackupMode::Simple));
+        assert_eq!("off".parse::<BackupMode>(), Ok(BackupMode::None));
+    }
+}
@@ -0,0 +1,308 @@
+//!
+//! Limit of write bandwidth, shared by all jobs of the process so that it is global.
+//! Limit is set by jobs which have one and stays until the last of them is over, it may be
+//! changed while copying through control socket.
+//!
+
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::utils::{format_size, parse_size};
+
+/// Longest sleep of writer, so that it notices change of limit soon enough
+const MAX_DELAY: Duration = Duration::from_millis(100);
+
+/// How often control socket looks for connections, so that it notices when job is over
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+///
+/// Token bucket which holds up to one second of traffic. Writer takes tokens before each chunk
+/// and may go into debt with large chunks, which is paid off by waiting.
+///
+pub struct TokenBucket {
+    /// Bytes per second, None if bandwidth is not limited
+    rate: Option<usize>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Makes bucket with given rate in bytes per second, which starts full
+    pub fn new(rate: Option<usize>) -> TokenBucket {
+        TokenBucket { rate, tokens: rate.unwrap_or(0) as f64, last_refill: Instant::now() }
+    }
+
+    #[inline]
+    pub fn rate(&self) -> Option<usize> {
+        self.rate
+    }
+
+    /// Changes rate, keeping tokens and debt which bucket has
+    pub fn set_rate(&mut self, rate: Option<usize>) {
+        self.refill();
+        self.rate = rate.filter(|rate| *rate > 0);
+        if let Some(rate) = self.rate {
+            self.tokens = self.tokens.min(rate as f64);
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        if let Some(rate) = self.rate {
+            let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        }
+        self.last_refill = now;
+    }
+
+    ///
+    /// Takes tokens for chunk if bucket is not in debt
+    ///
+    /// # Arguments
+    ///
+    /// * `amount`: size of chunk
+    ///
+    /// returns: Result<(), Duration>: time to wait before trying again if chunk has to wait
+    ///
+    pub fn take(&mut self, amount: usize) -> Result<(), Duration> {
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None => return Ok(()),
+        };
+        self.refill();
+        if self.tokens < 0.0 {
+            return Err(Duration::from_secs_f64(-self.tokens / rate as f64).min(MAX_DELAY));
+        }
+        self.tokens -= amount as f64;
+        Ok(())
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref BUCKET: Mutex<TokenBucket> = Mutex::new(TokenBucket::new(None));
+    /// Number of running jobs which have set limit
+    static ref LIMITED_JOBS: Mutex<usize> = Mutex::new(0);
+}
+
+///
+/// Sets bandwidth limit of all jobs
+///
+/// # Arguments
+///
+/// * `rate`: bytes per second, None or zero to remove limit
+///
+pub fn set_limit(rate: Option<usize>) {
+    BUCKET.lock().unwrap().set_rate(rate);
+}
+
+/// Gets bandwidth limit of all jobs in bytes per second
+pub fn limit() -> Option<usize> {
+    BUCKET.lock().unwrap().rate()
+}
+
+///
+/// Limit set by running job. Jobs without limit leave limits of the others in place, and
+/// limit is removed when the last job which has set it is over.
+///
+pub struct JobLimit(());
+
+impl JobLimit {
+    ///
+    /// Sets bandwidth limit of all jobs until returned value is dropped
+    ///
+    /// # Arguments
+    ///
+    /// * `rate`: bytes per second
+    ///
+    pub fn install(rate: usize) -> JobLimit {
+        let mut jobs = LIMITED_JOBS.lock().unwrap();
+        *jobs += 1;
+        set_limit(Some(rate));
+        JobLimit(())
+    }
+}
+
+impl Drop for JobLimit {
+    fn drop(&mut self) {
+        let mut jobs = LIMITED_JOBS.lock().unwrap();
+        *jobs -= 1;
+        if *jobs == 0 {
+            set_limit(None);
+        }
+    }
+}
+
+/// Waits until chunk of given size may be written
+pub(crate) async fn throttle(amount: usize) {
+    loop {
+        let result = BUCKET.lock().unwrap().take(amount);
+        match result {
+            Ok(()) => return,
+            Err(delay) => tokio::time::sleep(delay).await,
+        }
+    }
+}
+
+/// Describes limit for replies of control socket
+fn describe_limit(rate: Option<usize>) -> String {
+    match rate {
+        Some(rate) => format!("{}/s", format_size(rate)),
+        None => String::from("none"),
+    }
+}
+
+///
+/// Handles connection to control socket: every line sets new limit, like `10M` or `none`,
+/// and empty line only asks for current one. Each line is answered with limit in effect.
+///
+fn serve_connection(stream: UnixStream) -> std::io::Result<()> {
+    let mut output = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let reply = match line.trim() {
+            "" => Ok(limit()),
+            rate => if rate == "none" { Ok(0) } else { parse_size(rate) }.map(|rate| {
+                // Reply with limit set here, another job may replace it meanwhile
+                let rate = Some(rate).filter(|rate| *rate > 0);
+                set_limit(rate);
+                rate
+            }),
+        };
+        match reply {
+            Ok(rate) => writeln!(output, "bwlimit: {}", describe_limit(rate))?,
+            Err(error) => writeln!(output, "error: {}", error)?,
+        }
+    }
+    Ok(())
+}
+
+///
+/// Unix socket which changes bandwidth limit while job runs, e.g. with
+/// `echo 10M | socat - UNIX-CONNECT:/tmp/pcp.sock`. Socket is removed when it is dropped.
+///
+pub struct ControlSocket {
+    path: PathBuf,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlSocket {
+    ///
+    /// Starts listening on socket
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: path of socket, which must not exist
+    ///
+    /// returns: std::io::Result<ControlSocket>
+    ///
+    pub fn bind(path: &Path) -> std::io::Result<ControlSocket> {
+        let listener = UnixListener::bind(path)?;
+        // Blocked listener would not notice that socket is dropped
+        if let Err(error) = listener.set_nonblocking(true) {
+            let _ = std::fs::remove_file(path);
+            return Err(error);
+        }
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        let thread = std::thread::spawn(move || {
+            while !thread_stopped.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    // Client which stays connected does not hold up the others, and broken one
+                    // does not affect copying
+                    Ok((stream, _)) => if stream.set_nonblocking(false).is_ok() {
+                        std::thread::spawn(move || serve_connection(stream));
+                    },
+                    Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                    Err(_) => std::thread::sleep(ACCEPT_INTERVAL),
+                }
+            }
+        });
+        Ok(ControlSocket { path: path.to_path_buf(), stopped, thread: Some(thread) })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use super::*;
+    use crate::Args;
+
+    /// Tests which change limit of the process run one at a time
+    static LIMIT_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(Some(1000));
+        assert_eq!(bucket.take(600), Ok(()));
+        assert_eq!(bucket.take(600), Ok(()));
+        // Bucket is in debt of 200 bytes, which takes 0.2 seconds, but writer wakes up earlier
+        assert_eq!(bucket.take(1).unwrap_err(), MAX_DELAY);
+        bucket.set_rate(None);
+        assert_eq!(bucket.take(usize::MAX), Ok(()));
+    }
+
+    #[test]
+    fn test_control_socket() {
+        let _lock = LIMIT_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let path = std::env::temp_dir().join(format!("pcp-bwlimit-{}.sock", std::process::id()));
+        let socket = ControlSocket::bind(&path).unwrap();
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"2M\nbogus\nnone\n").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let replies: Vec<String> = BufReader::new(stream).lines().map(Result::unwrap).collect();
+        assert_eq!(replies, ["bwlimit: 2.0 MiB/s", "error: Bad size: bogus", "bwlimit: none"]);
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_jobs_without_limit() {
+        let _lock = LIMIT_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let directory = std::env::temp_dir().join(format!("pcp-bwlimit-jobs-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("source"), b"hello").unwrap();
+        // Limited job waits in the middle of copying until the other one is over
+        let (started, finished) = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+        let waited = Arc::new(AtomicBool::new(false));
+        let limited = {
+            let (started, finished, directory) = (started.clone(), finished.clone(), directory.clone());
+            std::thread::spawn(move || crate::CopyJob::new(directory.join("limited"))
+                .source(directory.join("source"))
+                .options(Args { bwlimit: Some(1 << 30), ..Args::default() })
+                .on_progress(move |_, _, _| if !waited.swap(true, Ordering::SeqCst) {
+                    started.wait();
+                    finished.wait();
+                })
+                .run())
+        };
+        started.wait();
+        let unlimited = crate::CopyJob::new(directory.join("unlimited"))
+            .source(directory.join("source"))
+            .options(Args { no_progress: true, ..Args::default() })
+            .run();
+        let limit_during_job = limit();
+        finished.wait();
+        let report = limited.join().unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(unlimited.is_success() && report.is_success());
+        assert_eq!(limit_during_job, Some(1 << 30));
+        assert_eq!(limit(), None);
+    }
+}
@@ -0,0 +1,419 @@
+//!
+//! Compression formats of streams, e.g. compressed tarballs
+//!
+
+use std::ffi::{OsStr, OsString};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+
+use crate::arguments::CompressionFormat;
+use crate::utils::shared_buffer::SharedBuffer;
+
+/// Supported compression format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+/// Magic bytes which start streams of each format
+const MAGIC_BYTES: [(Codec, &[u8]); 4] = [
+    (Codec::Gzip, &[0x1f, 0x8b]),
+    (Codec::Zstd, &[0x28, 0xb5, 0x2f, 0xfd]),
+    (Codec::Xz, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+    (Codec::Bzip2, b"BZh"),
+];
+
+/// Length of the longest magic bytes, which is enough to detect format
+const MAGIC_LENGTH: usize = 6;
+
+/// File name extensions of each format, the usual one first, then short forms of compressed tarballs
+const EXTENSIONS: [(Codec, &[&str]); 4] = [
+    (Codec::Gzip, &["gz", "tgz"]),
+    (Codec::Zstd, &["zst", "tzst"]),
+    (Codec::Xz, &["xz", "txz"]),
+    (Codec::Bzip2, &["bz2", "tbz", "tbz2"]),
+];
+
+impl From<CompressionFormat> for Codec {
+    fn from(format: CompressionFormat) -> Codec {
+        match format {
+            CompressionFormat::Gzip => Codec::Gzip,
+            CompressionFormat::Zstd => Codec::Zstd,
+            CompressionFormat::Xz => Codec::Xz,
+            CompressionFormat::Bzip2 => Codec::Bzip2,
+        }
+    }
+}
+
+impl Codec {
+    ///
+    /// Detects format by the first bytes of stream
+    ///
+    /// # Arguments
+    ///
+    /// * `header`: beginning of stream, at least 6 bytes unless stream is shorter
+    ///
+    /// returns: Option<Codec>: None if stream is not compressed by known format
+    ///
+    pub fn from_magic(header: &[u8]) -> Option<Codec> {
+        MAGIC_BYTES.iter()
+            .find(|(_, magic)| header.starts_with(magic))
+            .map(|(codec, _)| *codec)
+    }
+
+    ///
+    /// Detects format by extension of file name, e.g. `.tar.zst` or `.tgz`
+    ///
+    pub fn from_file_name(name: &OsStr) -> Option<Codec> {
+        let extension = std::path::Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+        EXTENSIONS.iter()
+            .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+            .map(|(codec, _)| *codec)
+    }
+
+    /// Gets usual file name extension of format, e.g. `zst`
+    pub fn extension(self) -> &'static str {
+        EXTENSIONS.iter()
+            .find(|(codec, _)| *codec == self)
+            .map(|(_, extensions)| extensions[0])
+            .unwrap()
+    }
+
+    ///
+    /// Wraps stream into decoder of this format
+    ///
+    /// # Arguments
+    ///
+    /// * `input`: compressed stream
+    ///
+    /// returns: std::io::Result<Box<dyn Read + Send>>: decompressed stream
+    ///
+    pub fn decoder(self, input: impl Read + Send + 'static) -> std::io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            // Multi-member decoder, since concatenated gzip files are valid as well
+            Codec::Gzip => Box::new(flate2::read::MultiGzDecoder::new(input)),
+            Codec::Zstd => Box::new(zstd::stream::read::Decoder::new(input)?),
+            Codec::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(input)),
+            Codec::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(input)),
+        })
+    }
+
+    ///
+    /// Wraps stream into encoder of this format with default compression level
+    ///
+    /// # Arguments
+    ///
+    /// * `output`: stream which receives compressed data
+    ///
+    /// returns: std::io::Result<Box<dyn CodecWriter>>: stream of uncompressed data
+    ///
+    pub fn encoder(self, output: impl Write + Send + 'static) -> std::io::Result<Box<dyn CodecWriter>> {
+        Ok(match self {
+            Codec::Gzip => Box::new(flate2::write::GzEncoder::new(output, flate2::Compression::default())),
+            Codec::Zstd => Box::new(zstd::stream::write::Encoder::new(output, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+            Codec::Xz => Box::new(xz2::write::XzEncoder::new(output, 6)),
+            Codec::Bzip2 => Box::new(bzip2::write::BzEncoder::new(output, bzip2::Compression::default())),
+        })
+    }
+
+    ///
+    /// Makes decoder of this format which receives compressed data by writes, so that
+    /// it can be fed with chunks as they come
+    ///
+    /// # Arguments
+    ///
+    /// * `output`: stream which receives decompressed data
+    ///
+    /// returns: std::io::Result<Box<dyn CodecWriter>>: stream of compressed data
+    ///
+    pub fn write_decoder(self, output: impl Write + Send + 'static) -> std::io::Result<Box<dyn CodecWriter>> {
+        Ok(match self {
+            Codec::Gzip => Box::new(flate2::write::MultiGzDecoder::new(output)),
+            Codec::Zstd => Box::new(zstd::stream::write::Decoder::new(output)?),
+            Codec::Xz => Box::new(xz2::write::XzDecoder::new_multi_decoder(output)),
+            Codec::Bzip2 => Box::new(MultiBzDecoder(Some(bzip2::write::BzDecoder::new(output)))),
+        })
+    }
+}
+
+///
+/// Gets name of file after decompression, e.g. `dump.sql` for `dump.sql.gz` or `release.tar` for `release.tgz`
+///
+/// returns: Option<OsString>: None if name has no extension of known format
+///
+pub fn decompressed_name(name: &OsStr) -> Option<OsString> {
+    let codec = Codec::from_file_name(name)?;
+    let path = std::path::Path::new(name);
+    let extension = path.extension()?;
+    let stem = &name.as_bytes()[..name.len() - extension.len() - 1];
+    let mut result = OsStr::from_bytes(stem).to_os_string();
+    // Short forms stand for compressed tarballs
+    if !extension.eq_ignore_ascii_case(codec.extension()) {
+        result.push(".tar");
+    }
+    Some(result).filter(|result| !result.is_empty())
+}
+
+///
+/// Stream which compresses or decompresses data written to it. Formats write their trailers
+/// only when finished explicitly, since errors of dropped streams would be lost.
+///
+pub trait CodecWriter: Write + Send {
+    /// Writes trailer of format and flushes underlying stream
+    fn finish(self: Box<Self>) -> std::io::Result<()>;
+}
+
+impl<W: Write + Send> CodecWriter for flate2::write::GzEncoder<W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        flate2::write::GzEncoder::finish(*self)?.flush()
+    }
+}
+
+impl<W: Write + Send> CodecWriter for zstd::stream::write::Encoder<'static, W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        zstd::stream::write::Encoder::finish(*self)?.flush()
+    }
+}
+
+impl<W: Write + Send> CodecWriter for xz2::write::XzEncoder<W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        xz2::write::XzEncoder::finish(*self)?.flush()
+    }
+}
+
+impl<W: Write + Send> CodecWriter for bzip2::write::BzEncoder<W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        bzip2::write::BzEncoder::finish(*self)?.flush()
+    }
+}
+
+impl<W: Write + Send> CodecWriter for flate2::write::MultiGzDecoder<W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        flate2::write::MultiGzDecoder::finish(*self)?.flush()
+    }
+}
+
+impl<W: Write + Send> CodecWriter for zstd::stream::write::Decoder<'static, W> {
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write + Send> CodecWriter for xz2::write::XzDecoder<W> {
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        xz2::write::XzDecoder::finish(&mut self)?.flush()
+    }
+}
+
+///
+/// Decoder of bzip2 streams which follow each other, e.g. made by parallel compressors.
+/// Decoder of the crate stops after the first stream, so the next one is started for the rest.
+///
+struct MultiBzDecoder<W: Write>(Option<bzip2::write::BzDecoder<W>>);
+
+impl<W: Write> Write for MultiBzDecoder<W> {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        let decoder = self.0.as_mut().unwrap();
+        let written = decoder.write(buffer)?;
+        if written > 0 || buffer.is_empty() {
+            return Ok(written);
+        }
+        // Previous stream has ended
+        let output = decoder.finish()?;
+        self.0 = Some(bzip2::write::BzDecoder::new(output));
+        self.0.as_mut().unwrap().write(buffer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write + Send> CodecWriter for MultiBzDecoder<W> {
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        self.0.take().unwrap().finish()?.flush()
+    }
+}
+
+///
+/// Compresses or decompresses stream chunk by chunk, so that it runs between reader and writer.
+/// Decompression detects format by magic bytes and passes streams of unknown format as they are.
+///
+pub struct CodecStage {
+    /// None until format is detected, and for streams which are passed as is
+    codec_writer: Option<Box<dyn CodecWriter>>,
+    output: SharedBuffer,
+    /// Beginning of stream which is kept until it is long enough to detect format
+    header: Option<Vec<u8>>,
+}
+
+impl CodecStage {
+    /// Makes stage which compresses stream with given format
+    pub fn compress(codec: Codec) -> std::io::Result<CodecStage> {
+        let output = SharedBuffer::default();
+        Ok(CodecStage {
+            codec_writer: Some(codec.encoder(output.clone())?),
+            output,
+            header: None,
+        })
+    }
+
+    /// Makes stage which decompresses stream of any supported format
+    pub fn decompress() -> CodecStage {
+        CodecStage {
+            codec_writer: None,
+            output: SharedBuffer::default(),
+            header: Some(Vec::new()),
+        }
+    }
+
+    /// Chooses decoder by the first bytes of stream
+    fn detect(&mut self, header: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        match Codec::from_magic(&header) {
+            Some(codec) => {
+                let mut decoder = codec.write_decoder(self.output.clone())?;
+                decoder.write_all(&header)?;
+                self.codec_writer = Some(decoder);
+                Ok(self.output.take())
+            }
+            None => Ok(header),
+        }
+    }
+
+    ///
+    /// Passes chunk of stream through codec
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk`: next part of stream
+    ///
+    /// returns: std::io::Result<Vec<u8>>: data which codec has produced so far, may be empty
+    ///
+    pub fn process(&mut self, chunk: &[u8]) -> std::io::Result<Vec<u8>> {
+        if let Some(mut header) = self.header.take() {
+            header.extend_from_slice(chunk);
+            if header.len() < MAGIC_LENGTH {
+                self.header = Some(header);
+                return Ok(Vec::new());
+            }
+            return self.detect(header);
+        }
+        match &mut self.codec_writer {
+            Some(codec_writer) => {
+                codec_writer.write_all(chunk)?;
+                Ok(self.output.take())
+            }
+            None => Ok(chunk.to_vec()),
+        }
+    }
+
+    ///
+    /// Completes stream
+    ///
+    /// returns: std::io::Result<Vec<u8>>: the rest of data, e.g. trailer of format
+    ///
+    pub fn finish(mut self) -> std::io::Result<Vec<u8>> {
+        let mut output = match self.header.take() {
+            // Stream is shorter than magic bytes
+            Some(header) => self.detect(header)?,
+            None => Vec::new(),
+        };
+        if let Some(codec_writer) = self.codec_writer.take() {
+            codec_writer.finish()?;
+            output.extend(self.output.take());
+        }
+        Ok(output)
+    }
+}
+
+///
+/// Decompresses stream if it starts with magic bytes of known format, otherwise passes it as is
+///
+/// # Arguments
+///
+/// * `input`: stream which may be compressed
+///
+/// returns: std::io::Result<(Box<dyn Read + Send>, Option<Codec>)>: stream and detected format
+///
+pub fn auto_decoder(input: impl Read + Send + 'static) -> std::io::Result<(Box<dyn Read + Send>, Option<Codec>)> {
+    let mut input = BufReader::new(input);
+    let codec = Codec::from_magic(input.fill_buf()?);
+    match codec {
+        Some(codec) => Ok((codec.decoder(input)?, Some(codec))),
+        None => Ok((Box::new(input), None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detection() {
+        assert_eq!(Codec::from_magic(&[0x28, 0xb5, 0x2f, 0xfd, 0, 0]), Some(Codec::Zstd));
+        assert_eq!(Codec::from_magic(b"BZh91AY"), Some(Codec::Bzip2));
+        assert_eq!(Codec::from_magic(b"ustar"), None);
+        assert_eq!(Codec::from_file_name(OsStr::new("release.TAR.GZ")), Some(Codec::Gzip));
+        assert_eq!(Codec::from_file_name(OsStr::new("release.txz")), Some(Codec::Xz));
+        assert_eq!(Codec::from_file_name(OsStr::new("release.tar")), None);
+        assert_eq!(decompressed_name(OsStr::new("dump.sql.gz")), Some(OsString::from("dump.sql")));
+        assert_eq!(decompressed_name(OsStr::new("release.TZST")), Some(OsString::from("release.tar")));
+        assert_eq!(decompressed_name(OsStr::new("notes.txt")), None);
+    }
+
+    #[test]
+    fn test_auto_decoder() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"hello").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let (mut decoder, codec) = auto_decoder(std::io::Cursor::new(compressed)).unwrap();
+        let mut output = String::new();
+        decoder.read_to_string(&mut output).unwrap();
+        assert_eq!((output.as_str(), codec), ("hello", Some(Codec::Gzip)));
+        let (mut plain, codec) = auto_decoder(std::io::Cursor::new(b"plain".to_vec())).unwrap();
+        output.clear();
+        plain.read_to_string(&mut output).unwrap();
+        assert_eq!((output.as_str(), codec), ("plain", None));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for codec in [Codec::Gzip, Codec::Zstd, Codec::Xz, Codec::Bzip2] {
+            let path = std::env::temp_dir().join(format!("pcp-codec-{:?}-{}", codec, std::process::id()));
+            let mut encoder = codec.encoder(std::fs::File::create(&path).unwrap()).unwrap();
+            encoder.write_all(b"round trip").unwrap();
+            encoder.finish().unwrap();
+            let (mut decoder, detected) = auto_decoder(std::fs::File::open(&path).unwrap()).unwrap();
+            let mut output = String::new();
+            decoder.read_to_string(&mut output).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!((output.as_str(), detected), ("round trip", Some(codec)));
+        }
+    }
+
+    /// Feeds stage with small chunks, so that headers and frames are split between them
+    fn run_stage(mut stage: CodecStage, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        for chunk in input.chunks(3) {
+            output.extend(stage.process(chunk).unwrap());
+        }
+        output.extend(stage.finish().unwrap());
+        output
+    }
+
+    #[test]
+    fn test_stages() {
+        let data = b"stage ".repeat(1000);
+        for codec in [Codec::Gzip, Codec::Zstd, Codec::Xz, Codec::Bzip2] {
+            let compressed = run_stage(CodecStage::compress(codec).unwrap(), &data);
+            assert_eq!(Codec::from_magic(&compressed), Some(codec));
+            // Concatenated streams are decompressed as a whole
+            let concatenated = [compressed.clone(), compressed].concat();
+            assert_eq!(run_stage(CodecStage::decompress(), &concatenated), [data.clone(), data.clone()].concat());
+        }
+        assert_eq!(run_stage(CodecStage::decompress(), b"plain"), b"plain");
+    }
+}
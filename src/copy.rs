@@ -1,20 +1,36 @@
-use colored::Colorize;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
 
 use crate::arguments::Args;
-use crate::factories::{get_reader_proxy_for_url, get_writer_proxy_for_url};
-use crate::progress::console::ConsoleProgress;
+use crate::bandwidth;
+use crate::backup::BackupPolicy;
+use crate::error::{PcpError, PcpResult};
+use crate::filter::PathFilter;
+use crate::factories::{get_reader_proxy_by_backend, get_writer_proxy_by_backend, ReaderProxy, WriterProxy,
+                       PROGRESS_FACTORY};
+use crate::location::Location;
+use crate::manifest::{hash_url, Manifest};
+use crate::overwrite::OverwritePolicy;
+use crate::plan::{Plan, PlanAction};
+use crate::job::CopyReport;
+use crate::progress::callback::CallbackProgress;
 use crate::progress::dummy::DummyProgress;
 use crate::progress::ProgressDisplay;
 use crate::reader::Reader;
+use crate::transform::{self, hash::HashTransform, Transform};
 use crate::utils::runtime::tokio_block_on;
+use crate::utils::stat::ObjectStat;
 use crate::writer::Writer;
 
 /// Buffer for file chunks
 pub type DynBuffer = Vec<u8>;
 
+/// Progress display used when `--progress` is not given
+const DEFAULT_PROGRESS: &str = "console";
+
 
 /// Generalizes buffer creation
-trait Buffer{
+pub trait Buffer{
     
     /// 
     /// Creates a buffer of given size
@@ -26,7 +42,7 @@ trait Buffer{
     /// 
     /// # Examples 
     /// 
-    /// ```
+    /// ```ignore
     /// let mut buffer = MyBuffer::make_buffer(1024);
     /// ```
     fn make_buffer(size: usize) -> Self where Self: Sized;
@@ -34,76 +50,549 @@ trait Buffer{
 
 impl Buffer for DynBuffer{
     fn make_buffer(size: usize) -> DynBuffer {
-        let mut buffer = DynBuffer::with_capacity(size);
-        for _ in 0..size{
-            buffer.push(0);
-        }
-        buffer
+        vec![0; size]
     }
 }
-#[inline]
-fn handle_error_if_needed(result: std::io::Result<usize>) -> bool{
-    if result.is_err(){
-        let error = result.err().unwrap();
-        crate::utils::term::flush();
-        println!("{}{}: {}", "Can not write destination".bold().red(), "".clear(),
-                 error.to_string());
-        true
-    } else { 
-        false
+pub fn get_progress_from_args(args: &Args) -> Box<dyn ProgressDisplay>{
+    if let Some(callback) = &args.progress_callback{
+        Box::new(CallbackProgress::with_callback(callback.clone()))
+    } else if args.no_progress{
+        Box::new(DummyProgress::new())
+    } else {
+        // Console display is absent from builds without `tui` feature, so progress is not shown there
+        let key = args.progress.as_deref().unwrap_or(DEFAULT_PROGRESS);
+        PROGRESS_FACTORY.lock().unwrap().produce(key).unwrap_or_else(|| Box::new(DummyProgress::new()))
     }
 }
 
-fn get_progress_from_args(args: &Args) -> Box<dyn ProgressDisplay>{
-    if args.no_progress{
-        Box::new(DummyProgress::new())
+/// Chunk of data on its way from reader to writer
+struct Chunk{
+    data: DynBuffer,
+    /// Bytes which chunk stands for in progress: bytes of source, unless transform measures
+    /// progress by its output
+    source_bytes: usize,
+}
+
+/// Part of source which is copied, set by `--skip` and `--count`
+#[derive(Clone, Copy, Default)]
+struct ByteRange{
+    /// Offset of the first copied byte
+    start: usize,
+    /// Bytes before start which reader could not seek over, so they are read and dropped
+    discard: usize,
+    /// Amount of bytes to copy, None to copy until end
+    count: Option<usize>,
+}
+
+type ChunkSender = tokio::sync::mpsc::Sender<Option<Chunk>>;
+type ChunkReceiver = tokio::sync::mpsc::Receiver<Option<Chunk>>;
+
+///
+/// Sends output of transform to the next stage. Source bytes go with the first chunk, or with
+/// empty one if transform has held data back, so that progress does not stall.
+///
+/// returns: bool: whether the next stage still receives chunks
+///
+async fn send_chunks(tx: &ChunkSender, mut outputs: Vec<DynBuffer>, mut source_bytes: usize) -> bool{
+    if outputs.is_empty() && source_bytes > 0{
+        outputs.push(DynBuffer::new());
+    }
+    for data in outputs{
+        if tx.send(Some(Chunk{data, source_bytes})).await.is_err(){
+            return false;
+        }
+        source_bytes = 0;
+    }
+    true
+}
+
+///
+/// Runs single stage of transform chain until end marker or closed channel
+///
+/// # Arguments
+///
+/// * `transform`: transform of stage
+/// * `rx`: chunks of previous stage
+/// * `tx`: chunks for the next stage
+/// * `measures_progress`: whether progress is measured by output of stage instead of source
+///
+/// returns: PcpResult<()>: error of transform
+///
+async fn run_transform(mut transform: Box<dyn Transform>, mut rx: ChunkReceiver, tx: ChunkSender,
+                       measures_progress: bool) -> PcpResult<()>{
+    let output_bytes = |outputs: &Vec<DynBuffer>, source_bytes: usize| if measures_progress{
+        outputs.iter().map(Vec::len).sum()
     } else {
-        Box::new(ConsoleProgress::new())
+        source_bytes
+    };
+    loop {
+        let chunk = match rx.recv().await{
+            Some(Some(chunk)) => chunk,
+            Some(None) => break,
+            // Previous stage has failed, so stream is not completed
+            None => return Ok(()),
+        };
+        let outputs = transform.process(chunk.data).await?;
+        let source_bytes = output_bytes(&outputs, chunk.source_bytes);
+        if !send_chunks(&tx, outputs, source_bytes).await{
+            return Ok(());
+        }
     }
+    let outputs = transform.finish().await?;
+    let source_bytes = output_bytes(&outputs, 0);
+    if send_chunks(&tx, outputs, source_bytes).await{
+        let _ = tx.send(None).await;
+    }
+    Ok(())
 }
 
-async fn do_copy(mut reader: Box<dyn Reader>, mut writer: Box<dyn Writer>,
+///
+/// Copies data from reader to writer through chain of transforms. Reading, every transform
+/// and writing run concurrently, connected by channels which hold up to `max_chunks_staged` chunks.
+///
+/// returns: PcpResult<usize>: amount of source bytes which were copied
+///
+#[allow(clippy::too_many_arguments)]
+async fn do_copy(mut reader: Box<dyn Reader>, mut writer: Box<dyn Writer>, destination: &Location,
                        mut progress: Box<dyn ProgressDisplay>,
+                       transforms: Vec<Box<dyn Transform>>,
+                       range: ByteRange,
                        max_chunks_staged: usize,
-                       chunk_size: usize) -> bool{
+                       chunk_size: usize) -> PcpResult<usize>{
     let (tx, mut rx) =
-        tokio::sync::mpsc::channel::<Option<(usize, DynBuffer)>>(max_chunks_staged);
-    let size = reader.get_size();
-    progress.set_size(size);
+        tokio::sync::mpsc::channel::<Option<Chunk>>(max_chunks_staged);
+    let size = reader.get_size()?.saturating_sub(range.start);
+    let size = range.count.map_or(size, |count| count.min(size));
+    // Transforms like compression do not know size of their output in advance
+    if let Some(output_size) = transforms.iter().try_fold(size, |size, transform| transform.output_size(size)){
+        writer.set_size(output_size)?;
+    }
+    // Progress follows source, unless the last transform which measures it by its output
+    let mut progress_stage = None;
+    let mut progress_size = size;
+    let mut input_size = Some(size);
+    for (index, transform) in transforms.iter().enumerate(){
+        if let Some(stage_size) = input_size.and_then(|input_size| transform.progress_size(input_size)){
+            progress_stage = Some(index);
+            progress_size = stage_size;
+        }
+        input_size = input_size.and_then(|input_size| transform.output_size(input_size));
+    }
+    progress.set_size(progress_size);
     let read_coroutine = async move{
         let mut buffer = DynBuffer::make_buffer(chunk_size);
+        let mut discard = range.discard;
+        while discard > 0{
+            let size = discard.min(chunk_size);
+            match reader.read_chunk(&mut buffer[..size], size).await?{
+                0 => break,
+                bytes_read => discard -= bytes_read,
+            }
+        }
+        let mut remaining = range.count;
+        let mut copied = 0;
         loop {
-            let bytes_read = reader.read_chunk(&mut buffer, chunk_size).await;
-            //println!("{}", bytes_read);
+            let size = remaining.map_or(chunk_size, |remaining| remaining.min(chunk_size));
+            // On error channel is closed without end marker, so the rest of chain stops as well
+            let bytes_read = if size > 0 { reader.read_chunk(&mut buffer[..size], size).await? } else { 0 };
+            remaining = remaining.map(|remaining| remaining - bytes_read);
+            copied += bytes_read;
             if bytes_read == 0{
-                tx.send(None).await.expect("Can not send buffer");
+                let _ = tx.send(None).await;
                 break;
             }
-            if tx.send(Some((bytes_read, buffer.clone()))).await.is_err(){
+            let chunk = Chunk{data: buffer[..bytes_read].to_vec(), source_bytes: bytes_read};
+            if tx.send(Some(chunk)).await.is_err(){
                 break;
             }
         }
-        true
+        Ok(copied)
     };
+    let mut stages = Vec::with_capacity(transforms.len());
+    for (index, transform) in transforms.into_iter().enumerate(){
+        let (stage_tx, stage_rx) = tokio::sync::mpsc::channel::<Option<Chunk>>(max_chunks_staged);
+        stages.push(run_transform(transform, rx, stage_tx, progress_stage == Some(index)));
+        rx = stage_rx;
+    }
     let write_coroutine = async move {
-        let mut result = true;
-        loop  {
-            let chunk_wrapped = rx.recv().await.unwrap();
-            if chunk_wrapped.is_none(){
+        let mut result = Ok(());
+        // Channel is closed without end marker if stream is not completed
+        while let Some(message) = rx.recv().await {
+            let mut chunk = match message{
+                Some(chunk) => chunk,
+                None => {
+                    result = writer.close();
+                    break;
+                }
+            };
+            let source_bytes = chunk.source_bytes;
+            if !chunk.data.is_empty(){
+                bandwidth::throttle(chunk.data.len()).await;
+            }
+            // Writers may take only part of chunk, so the rest is written again
+            while !chunk.data.is_empty(){
+                match writer.write_chunk(&chunk.data, chunk.data.len()).await{
+                    Ok(0) => result = Err(PcpError::other(destination, "Nothing was written")),
+                    Ok(written) => {
+                        chunk.data.drain(..written);
+                        continue;
+                    }
+                    Err(error) => result = Err(error),
+                }
                 break;
             }
-            let (n, chunk) = chunk_wrapped.unwrap();
-            if handle_error_if_needed(writer.write_chunk(&chunk, n).await){
-                result = false;
+            if result.is_err(){
                 break;
             }
-            progress.add_bytes_written(n);
+            progress.add_bytes_written(source_bytes);
         }
         progress.flush();
         result
     };
-    let (result_read, result_write) = tokio::join!(read_coroutine, write_coroutine);
-    result_read && result_write
+    let (result_read, result_transforms, result_write) =
+        tokio::join!(read_coroutine, futures::future::try_join_all(stages), write_coroutine);
+    let copied = result_read?;
+    result_transforms?;
+    result_write.map(|_| copied)
+}
+
+///
+/// Reports failure of object to error callback and records it in report
+///
+/// # Arguments
+///
+/// * `report`: report to record failure into
+/// * `args`: program-wide arguments with error callback
+/// * `url`: URL of object which failed
+/// * `reason`: description of failure
+///
+/// returns: bool: always false, so that it can be returned right away
+///
+pub(crate) fn report_failure(report: &mut CopyReport, args: &Args, url: impl Display, reason: impl Display) -> bool{
+    let (url, reason) = (url.to_string(), reason.to_string());
+    notify_error(args, &url, &reason);
+    report.add_failure(&url, reason);
+    false
+}
+
+#[inline]
+fn report_error(report: &mut CopyReport, args: &Args, error: PcpError) -> bool{
+    report_failure(report, args, error.url(), error.reason())
+}
+
+/// Passes error or warning to error callback of job, if there is one
+pub(crate) fn notify_error(args: &Args, url: &str, reason: &str){
+    if let Some(callback) = &args.error_callback{
+        (callback.0)(url, reason);
+    }
+}
+
+///
+/// Applies modification time and permissions of source to copied file
+///
+/// # Arguments
+///
+/// * `destination`: copied file
+/// * `source_stat`: metadata of source taken before copying
+/// * `writer_proxy`: writer which owns destination
+///
+/// returns: PcpResult<()>: error if metadata could not be applied
+///
+fn preserve_metadata(destination: &Location, source_stat: Option<&ObjectStat>,
+                     writer_proxy: &WriterProxy) -> PcpResult<()>{
+    if let Some(stat) = source_stat{
+        writer_proxy.set_metadata(destination, stat).map_err(|error| {
+            PcpError::other(destination, format!("Can not preserve metadata: {}", error))
+        })?;
+    }
+    Ok(())
+}
+
+/// Moved file whose source is kept until its copy is verified
+struct PendingMove{
+    source: Location,
+    destination: Location,
+    source_stat: Option<ObjectStat>,
+    digest: String,
+    reader_proxy: ReaderProxy,
+    writer_proxy: WriterProxy,
+}
+
+///
+/// Moves of one job which wait until destination is finished, since copies in destinations
+/// of other backends, like archive members, can not be read back before that.
+/// They are completed by `complete_pending_moves`.
+///
+#[derive(Default)]
+pub struct PendingMoves{
+    files: Vec<PendingMove>,
+    /// Moved directories, which are removed after all files
+    directories: Vec<(Vec<Location>, ReaderProxy)>,
+}
+
+/// Whether source and destination are handled by the same backend
+#[inline]
+fn is_same_backend(reader_proxy: &ReaderProxy, writer_proxy: &WriterProxy) -> bool{
+    reader_proxy.key() == writer_proxy.key()
+}
+
+///
+/// Checks whether source may be moved by renaming it: data must not be changed on the way and
+/// destination must belong to the same backend as source, otherwise e.g. directory renamed to
+/// `out.tar` would not become tarball
+///
+#[inline]
+fn can_rename(reader_proxy: &ReaderProxy, writer_proxy: &WriterProxy, args: &Args) -> bool{
+    args.move_sources && !transform::is_active(args) && is_same_backend(reader_proxy, writer_proxy)
+}
+
+///
+/// Finishes moving of file after it was copied: verifies copy by reading it back,
+/// preserves metadata and only then removes source. Destinations of other backends
+/// are verified after they are finished, see `complete_pending_moves`.
+///
+/// # Arguments
+///
+/// * `file`: copied file
+/// * `moves`: moves of job, which file joins if it has to wait
+/// * `args`: program-wide arguments
+///
+/// returns: PcpResult<()>: error if source was kept
+///
+fn finish_move(file: PendingMove, moves: &mut PendingMoves, args: &Args) -> PcpResult<()>{
+    if !is_same_backend(&file.reader_proxy, &file.writer_proxy){
+        moves.files.push(file);
+        return Ok(());
+    }
+    verify_move(&file, args)
+}
+
+/// Removes source of moved file if its copy matches digest, see `finish_move`
+fn verify_move(file: &PendingMove, args: &Args) -> PcpResult<()>{
+    let destination = &file.destination;
+    // Writer which verifies data itself has already failed on close if it differs
+    if !file.writer_proxy.verifies_on_close(destination)?
+        && hash_url(destination, args).as_deref() != Some(file.digest.as_str()){
+        return Err(PcpError::other(&file.source, format!("Verification of {} failed, keeping source",
+                                                         destination)));
+    }
+    preserve_metadata(destination, file.source_stat.as_ref(), &file.writer_proxy)?;
+    file.reader_proxy.remove(&file.source).map_err(|error| PcpError::from_io(&file.source, error))
+}
+
+///
+/// Removes source directories after their files were moved. Deepest directories go last
+/// in iteration order, so they are removed first. Directories which still contain skipped
+/// files are kept.
+///
+fn remove_moved_directories(directories: &[Location], reader_proxy: &ReaderProxy, args: &Args,
+                            report: &mut CopyReport){
+    for directory in directories.iter().rev(){
+        if let Err(error) = reader_proxy.remove(directory){
+            if error.kind() != std::io::ErrorKind::DirectoryNotEmpty{
+                report_failure(report, args, directory, format!("Can not remove: {}", error));
+            }
+        }
+    }
+}
+
+///
+/// Completes moves which waited for destination to be finished
+///
+/// # Arguments
+///
+/// * `moves`: moves collected by copying of job
+/// * `completed`: whether destination was finished, otherwise sources are kept
+/// * `args`: program-wide arguments
+/// * `report`: report to record failed verifications into
+///
+pub fn complete_pending_moves(moves: PendingMoves, completed: bool, args: &Args, report: &mut CopyReport){
+    if !completed{
+        return;
+    }
+    for file in &moves.files{
+        if let Err(error) = verify_move(file, args){
+            report_error(report, args, error);
+        }
+    }
+    for (directories, reader_proxy) in moves.directories{
+        remove_moved_directories(&directories, &reader_proxy, args, report);
+    }
+}
+
+///
+/// Checks destination against overwrite policy and backs it up if needed
+///
+/// returns: PcpResult<bool>: whether destination should be written
+///
+fn prepare_destination(destination: &Location, source_stat: Option<&ObjectStat>,
+                       writer_proxy: &WriterProxy, policy: &OverwritePolicy,
+                       backup: Option<&BackupPolicy>,
+                       progress: &dyn ProgressDisplay) -> PcpResult<bool>{
+    // Archive which is not open yet is replaced as a whole when its first member is written
+    if let Some(container) = writer_proxy.container(destination).filter(|container| container != destination){
+        if !prepare_destination(&container, source_stat, writer_proxy, policy, backup, progress)?{
+            return Ok(false);
+        }
+    }
+    let existing = writer_proxy.stat(destination);
+    if !policy.should_write(destination, existing.as_ref(),
+                            source_stat.and_then(|stat| stat.modified), progress){
+        return Ok(false);
+    }
+    if let (Some(_), Some(backup)) = (existing, backup){
+        backup.make_backup(destination, writer_proxy)?;
+    }
+    Ok(true)
+}
+
+///
+/// Moves reader to offset given by `--skip`
+///
+/// returns: PcpResult<ByteRange>: part of source to copy
+///
+fn seek_source(reader: &mut dyn Reader, source: &Location, args: &Args) -> PcpResult<ByteRange>{
+    let start = args.skip.unwrap_or(0);
+    let mut range = ByteRange{start, discard: 0, count: args.count};
+    if start > 0{
+        match reader.seek(start){
+            Ok(()) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::Unsupported => range.discard = start,
+            Err(error) => return Err(PcpError::from_io(source, error)),
+        }
+    }
+    Ok(range)
+}
+
+/// What happened with single file
+enum FileOutcome{
+    /// File was left as is because of overwrite policy
+    Skipped,
+    /// File was copied, with amount of copied source bytes and SHA256 of its contents if it was asked for
+    Copied{bytes: usize, digest: Option<String>},
+}
+
+///
+/// Copies single file: checks overwrite policy, copies data or renames file when moving,
+/// and finishes move
+///
+/// # Arguments
+///
+/// * `source`: source file
+/// * `destination`: destination file
+/// * `source_stat`: metadata of source
+/// * `reader_proxy`: reader which owns source
+/// * `writer_proxy`: writer which owns destination
+/// * `policy`: overwrite policy
+/// * `backup`: backup policy if backups are enabled
+/// * `hash`: whether checksum of copied data is needed
+/// * `moves`: moves of job, which wait until destination is finished
+/// * `args`: program-wide arguments
+///
+/// returns: PcpResult<FileOutcome>
+///
+#[allow(clippy::too_many_arguments)]
+fn transfer_file(source: &Location, destination: &Location, source_stat: Option<&ObjectStat>,
+                 reader_proxy: &ReaderProxy, writer_proxy: &WriterProxy,
+                 policy: &OverwritePolicy, backup: Option<&BackupPolicy>, hash: bool,
+                 moves: &mut PendingMoves, args: &Args) -> PcpResult<FileOutcome>{
+    if args.move_sources{
+        // Refuse before anything is written, if copy could not be verified
+        writer_proxy.verifies_on_close(destination)?;
+    }
+    let mut progress = get_progress_from_args(args);
+    if !prepare_destination(destination, source_stat, writer_proxy, policy, backup, &*progress)?{
+        return Ok(FileOutcome::Skipped);
+    }
+    if can_rename(reader_proxy, writer_proxy, args) && reader_proxy.rename(source, destination).is_ok(){
+        let bytes = source_stat.map(|stat| stat.size).unwrap_or(0);
+        if !hash{
+            return Ok(FileOutcome::Copied{bytes, digest: None});
+        }
+        return hash_url(destination, args)
+            .map(|digest| FileOutcome::Copied{bytes, digest: Some(digest)})
+            .ok_or_else(|| PcpError::other(destination, "Can not read moved file back"));
+    }
+    let mut transforms = transform::from_args(source, args)?;
+    // Hash is taken from data as it is written, so it goes after everything else
+    let digest = (hash || args.move_sources).then(|| {
+        let (hasher, digest) = HashTransform::new();
+        transforms.push(Box::new(hasher));
+        digest
+    });
+    let mut reader = reader_proxy.produce(source)?;
+    let range = seek_source(&mut *reader, source, args)?;
+    let mut writer = match args.seek{
+        Some(offset) => writer_proxy.produce_at(destination, offset)?,
+        None => match writer_proxy.produce(destination){
+            // Like `cp -f`, destination which can not be opened is removed and created again
+            Err(PcpError::PermissionDenied(_)) if args.force && writer_proxy.remove(destination).is_ok() =>
+                writer_proxy.produce(destination)?,
+            writer => writer?,
+        },
+    };
+    if let Some(stat) = source_stat{
+        writer.set_source_metadata(stat)?;
+    }
+    let buffer_size = reader.get_blocksize();
+    let mut status = source.to_os_string();
+    status.push(" -> ");
+    status.push(destination.to_os_string());
+    progress.set_progress(&status, 0);
+    let bytes = tokio_block_on(do_copy(reader, writer, destination, progress, transforms, range, args.max_chunks_number as usize,
+                           buffer_size))?;
+    let digest = digest.and_then(|digest| digest.lock().unwrap().take());
+    if args.move_sources{
+        let file = PendingMove{
+            source: source.clone(),
+            destination: destination.clone(),
+            source_stat: source_stat.cloned(),
+            digest: digest.clone().unwrap(),
+            reader_proxy: reader_proxy.clone(),
+            writer_proxy: writer_proxy.clone(),
+        };
+        finish_move(file, moves, args)?;
+    }
+    if args.sync{
+        preserve_metadata(destination, source_stat, writer_proxy)?;
+    }
+    Ok(FileOutcome::Copied{bytes, digest: digest.filter(|_| hash)})
+}
+
+///
+/// Records result of copying single file in report and manifest
+///
+/// returns: bool: whether file was copied or skipped without errors
+///
+fn record_outcome(outcome: PcpResult<FileOutcome>, manifest_path: &Path, manifest: Option<&mut Manifest>,
+                  report: &mut CopyReport, args: &Args) -> bool{
+    match outcome{
+        Ok(FileOutcome::Skipped) => {
+            report.skipped += 1;
+            true
+        }
+        Ok(FileOutcome::Copied{bytes, digest}) => {
+            if let (Some(manifest), Some(digest)) = (manifest, digest){
+                manifest.add_entry(manifest_path, digest);
+            }
+            report.add_copied(bytes);
+            true
+        }
+        Err(error) => report_error(report, args, error),
+    }
+}
+
+///
+/// Gets reader of source and writer of target, respecting backends forced by user
+///
+/// returns: PcpResult<(ReaderProxy, WriterProxy)>: Unsupported error if either URL has no backend
+///
+fn get_proxies(source: &Location, target: &Location, args: &Args) -> PcpResult<(ReaderProxy, WriterProxy)>{
+    let reader_proxy = get_reader_proxy_by_backend(source, args.src_backend.as_deref())
+        .ok_or_else(|| PcpError::Unsupported(source.to_string(), String::from("No reader for URL")))?;
+    let writer_proxy = get_writer_proxy_by_backend(target, args.dst_backend.as_deref())
+        .ok_or_else(|| PcpError::Unsupported(target.to_string(), String::from("No writer for URL")))?;
+    Ok((reader_proxy, writer_proxy))
 }
 
 /// 
@@ -114,74 +603,422 @@ async fn do_copy(mut reader: Box<dyn Reader>, mut writer: Box<dyn Writer>,
 /// * `source`: source file
 /// * `target`: target file
 /// * `args`: arguments
+/// * `report`: report to record copied, skipped or failed file into
+/// * `manifest`: manifest to record checksum of copied file into
+/// * `moves`: moves of job, which wait until destination is finished
 /// 
 /// returns: bool: whether copy was successful
 ///
-pub fn copy_file(source: &str, target: &str, args: &Args) -> bool{
-    let writer_proxy = get_writer_proxy_for_url(target).unwrap();
-    let reader_proxy = get_reader_proxy_for_url(source).unwrap();
-    let mut progress = get_progress_from_args(args);
-    let mut str_target = target.to_string();
+pub fn copy_file(source: &Location, target: &Location, args: &Args, report: &mut CopyReport,
+                 manifest: Option<&mut Manifest>, moves: &mut PendingMoves) -> bool{
+    let (reader_proxy, writer_proxy) = match get_proxies(source, target, args){
+        Ok(proxies) => proxies,
+        Err(error) => return report_error(report, args, error),
+    };
+    let (destination, manifest_path) = file_target(source, target, &reader_proxy, &writer_proxy, args);
+    let plan = Plan::for_file(source, &destination, &reader_proxy, &writer_proxy, args);
+    let entry = &plan.entries[0];
+    if entry.action == PlanAction::Skip{
+        report.skipped += 1;
+        return true;
+    }
+    let outcome = transfer_file(source, &destination, entry.source_stat.as_ref(), &reader_proxy,
+                                &writer_proxy, &OverwritePolicy::from_args(args),
+                                BackupPolicy::from_args(args).as_ref(), manifest.is_some(), moves, args);
+    record_outcome(outcome, &manifest_path, manifest, report, args)
+}
+
+///
+/// Resolves directory which receives contents of source directory
+///
+/// # Arguments
+///
+/// * `source`: source directory
+/// * `target`: target given by user
+/// * `reader_proxy`: reader of source
+/// * `writer_proxy`: writer of target
+/// * `args`: program-wide arguments
+///
+/// returns: (Location, bool): URL of directory and whether it is target itself
+///
+fn directory_target(source: &Location, target: &Location, reader_proxy: &ReaderProxy,
+                    writer_proxy: &WriterProxy, args: &Args) -> (Location, bool){
+    // Repeated syncs must land in the same place, so placement does not depend on whether
+    // target exists: like in rsync, trailing slash means copying contents of source
+    let contents_only = if args.sync{
+        source.has_trailing_separator()
+    } else {
+        !writer_proxy.is_directory(target)
+    };
+    if contents_only{
+        (target.clone(), true)
+    } else {
+        (writer_proxy.join_path(target, Path::new(&reader_proxy.dirname(source))), false)
+    }
+}
+
+///
+/// Resolves destination file for source file. File put into directory gets name of source,
+/// changed by transforms like compression.
+///
+/// returns: (Location, PathBuf): URL of destination and its path for manifest
+///
+fn file_target(source: &Location, target: &Location, reader_proxy: &ReaderProxy,
+               writer_proxy: &WriterProxy, args: &Args) -> (Location, PathBuf){
     if writer_proxy.is_directory(target){
-        let filename = reader_proxy.filename(source);
-        str_target = writer_proxy.join_path(target, &filename);
+        let filename = PathBuf::from(transform::destination_name(&reader_proxy.filename(source), args));
+        (writer_proxy.join_path(target, &filename), filename)
+    } else {
+        // Relative to directory of target, see `manifest_base`
+        (target.clone(), PathBuf::from(target.file_name().unwrap_or_default()))
     }
-    let reader = reader_proxy.produce(source);
-    let writer = writer_proxy.produce(&str_target);
-    progress.set_progress(&*format!("{} -> {}", source, target), 0);
-    let buffer_size = reader.get_blocksize();
-    tokio_block_on(do_copy(reader, writer, progress,
-                           args.max_chunks_number as usize, buffer_size))
+}
+
+///
+/// Plans copy of source without touching destination
+///
+/// # Arguments
+///
+/// * `source`: source file or directory
+/// * `target`: target given by user
+/// * `args`: program-wide arguments
+///
+/// returns: Result<Plan, String>: plan or description of bad filter
+///
+pub fn plan_copy(source: &Location, target: &Location, args: &Args) -> Result<Plan, String>{
+    let (reader_proxy, writer_proxy) = get_proxies(source, target, args).map_err(|error| error.to_string())?;
+    if !reader_proxy.is_directory(source){
+        let (destination, _) = file_target(source, target, &reader_proxy, &writer_proxy, args);
+        return Ok(Plan::for_file(source, &destination, &reader_proxy, &writer_proxy, args));
+    }
+    let (target_path, _) = directory_target(source, target, &reader_proxy, &writer_proxy, args);
+    let filter = PathFilter::from_args(args)?;
+    Ok(Plan::for_directory(source, &target_path, &reader_proxy, &writer_proxy, filter.as_ref(),
+                           args))
+}
 
+///
+/// Records files of directory which was moved by renaming it as a whole. Directory is renamed
+/// only within the same backend, so it is listed with reader of source.
+///
+fn record_renamed_directory(directory: &Location, reader_proxy: &ReaderProxy, report: &mut CopyReport){
+    for object in reader_proxy.iter_directory(directory){
+        match reader_proxy.stat(&object){
+            Some(stat) if !stat.is_directory => report.add_copied(stat.size),
+            _ => {}
+        }
+    }
 }
 
 /// 
-/// Copies directory
+/// Copies directory. Objects which fail are reported and skipped, unless `--fail-fast` is given.
 /// 
 /// # Arguments 
 /// 
 /// * `source`: source directory
 /// * `target`: target directory
 /// * `args`: program-wide arguments
+/// * `report`: report to record copied, skipped and failed files into
+/// * `manifest`: manifest to record checksums of copied files into
+/// * `moves`: moves of job, which wait until destination is finished
 /// 
-/// returns: bool: whether copy was successful
+/// returns: bool: whether every object was copied successfully
 ///
-pub fn copy_directory(source: &str, target: &str, args: &Args) -> bool{
-    let mut target_path = target.to_string();
-    let writer_proxy = get_writer_proxy_for_url(target).unwrap();
-    let reader_proxy = get_reader_proxy_for_url(source).unwrap();
-    let is_new_dir = if writer_proxy.is_directory(target){
-        target_path = writer_proxy.join_path(&target_path, &reader_proxy.dirname(source));
-        writer_proxy.make_directory(&target_path);
-        false
-    } else {
-        writer_proxy.make_directory(target);
-        true
+pub fn copy_directory(source: &Location, target: &Location, args: &Args, report: &mut CopyReport,
+                      mut manifest: Option<&mut Manifest>, moves: &mut PendingMoves) -> bool{
+    let (reader_proxy, writer_proxy) = match get_proxies(source, target, args){
+        Ok(proxies) => proxies,
+        Err(error) => return report_error(report, args, error),
     };
-    for object in reader_proxy.iter_directory(source){
-        //println!("{}", object);
-        let target_object = if is_new_dir{
-            reader_proxy.relative_path(&reader_proxy.dirname(&source), &object)
-        } else {
-            reader_proxy.relative_path(&source, &object)
-        };
-        let reader = reader_proxy.produce(&object);
-        let destination = writer_proxy.join_path(&target_path, &target_object);
-        //println!("target_path={}, object={}, dest={}", target_path, target_object, destination);
-        let mut progress = get_progress_from_args(args);
-        let buffer_size = reader.get_blocksize();
-        if reader_proxy.is_directory(&object){
-            writer_proxy.make_directory(&destination);
-            continue;
-        }
-        let writer = writer_proxy.produce(&destination);
-        progress.set_progress(&*format!("{} -> {}", object, destination), 0);
-        let coroutine = async move {
-            do_copy(reader, writer, progress, args.max_chunks_number as usize, buffer_size).await
-        };
-        if !tokio_block_on(coroutine){
+    let (target_path, contents_only) = directory_target(source, target, &reader_proxy,
+                                                        &writer_proxy, args);
+    let filter = match PathFilter::from_args(args){
+        Ok(filter) => filter,
+        Err(error) => return report_failure(report, args, source, format!("Bad filter: {}", error)),
+    };
+    // Manifest needs every file to be hashed and filters may leave some of them behind,
+    // so whole directory is renamed only without them, as well as without transforms of data
+    if can_rename(&reader_proxy, &writer_proxy, args) && manifest.is_none() && filter.is_none()
+        && writer_proxy.stat(&target_path).is_none()
+        && reader_proxy.rename(source, &target_path).is_ok(){
+        record_renamed_directory(&target_path, &reader_proxy, report);
+        return true;
+    }
+    let mut plan = Plan::for_directory(source, &target_path, &reader_proxy, &writer_proxy,
+                                       filter.as_ref(), args);
+    if args.sync{
+        plan.notify(args);
+    }
+    let mut succeeded = true;
+    for error in plan.errors.drain(..){
+        succeeded = report_error(report, args, error);
+        if args.fail_fast{
             return false;
         }
     }
-    true
-}
\ No newline at end of file
+    let policy = OverwritePolicy::from_args(args);
+    let backup = BackupPolicy::from_args(args);
+    // Directories are written into archive too, so it has to be checked before any of them
+    if let Some(container) = writer_proxy.container(&target_path){
+        let root_stat = plan.entries.iter().find(|entry| entry.relative.as_os_str().is_empty())
+            .and_then(|entry| entry.source_stat.as_ref());
+        let progress = get_progress_from_args(args);
+        match prepare_destination(&container, root_stat, &writer_proxy, &policy, backup.as_ref(), &*progress)
+            .and_then(|write| write.then(|| match root_stat{
+                Some(stat) => writer_proxy.make_directory_like(&target_path, stat),
+                None => writer_proxy.make_directory(&target_path),
+            }).transpose()){
+            Ok(Some(())) => {}
+            Ok(None) => {
+                report.skipped += plan.entries.iter().filter(|entry| !entry.is_directory).count();
+                return succeeded;
+            }
+            Err(error) => return report_error(report, args, error),
+        }
+    }
+    let mut moved_directories = Vec::new();
+    for entry in plan.entries{
+        let destination = &entry.destination;
+        let result = match (entry.action, &entry.source){
+            (PlanAction::Delete, _) | (_, None) => writer_proxy.remove(destination)
+                .map_err(|error| PcpError::from_io(destination, error))
+                .map_or_else(|error| report_error(report, args, error), |_| true),
+            (PlanAction::MakeDirectory, Some(_)) if entry.is_directory => {
+                let created = match &entry.source_stat{
+                    Some(stat) => writer_proxy.make_directory_like(destination, stat),
+                    None => writer_proxy.make_directory(destination),
+                };
+                match created{
+                    Ok(()) => true,
+                    // Nothing can be copied without directory which receives everything
+                    Err(error) if entry.relative.as_os_str().is_empty() => return report_error(report, args, error),
+                    Err(error) => report_error(report, args, error),
+                }
+            }
+            (_, Some(_)) if entry.is_directory => true,
+            (PlanAction::Skip, Some(_)) => {
+                report.skipped += 1;
+                true
+            }
+            (_, Some(object)) => {
+                let manifest_path = if contents_only{
+                    entry.relative.clone()
+                } else {
+                    Path::new(&reader_proxy.dirname(source)).join(&entry.relative)
+                };
+                let outcome = transfer_file(object, destination, entry.source_stat.as_ref(),
+                                            &reader_proxy, &writer_proxy, &policy,
+                                            backup.as_ref(), manifest.is_some(), moves, args);
+                record_outcome(outcome, &manifest_path, manifest.as_deref_mut(), report, args)
+            }
+        };
+        if !result{
+            succeeded = false;
+            if args.fail_fast{
+                return false;
+            }
+        }
+        if let (true, true, Some(object)) = (args.move_sources, entry.is_directory, entry.source){
+            moved_directories.push(object);
+        }
+    }
+    if args.move_sources{
+        if is_same_backend(&reader_proxy, &writer_proxy){
+            remove_moved_directories(&moved_directories, &reader_proxy, args, report);
+        } else {
+            moves.directories.push((moved_directories, reader_proxy));
+        }
+    }
+    succeeded
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::reader::file::FileReader;
+    use crate::utils::runtime::init_tokio;
+    use crate::writer::file::FileWriter;
+
+    /// Holds data back until stream is finished, like block compressors do
+    struct Reverse(DynBuffer);
+
+    #[async_trait]
+    impl Transform for Reverse {
+        async fn process(&mut self, chunk: DynBuffer) -> PcpResult<Vec<DynBuffer>> {
+            self.0.extend(chunk);
+            Ok(Vec::new())
+        }
+
+        async fn finish(&mut self) -> PcpResult<Vec<DynBuffer>> {
+            self.0.reverse();
+            Ok(vec![std::mem::take(&mut self.0)])
+        }
+    }
+
+    #[test]
+    fn test_transform_chain() {
+        let directory = std::env::temp_dir().join(format!("pcp-copy-chain-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let source = Location::from_path(directory.join("source"));
+        let destination = Location::from_path(directory.join("destination"));
+        std::fs::write(directory.join("source"), b"hello world").unwrap();
+
+        init_tokio();
+        let (hasher, digest) = HashTransform::new();
+        let transforms: Vec<Box<dyn Transform>> = vec![Box::new(Reverse(DynBuffer::new())), Box::new(hasher)];
+        tokio_block_on(do_copy(Box::new(FileReader::new(&source).unwrap()),
+                               Box::new(FileWriter::new(&destination).unwrap()), &destination,
+                               Box::new(DummyProgress::new()), transforms, ByteRange::default(), 1, 4)).unwrap();
+        assert_eq!(std::fs::read(directory.join("destination")).unwrap(), b"dlrow olleh");
+        assert_eq!(digest.lock().unwrap().as_deref(),
+                   Some("bd3f9adee5aca3147154910834a7c7e176692eab2778ece115563df18de2233d"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_large_transform_chunk() {
+        let directory = std::env::temp_dir().join(format!("pcp-copy-large-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let source = Location::from_path(directory.join("source"));
+        let destination = Location::from_path(directory.join("destination"));
+        // Transform emits it as one chunk, which is more than file writes at once
+        let content: Vec<u8> = (0..3 * 1024 * 1024).map(|index| (index % 251) as u8).collect();
+        std::fs::write(directory.join("source"), &content).unwrap();
+
+        init_tokio();
+        let transforms: Vec<Box<dyn Transform>> = vec![Box::new(Reverse(DynBuffer::new()))];
+        tokio_block_on(do_copy(Box::new(FileReader::new(&source).unwrap()),
+                               Box::new(FileWriter::new(&destination).unwrap()), &destination,
+                               Box::new(DummyProgress::new()), transforms, ByteRange::default(), 1, 65536)).unwrap();
+        let written = std::fs::read(directory.join("destination")).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(written.len(), content.len());
+        assert!(written.iter().eq(content.iter().rev()));
+    }
+
+    #[test]
+    fn test_unknown_backend() {
+        let source = std::env::temp_dir().join(format!("pcp-copy-backend-{}", std::process::id()));
+        std::fs::write(&source, b"hello").unwrap();
+        let (source, target) = (Location::from_path(&source), Location::from_path(std::env::temp_dir()));
+        let args = Args { dst_backend: Some(String::from("nonexistent")), no_progress: true, ..Args::default() };
+        let mut report = CopyReport::default();
+        let copied = copy_file(&source, &target, &args, &mut report, None, &mut PendingMoves::default());
+        let planned = plan_copy(&source, &target, &args);
+        std::fs::remove_file(source.path()).unwrap();
+        assert!(!copied);
+        assert!(report.failed[0].reason.ends_with("No writer for URL"));
+        assert!(planned.is_err());
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let directory = std::env::temp_dir().join(format!("pcp-copy-range-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let source = Location::from_path(directory.join("source"));
+        let destination = Location::from_path(directory.join("destination"));
+        std::fs::write(directory.join("source"), b"hello world").unwrap();
+        std::fs::write(directory.join("destination"), b"0123456789ABCDEF").unwrap();
+
+        init_tokio();
+        // Skipped bytes are read and dropped, like with readers which can not seek
+        let range = ByteRange{start: 2, discard: 2, count: Some(5)};
+        let copied = tokio_block_on(do_copy(Box::new(FileReader::new(&source).unwrap()),
+                               Box::new(FileWriter::open_at(&destination, 3).unwrap()), &destination,
+                               Box::new(DummyProgress::new()), Vec::new(), range, 1, 4)).unwrap();
+        assert_eq!(copied, 5);
+        assert_eq!(std::fs::read(directory.join("destination")).unwrap(), b"012llo w89ABCDEF");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_byte_range_report() {
+        let directory = std::env::temp_dir().join(format!("pcp-copy-range-report-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("source"), b"hello world").unwrap();
+        let report = crate::CopyJob::new(directory.join("destination"))
+            .source(directory.join("source"))
+            .options(Args { count: Some(5), no_progress: true, ..Args::default() })
+            .run();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(report.is_success());
+        // Summary counts bytes which were copied, not size of source
+        assert_eq!(report.bytes_copied, 5);
+    }
+
+    #[test]
+    fn test_sync_keeps_files_after_listing_error() {
+        use std::os::unix::fs::PermissionsExt;
+        let directory = std::env::temp_dir().join(format!("pcp-copy-sync-error-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("source/sub")).unwrap();
+        std::fs::write(directory.join("source/sub/file"), b"hello").unwrap();
+        std::fs::create_dir_all(directory.join("destination/sub")).unwrap();
+        std::fs::write(directory.join("destination/sub/file"), b"hello").unwrap();
+        std::fs::write(directory.join("destination/extra"), b"extra").unwrap();
+        std::fs::set_permissions(directory.join("source/sub"), std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Superuser reads directory anyway, so there is no listing error to check
+        let listed = std::fs::read_dir(directory.join("source/sub")).is_ok();
+        let report = (!listed).then(|| crate::CopyJob::new(directory.join("destination"))
+            .source(format!("{}/", directory.join("source").display()))
+            .options(Args { recursive: true, no_progress: true, sync: true, delete: true, ..Args::default() })
+            .run());
+        std::fs::set_permissions(directory.join("source/sub"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        if let Some(report) = report {
+            assert!(!report.is_success());
+            assert!(directory.join("destination/sub/file").exists());
+            assert!(directory.join("destination/extra").exists());
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_move_into_tarball() {
+        let directory = std::env::temp_dir().join(format!("pcp-copy-move-tar-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("source/inner")).unwrap();
+        std::fs::write(directory.join("source/inner/file"), b"hello").unwrap();
+        let report = crate::CopyJob::new(directory.join("out.tar"))
+            .source(directory.join("source"))
+            .options(Args { recursive: true, no_progress: true, move_sources: true, ..Args::default() })
+            .run();
+        assert!(report.is_success());
+        assert!(!directory.join("source").exists());
+        let archive = std::fs::File::open(directory.join("out.tar")).unwrap();
+        let members: Vec<PathBuf> = tar::Archive::new(archive).entries().unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert_eq!(members, [PathBuf::from("inner/"), PathBuf::from("inner/file")]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_moves_are_kept_by_job() {
+        crate::register_backends();
+        init_tokio();
+        let directory = std::env::temp_dir().join(format!("pcp-copy-move-jobs-{}", std::process::id()));
+        for name in ["first", "second"] {
+            std::fs::create_dir_all(directory.join(name)).unwrap();
+            std::fs::write(directory.join(name).join("file"), name).unwrap();
+        }
+        let args = Args { recursive: true, no_progress: true, move_sources: true, ..Args::default() };
+        let source = Location::from_path(directory.join("first"));
+        let target = Location::from_path(directory.join("first.tar"));
+        let mut report = CopyReport::default();
+        let mut moves = PendingMoves::default();
+        assert!(copy_directory(&source, &target, &args, &mut report, None, &mut moves));
+        // Job which runs meanwhile completes only its own moves
+        let second = crate::CopyJob::new(directory.join("second.tar"))
+            .source(directory.join("second"))
+            .options(args.clone())
+            .run();
+        assert!(second.is_success() && !directory.join("second").exists());
+        assert!(directory.join("first/file").exists());
+        get_writer_proxy_by_backend(&target, None).unwrap().finish(&target).unwrap();
+        complete_pending_moves(moves, true, &args, &mut report);
+        assert!(report.is_success() && !directory.join("first").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+///
+/// Error of reader or writer, tied to URL of object it happened with
+///
+#[derive(Debug)]
+pub enum PcpError {
+    /// Object does not exist
+    NotFound(String),
+    /// Access to object is denied
+    PermissionDenied(String),
+    /// Any other input/output error
+    Io(String, std::io::Error),
+    /// Remote side or archive format misbehaved
+    Protocol(String, String),
+    /// Operation is not supported by backend
+    Unsupported(String, String),
+}
+
+/// Result of reader or writer operation
+pub type PcpResult<T> = Result<T, PcpError>;
+
+type ErrorFn = dyn Fn(&str, &str) + Send + Sync;
+
+///
+/// Function which receives URL of object and description of error as soon as it happens,
+/// as well as warnings like directories skipped without recursive flag
+///
+#[derive(Clone)]
+pub struct ErrorCallback(pub Arc<ErrorFn>);
+
+impl Debug for ErrorCallback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ErrorCallback")
+    }
+}
+
+impl PcpError {
+    ///
+    /// Classifies input/output error
+    ///
+    /// # Arguments
+    ///
+    /// * `url`: URL of object which caused error
+    /// * `error`: error itself
+    ///
+    /// returns: PcpError
+    ///
+    pub fn from_io(url: impl Display, error: std::io::Error) -> PcpError {
+        match error.kind() {
+            ErrorKind::NotFound => PcpError::NotFound(url.to_string()),
+            ErrorKind::PermissionDenied => PcpError::PermissionDenied(url.to_string()),
+            ErrorKind::Unsupported => PcpError::Unsupported(url.to_string(), error.to_string()),
+            _ => PcpError::Io(url.to_string(), error),
+        }
+    }
+
+    ///
+    /// Creates error which has no better classification than its description
+    ///
+    /// # Arguments
+    ///
+    /// * `url`: URL of object which caused error
+    /// * `message`: description of error
+    ///
+    /// returns: PcpError
+    ///
+    pub fn other(url: impl Display, message: impl Into<String>) -> PcpError {
+        PcpError::Io(url.to_string(), std::io::Error::other(message.into()))
+    }
+
+    /// Gets URL of object which caused error
+    pub fn url(&self) -> &str {
+        match self {
+            PcpError::NotFound(url)
+            | PcpError::PermissionDenied(url)
+            | PcpError::Io(url, _)
+            | PcpError::Protocol(url, _)
+            | PcpError::Unsupported(url, _) => url,
+        }
+    }
+
+    /// Describes error without URL
+    pub fn reason(&self) -> String {
+        match self {
+            PcpError::NotFound(_) => String::from("No such file or directory"),
+            PcpError::PermissionDenied(_) => String::from("Permission denied"),
+            PcpError::Io(_, error) => error.to_string(),
+            PcpError::Protocol(_, message) => format!("Protocol error: {}", message),
+            PcpError::Unsupported(_, message) => format!("Not supported: {}", message),
+        }
+    }
+}
+
+impl Display for PcpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.url(), self.reason())
+    }
+}
+
+impl std::error::Error for PcpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PcpError::Io(_, error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_io() {
+        let error = PcpError::from_io("/tmp/x", std::io::Error::from(ErrorKind::NotFound));
+        assert!(matches!(error, PcpError::NotFound(_)));
+        assert_eq!(error.to_string(), "/tmp/x: No such file or directory");
+        let error = PcpError::from_io("/tmp/y", std::io::Error::from(ErrorKind::PermissionDenied));
+        assert!(matches!(error, PcpError::PermissionDenied(_)));
+        let error = PcpError::from_io("/tmp/z", std::io::Error::other("disk on fire"));
+        assert_eq!(error.reason(), "disk on fire");
+    }
+}
@@ -1,31 +1,47 @@
-use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use lazy_static::lazy_static;
 
+use crate::error::PcpResult;
+use crate::location::Location;
+use crate::progress::ProgressDisplay;
 use crate::reader::Reader;
 use crate::utils::generic_iterator::GenericIterator;
+use crate::utils::stat::ObjectStat;
 use crate::writer::Writer;
 
+type ReaderConstructor = fn(&Location) -> PcpResult<Box<dyn Reader>>;
+type DirectoryIteratorConstructor = fn(&Location) -> Box<dyn GenericIterator<Location>>;
+type WriterConstructor = fn(&Location) -> PcpResult<Box<dyn Writer>>;
+type OffsetWriterConstructor = fn(&Location, usize) -> PcpResult<Box<dyn Writer>>;
+type DirectoryListing = fn(&Location) -> std::io::Result<Vec<OsString>>;
+pub(crate) type ProgressConstructor = fn() -> Box<dyn ProgressDisplay>;
+
 ///
 /// Allows access to static methods of Reader trait
 ///
 #[derive(Clone)]
 pub struct ReaderProxy{
-    constructor: Box<fn(&str)-> Box<dyn Reader>>,
-    can_read_fn: Box<fn(&str) -> bool>,
-    is_directory_fn: Box<fn(&str) -> bool>,
-    iter_directory_fn: Box<fn(&str) -> Box<dyn GenericIterator<String>>>,
-    relative_path_fn: Box<fn(&str, &str) -> String>,
-    dirname_fn: Box<fn(&str) -> String>,
-    filename_fn: Box<fn(&str) -> String>,
-    
+    key: String,
+    constructor: Box<ReaderConstructor>,
+    can_read_fn: Box<fn(&Location) -> bool>,
+    is_directory_fn: Box<fn(&Location) -> bool>,
+    iter_directory_fn: Box<DirectoryIteratorConstructor>,
+    relative_path_fn: Box<fn(&Location, &Location) -> PathBuf>,
+    dirname_fn: Box<fn(&Location) -> OsString>,
+    filename_fn: Box<fn(&Location) -> OsString>,
+    stat_fn: Box<fn(&Location) -> Option<ObjectStat>>,
+    rename_fn: Box<fn(&Location, &Location) -> std::io::Result<()>>,
+    remove_fn: Box<fn(&Location) -> std::io::Result<()>>,
 }
 
 ///
 /// Produces instances of Reader trait object via ReaderProxy
 ///
+#[derive(Default)]
 pub struct ReaderFactory{
-    components: HashMap<String, ReaderProxy>
+    components: Vec<Registration<ReaderProxy>>,
 }
 
 ///
@@ -33,172 +49,447 @@ pub struct ReaderFactory{
 ///
 #[derive(Clone)]
 pub struct WriterProxy{
-    constructor: Box<fn(&str)-> Box<dyn Writer>>,
-    can_write_fn: Box<fn(&str) -> bool>,
-    is_directory_fn: Box<fn(&str) -> bool>,
-    make_directory_fn: Box<fn(&str)>,
-    join_path_fn: Box<fn(&str, &str) -> String>,
+    key: String,
+    constructor: Box<WriterConstructor>,
+    offset_constructor: Box<OffsetWriterConstructor>,
+    can_write_fn: Box<fn(&Location) -> bool>,
+    is_directory_fn: Box<fn(&Location) -> bool>,
+    make_directory_fn: Box<fn(&Location) -> PcpResult<()>>,
+    make_directory_like_fn: Box<fn(&Location, &ObjectStat) -> PcpResult<()>>,
+    stat_fn: Box<fn(&Location) -> Option<ObjectStat>>,
+    rename_fn: Box<fn(&Location, &Location) -> std::io::Result<()>>,
+    list_directory_fn: Box<DirectoryListing>,
+    verifies_on_close_fn: Box<fn(&Location) -> PcpResult<bool>>,
+    container_fn: Box<fn(&Location) -> Option<Location>>,
+    remove_fn: Box<fn(&Location) -> std::io::Result<()>>,
+    set_metadata_fn: Box<fn(&Location, &ObjectStat) -> std::io::Result<()>>,
+    join_path_fn: Box<fn(&Location, &Path) -> Location>,
+    finish_fn: Box<fn(&Location) -> PcpResult<()>>,
 }
 
 impl ReaderProxy {
     pub fn from_type<T: Reader + 'static>() -> ReaderProxy{
         ReaderProxy{
-            constructor: Box::new(|url| { Box::new(T::new(url)) }),
+            key: String::new(),
+            constructor: Box::new(|url| { Ok(Box::new(T::new(url)?)) }),
             can_read_fn: Box::new(T::can_read),
             is_directory_fn: Box::new(T::is_directory),
             iter_directory_fn: Box::new(T::iter_directory),
             relative_path_fn: Box::new(T::relative_path),
             dirname_fn: Box::new(T::dirname),
             filename_fn: Box::new(T::filename),
+            stat_fn: Box::new(T::stat),
+            rename_fn: Box::new(T::rename),
+            remove_fn: Box::new(T::remove),
         }
     }
 
+    /// Gets key under which backend is registered, empty until proxy is added to factory
     #[inline]
-    pub fn produce(&self, url: &str) -> Box<dyn Reader>{
+    pub fn key(&self) -> &str{
+        &self.key
+    }
+
+    #[inline]
+    pub fn produce(&self, url: &Location) -> PcpResult<Box<dyn Reader>>{
         let fun = *self.constructor;
         fun(url)
     }
     
     #[inline]
-    pub fn can_read(&self, url: &str) -> bool{
+    pub fn can_read(&self, url: &Location) -> bool{
         let fun = *self.can_read_fn;
         fun(url)
     }
     
     #[inline]
-    pub fn relative_path(&self, base: &str, path: &str) -> String{
+    pub fn relative_path(&self, base: &Location, path: &Location) -> PathBuf{
         let fun = *self.relative_path_fn;
         fun(base, path)
     }
     
     #[inline]
     #[allow(dead_code)]
-    pub fn dirname(&self, url: &str) -> String{
+    pub fn dirname(&self, url: &Location) -> OsString{
         let fun = *self.dirname_fn;
         fun(url)
     }
 
     #[inline]
-    pub fn filename(&self, url: &str) -> String{
+    pub fn filename(&self, url: &Location) -> OsString{
         let fun = *self.filename_fn;
         fun(url)
     }
     
     #[inline]
-    pub fn iter_directory(&self, url: &str) -> Box<dyn GenericIterator<String>>{
+    pub fn iter_directory(&self, url: &Location) -> Box<dyn GenericIterator<Location>>{
         let fun = *self.iter_directory_fn;
         fun(url)
     }
 
     #[inline]
-    pub fn is_directory(&self, url: &str) -> bool{
+    pub fn is_directory(&self, url: &Location) -> bool{
         let fun = *self.is_directory_fn;
         fun(url)
     }
+
+    #[inline]
+    pub fn stat(&self, url: &Location) -> Option<ObjectStat>{
+        let fun = *self.stat_fn;
+        fun(url)
+    }
+
+    #[inline]
+    pub fn rename(&self, from: &Location, to: &Location) -> std::io::Result<()>{
+        let fun = *self.rename_fn;
+        fun(from, to)
+    }
+
+    #[inline]
+    pub fn remove(&self, url: &Location) -> std::io::Result<()>{
+        let fun = *self.remove_fn;
+        fun(url)
+    }
 }
 
 impl WriterProxy{
     pub fn from_type<T: Writer + 'static>() -> WriterProxy {
         WriterProxy{
-            constructor: Box::new(|url|{ Box::new(T::new(url)) }),
+            key: String::new(),
+            constructor: Box::new(|url|{ Ok(Box::new(T::new(url)?)) }),
+            offset_constructor: Box::new(|url, offset|{ Ok(Box::new(T::open_at(url, offset)?)) }),
             can_write_fn: Box::new(T::can_write),
             is_directory_fn: Box::new(T::is_directory),
             make_directory_fn: Box::new(T::make_directory),
+            make_directory_like_fn: Box::new(T::make_directory_like),
+            stat_fn: Box::new(T::stat),
+            rename_fn: Box::new(T::rename),
+            list_directory_fn: Box::new(T::list_directory),
+            verifies_on_close_fn: Box::new(T::verifies_on_close),
+            container_fn: Box::new(T::container),
+            remove_fn: Box::new(T::remove),
+            set_metadata_fn: Box::new(T::set_metadata),
             join_path_fn: Box::new(T::join_path),
+            finish_fn: Box::new(T::finish),
         }
     }
     
+    /// Gets key under which backend is registered, empty until proxy is added to factory
     #[inline]
-    pub fn produce(&self, url: &str) -> Box<dyn Writer>{
+    pub fn key(&self) -> &str{
+        &self.key
+    }
+
+    #[inline]
+    pub fn produce(&self, url: &Location) -> PcpResult<Box<dyn Writer>>{
         let fun = *self.constructor;
         fun(url)
     }
+
+    /// Produces writer which keeps existing object and starts writing at offset, see `Writer::open_at`
+    #[inline]
+    pub fn produce_at(&self, url: &Location, offset: usize) -> PcpResult<Box<dyn Writer>>{
+        let fun = *self.offset_constructor;
+        fun(url, offset)
+    }
     
     #[inline]
-    pub fn can_write(&self, url: &str) -> bool{
+    pub fn can_write(&self, url: &Location) -> bool{
         let fun = *self.can_write_fn;
         fun(url)
     }
     
     #[inline]
-    pub fn is_directory(&self, url: &str) -> bool{
+    pub fn is_directory(&self, url: &Location) -> bool{
         let fun = *self.is_directory_fn;
         fun(url)
     }
     
     #[inline]
-    pub fn make_directory(&self, url: &str){
+    pub fn make_directory(&self, url: &Location) -> PcpResult<()>{
         let fun = *self.make_directory_fn;
-        fun(url);
+        fun(url)
+    }
+
+    /// Creates directory with metadata of source directory, see `Writer::make_directory_like`
+    #[inline]
+    pub fn make_directory_like(&self, url: &Location, source: &ObjectStat) -> PcpResult<()>{
+        let fun = *self.make_directory_like_fn;
+        fun(url, source)
     }
     
     #[inline]
-    pub fn join_path(&self, base: &str, path: &str) -> String{
+    pub fn stat(&self, url: &Location) -> Option<ObjectStat>{
+        let fun = *self.stat_fn;
+        fun(url)
+    }
+    
+    #[inline]
+    pub fn rename(&self, from: &Location, to: &Location) -> std::io::Result<()>{
+        let fun = *self.rename_fn;
+        fun(from, to)
+    }
+
+    #[inline]
+    pub fn list_directory(&self, url: &Location) -> std::io::Result<Vec<OsString>>{
+        let fun = *self.list_directory_fn;
+        fun(url)
+    }
+
+    #[inline]
+    pub fn verifies_on_close(&self, url: &Location) -> PcpResult<bool>{
+        let fun = *self.verifies_on_close_fn;
+        fun(url)
+    }
+
+    /// Gets object which is replaced as a whole when URL is written, see `Writer::container`
+    #[inline]
+    pub fn container(&self, url: &Location) -> Option<Location>{
+        let fun = *self.container_fn;
+        fun(url)
+    }
+    
+    #[inline]
+    pub fn remove(&self, url: &Location) -> std::io::Result<()>{
+        let fun = *self.remove_fn;
+        fun(url)
+    }
+    
+    #[inline]
+    pub fn set_metadata(&self, url: &Location, stat: &ObjectStat) -> std::io::Result<()>{
+        let fun = *self.set_metadata_fn;
+        fun(url, stat)
+    }
+    
+    #[inline]
+    pub fn join_path(&self, base: &Location, path: &Path) -> Location{
         let fun = *self.join_path_fn;
         fun(base, path)
     }
+
+    #[inline]
+    pub fn finish(&self, url: &Location) -> PcpResult<()>{
+        let fun = *self.finish_fn;
+        fun(url)
+    }
 }
 
+#[derive(Default)]
 pub struct WriterFactory{
-    components: HashMap<String, WriterProxy>,
+    components: Vec<Registration<WriterProxy>>,
+}
+
+///
+/// Backend added to factory under a key, which is also URL scheme it handles
+///
+struct Registration<P>{
+    key: String,
+    priority: i32,
+    proxy: P,
+}
+
+/// Adds backend keeping components ordered by priority, earlier registered first among equal ones
+fn register<P>(components: &mut Vec<Registration<P>>, key: &str, priority: i32, proxy: P){
+    components.retain(|registration| registration.key != key);
+    let position = components.iter()
+        .position(|registration| registration.priority < priority)
+        .unwrap_or(components.len());
+    components.insert(position, Registration{
+        key: key.to_string(),
+        priority,
+        proxy,
+    });
 }
 
+///
+/// Chooses backend for URL: forced one if given, then one registered for URL scheme,
+/// then the first one by priority which accepts URL
+///
+fn select<P: Clone>(components: &[Registration<P>], url: &Location, backend: Option<&str>,
+                    accepts: impl Fn(&P) -> bool) -> Option<P>{
+    let by_key = |key: &str| components.iter()
+        .find(|registration| registration.key.eq_ignore_ascii_case(key))
+        .map(|registration| registration.proxy.clone());
+    if let Some(key) = backend{
+        return by_key(key);
+    }
+    if let Some(proxy) = url.scheme().and_then(by_key){
+        return Some(proxy);
+    }
+    components.iter()
+        .find(|registration| accepts(&registration.proxy))
+        .map(|registration| registration.proxy.clone())
+}
 
 impl ReaderFactory {
     pub fn new() -> ReaderFactory{
         ReaderFactory{
-            components: HashMap::new(),
+            components: Vec::new(),
         }
     }
-    
-    pub fn add_reader<T: Reader + 'static>(&mut self, key: &str){
-        self.components.insert(key.to_string(), ReaderProxy::from_type::<T>());
+
+    ///
+    /// Registers reader
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: name of backend and URL scheme it handles
+    /// * `priority`: order of probing URLs without known scheme, higher goes first
+    ///
+    pub fn add_reader<T: Reader + 'static>(&mut self, key: &str, priority: i32){
+        self.add_reader_proxy(key, priority, ReaderProxy::from_type::<T>());
     }
 
-    pub fn get_reader_proxy(&self, url: &str) -> Option<ReaderProxy>{
-        for (_, proxy) in self.components.iter(){
-            if proxy.can_read(url){
-                return Some(proxy.clone());
-            }
-        }
-        None
+    /// Registers reader which was already wrapped into proxy, e.g. by plugin
+    pub fn add_reader_proxy(&mut self, key: &str, priority: i32, mut proxy: ReaderProxy){
+        proxy.key = key.to_string();
+        register(&mut self.components, key, priority, proxy);
+    }
+
+    pub fn get_reader_proxy(&self, url: &Location, backend: Option<&str>) -> Option<ReaderProxy>{
+        select(&self.components, url, backend, |proxy| proxy.can_read(url))
+    }
+
+    /// Gets keys of registered readers in order of probing
+    pub fn keys(&self) -> Vec<String>{
+        self.components.iter().map(|registration| registration.key.clone()).collect()
     }
 }
 
 impl WriterFactory{
     pub fn new() -> WriterFactory{
         WriterFactory{
-            components: HashMap::new()
+            components: Vec::new()
         }
     }
 
-    pub fn add_writer<T: Writer + 'static>(&mut self, key: &str){
-        self.components.insert(key.to_string(), WriterProxy::from_type::<T>());
+    ///
+    /// Registers writer
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: name of backend and URL scheme it handles
+    /// * `priority`: order of probing URLs without known scheme, higher goes first
+    ///
+    pub fn add_writer<T: Writer + 'static>(&mut self, key: &str, priority: i32){
+        self.add_writer_proxy(key, priority, WriterProxy::from_type::<T>());
     }
 
-    pub fn get_writer_proxy(&self, url: &str) -> Option<WriterProxy>{
-        for (_, proxy) in self.components.iter(){
-            if proxy.can_write(url){
-                return Some(proxy.clone());
-            }
-        }
-        None
+    /// Registers writer which was already wrapped into proxy, e.g. by plugin
+    pub fn add_writer_proxy(&mut self, key: &str, priority: i32, mut proxy: WriterProxy){
+        proxy.key = key.to_string();
+        register(&mut self.components, key, priority, proxy);
+    }
+
+    pub fn get_writer_proxy(&self, url: &Location, backend: Option<&str>) -> Option<WriterProxy>{
+        select(&self.components, url, backend, |proxy| proxy.can_write(url))
+    }
+
+    /// Gets keys of registered writers in order of probing
+    pub fn keys(&self) -> Vec<String>{
+        self.components.iter().map(|registration| registration.key.clone()).collect()
+    }
+}
+
+///
+/// Produces progress displays selected by name
+///
+#[derive(Default)]
+pub struct ProgressFactory{
+    components: Vec<(String, ProgressConstructor)>,
+}
+
+impl ProgressFactory{
+    ///
+    /// Registers progress display, replacing one with the same key
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: name under which display is selected
+    ///
+    pub fn add_progress<T: ProgressDisplay + 'static>(&mut self, key: &str){
+        self.add_progress_constructor(key, || Box::new(T::new()));
+    }
+
+    /// Registers function which creates progress display, e.g. one provided by plugin
+    pub fn add_progress_constructor(&mut self, key: &str, constructor: ProgressConstructor){
+        self.components.retain(|(existing, _)| existing != key);
+        self.components.push((key.to_string(), constructor));
+    }
+
+    /// Creates progress display registered under given key
+    pub fn produce(&self, key: &str) -> Option<Box<dyn ProgressDisplay>>{
+        self.components.iter()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(key))
+            .map(|(_, constructor)| constructor())
+    }
+
+    /// Gets keys of registered progress displays in order of registration
+    pub fn keys(&self) -> Vec<String>{
+        self.components.iter().map(|(key, _)| key.clone()).collect()
     }
 }
 
 lazy_static! {
     pub static ref READER_FACTORY: Mutex<ReaderFactory> = Mutex::new(ReaderFactory::new());
     pub static ref WRITER_FACTORY: Mutex<WriterFactory> = Mutex::new(WriterFactory::new());
+    pub static ref PROGRESS_FACTORY: Mutex<ProgressFactory> = Mutex::new(ProgressFactory::default());
 }
 
 #[inline]
-pub fn get_reader_proxy_for_url(url: &str) -> Option<ReaderProxy>{
-    let result = READER_FACTORY.lock().unwrap().get_reader_proxy(url);
-    result
+pub fn get_reader_proxy_for_url(url: &Location) -> Option<ReaderProxy>{
+    get_reader_proxy_by_backend(url, None)
 }
 
 #[inline]
-pub fn get_writer_proxy_for_url(url: &str) -> Option<WriterProxy>{
-    WRITER_FACTORY.lock().unwrap().get_writer_proxy(url)
+pub fn get_writer_proxy_for_url(url: &Location) -> Option<WriterProxy>{
+    get_writer_proxy_by_backend(url, None)
+}
+
+///
+/// Gets reader for URL, using given backend instead of choosing it automatically
+///
+#[inline]
+pub fn get_reader_proxy_by_backend(url: &Location, backend: Option<&str>) -> Option<ReaderProxy>{
+    READER_FACTORY.lock().unwrap().get_reader_proxy(url, backend)
+}
+
+///
+/// Gets writer for URL, using given backend instead of choosing it automatically
+///
+#[inline]
+pub fn get_writer_proxy_by_backend(url: &Location, backend: Option<&str>) -> Option<WriterProxy>{
+    WRITER_FACTORY.lock().unwrap().get_writer_proxy(url, backend)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components() -> Vec<Registration<&'static str>>{
+        let mut components = Vec::new();
+        register(&mut components, "file", 0, "file");
+        register(&mut components, "http", 10, "http");
+        register(&mut components, "tar", 10, "tar");
+        components
+    }
+
+    #[test]
+    fn test_priority_order(){
+        let components = components();
+        let keys: Vec<_> = components.iter().map(|registration| registration.key.as_str()).collect();
+        assert_eq!(keys, ["http", "tar", "file"]);
+        assert_eq!(select(&components, &Location::parse("/tmp/a.tar"), None, |_| true), Some("http"));
+        assert_eq!(select(&components, &Location::parse("/tmp/a.tar"), None, |proxy| *proxy != "http"), Some("tar"));
+    }
+
+    #[test]
+    fn test_scheme_and_forced_backend(){
+        let components = components();
+        assert_eq!(select(&components, &Location::parse("TAR://a.tar"), None, |_| false), Some("tar"));
+        assert_eq!(select(&components, &Location::parse("ftp://host/file"), None, |proxy| *proxy == "file"), Some("file"));
+        assert_eq!(select(&components, &Location::parse("http://host/file"), Some("file"), |_| false), Some("file"));
+        assert_eq!(select(&components, &Location::parse("/tmp/file"), Some("ssh"), |_| true), None);
+    }
 }
 
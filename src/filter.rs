@@ -0,0 +1,247 @@
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+use crate::arguments::Args;
+use crate::error::PcpError;
+use crate::factories::{get_reader_proxy_for_url, ReaderProxy};
+use crate::location::Location;
+use crate::reader::read_to_end;
+use crate::utils::generic_iterator::GenericIterator;
+use crate::utils::runtime::tokio_block_on;
+
+/// Names of files with ignore patterns honoured in source directories, later ones take precedence
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+///
+/// Decides which objects of recursive copy are skipped. Patterns use gitignore syntax
+/// and are matched against paths relative to copied directory.
+///
+#[derive(Clone)]
+pub struct PathFilter {
+    include: Option<Gitignore>,
+    exclude: Option<Gitignore>,
+    ignore_files: bool,
+}
+
+fn build_matcher<'a>(lines: impl Iterator<Item=&'a str>, root: &Path) -> Result<Option<Gitignore>, String> {
+    let mut builder = GitignoreBuilder::new(root);
+    for line in lines {
+        builder.add_line(None, line).map_err(|error| error.to_string())?;
+    }
+    let matcher = builder.build().map_err(|error| error.to_string())?;
+    Ok(if matcher.is_empty() { None } else { Some(matcher) })
+}
+
+impl PathFilter {
+    ///
+    /// Creates filter from arguments, reading pattern files with registered readers
+    ///
+    /// # Arguments
+    ///
+    /// * `args`: program-wide arguments
+    ///
+    /// returns: Result<Option<PathFilter>, String>: None if nothing should be filtered,
+    /// or description of bad pattern or unreadable file
+    ///
+    pub fn from_args(args: &Args) -> Result<Option<PathFilter>, String> {
+        let mut exclude = args.exclude.clone();
+        for url in &args.exclude_from {
+            let url = Location::parse_os(url);
+            let reader_proxy = get_reader_proxy_for_url(&url)
+                .ok_or_else(|| format!("{}: No reader for URL", url))?;
+            let content = reader_proxy.produce(&url)
+                .and_then(|reader| tokio_block_on(read_to_end(reader)))
+                .map_err(|error| error.to_string())?;
+            exclude.extend(String::from_utf8_lossy(&content).lines().map(String::from));
+        }
+        let root = PathBuf::new();
+        let filter = PathFilter {
+            include: build_matcher(args.include.iter().map(String::as_str), &root)?,
+            exclude: build_matcher(exclude.iter().map(String::as_str), &root)?,
+            ignore_files: args.ignore_files,
+        };
+        if filter.include.is_none() && filter.exclude.is_none() && !filter.ignore_files {
+            Ok(None)
+        } else {
+            Ok(Some(filter))
+        }
+    }
+
+    ///
+    /// Checks whether object should be skipped. Exclude patterns and ignore files go first,
+    /// so include patterns only choose among objects which are not excluded. Directories are
+    /// never skipped only because of include patterns, so that files inside them still can
+    /// be matched.
+    ///
+    /// # Arguments
+    ///
+    /// * `relative`: path relative to copied directory
+    /// * `is_directory`: whether object is directory
+    /// * `ignore_stack`: matchers built from ignore files of parent directories, outermost first
+    ///
+    /// returns: bool: whether object is excluded
+    ///
+    fn is_excluded(&self, relative: &Path, is_directory: bool,
+                   ignore_stack: &[(PathBuf, Gitignore)]) -> bool {
+        let exclude = self.exclude.as_ref()
+            .map_or(Match::None, |exclude| exclude.matched_path_or_any_parents(relative, is_directory));
+        match exclude {
+            Match::Ignore(_) => return true,
+            // Negated exclude pattern overrides ignore files as well
+            Match::Whitelist(_) => {}
+            Match::None => for (directory, matcher) in ignore_stack.iter().rev() {
+                let path = relative.strip_prefix(directory).unwrap_or(relative);
+                match matcher.matched(path, is_directory) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => break,
+                    Match::None => {}
+                }
+            },
+        }
+        match &self.include {
+            Some(include) if !is_directory => !include.matched_path_or_any_parents(relative, false).is_ignore(),
+            _ => false,
+        }
+    }
+}
+
+///
+/// Wraps directory iterator of reader, skipping excluded objects without descending
+/// into excluded directories
+///
+pub struct FilteredIterator {
+    inner: Box<dyn GenericIterator<Location>>,
+    filter: PathFilter,
+    reader_proxy: ReaderProxy,
+    base: Location,
+    ignore_stack: Vec<(PathBuf, Gitignore)>,
+}
+
+impl FilteredIterator {
+    pub fn new(inner: Box<dyn GenericIterator<Location>>, filter: PathFilter,
+               reader_proxy: ReaderProxy, base: &Location) -> FilteredIterator {
+        let mut iterator = FilteredIterator {
+            inner,
+            filter,
+            reader_proxy,
+            base: base.clone(),
+            ignore_stack: Vec::new(),
+        };
+        iterator.load_ignore_files(base, PathBuf::new());
+        iterator
+    }
+
+    fn load_ignore_files(&mut self, directory_url: &Location, relative: PathBuf) {
+        if !self.filter.ignore_files {
+            return;
+        }
+        let mut lines = String::new();
+        for name in IGNORE_FILES {
+            let url = directory_url.join(name);
+            match self.reader_proxy.stat(&url) {
+                Some(stat) if !stat.is_directory => {},
+                _ => continue,
+            }
+            let content = match self.reader_proxy.produce(&url)
+                .and_then(|reader| tokio_block_on(read_to_end(reader))) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            lines.push_str(&String::from_utf8_lossy(&content));
+            lines.push('\n');
+        }
+        // Broken ignore files are not worth failing the copy for, like in git itself
+        if let Ok(Some(matcher)) = build_matcher(lines.lines(), &relative) {
+            self.ignore_stack.push((relative, matcher));
+        }
+    }
+}
+
+impl GenericIterator<Location> for FilteredIterator {
+    fn internal_next(&mut self) -> Option<Location> {
+        loop {
+            let object = self.inner.internal_next()?;
+            let relative = self.reader_proxy.relative_path(&self.base, &object);
+            while let Some((directory, _)) = self.ignore_stack.last() {
+                if relative.starts_with(directory) {
+                    break;
+                }
+                self.ignore_stack.pop();
+            }
+            let is_directory = self.reader_proxy.is_directory(&object);
+            if self.filter.is_excluded(&relative, is_directory, &self.ignore_stack) {
+                if is_directory {
+                    self.inner.skip_directory();
+                }
+                continue;
+            }
+            if is_directory {
+                self.load_ignore_files(&object, relative);
+            }
+            return Some(object);
+        }
+    }
+
+    #[inline]
+    fn skip_directory(&mut self) {
+        self.inner.skip_directory();
+    }
+
+    #[inline]
+    fn take_errors(&mut self) -> Vec<PcpError> {
+        self.inner.take_errors()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> PathFilter {
+        let root = PathBuf::new();
+        PathFilter {
+            include: build_matcher(include.iter().copied(), &root).unwrap(),
+            exclude: build_matcher(exclude.iter().copied(), &root).unwrap(),
+            ignore_files: false,
+        }
+    }
+
+    #[test]
+    fn test_exclude() {
+        let filter = filter(&[], &["*.log", "/build/", "!keep.log"]);
+        assert!(filter.is_excluded(Path::new("sub/debug.log"), false, &[]));
+        assert!(!filter.is_excluded(Path::new("sub/keep.log"), false, &[]));
+        assert!(filter.is_excluded(Path::new("build"), true, &[]));
+        assert!(!filter.is_excluded(Path::new("sub/build"), true, &[]));
+        assert!(!filter.is_excluded(Path::new("build"), false, &[]));
+    }
+
+    #[test]
+    fn test_include() {
+        let filter = filter(&["*.rs", "docs/"], &["target/"]);
+        assert!(!filter.is_excluded(Path::new("src/main.rs"), false, &[]));
+        assert!(filter.is_excluded(Path::new("src/notes.txt"), false, &[]));
+        assert!(!filter.is_excluded(Path::new("src"), true, &[]));
+        assert!(!filter.is_excluded(Path::new("docs/index.md"), false, &[]));
+        assert!(filter.is_excluded(Path::new("target"), true, &[]));
+        // Included files in excluded directories or ignore files are skipped all the same
+        let ignored = build_matcher(["*.bak.rs"].into_iter(), Path::new("")).unwrap().unwrap();
+        assert!(filter.is_excluded(Path::new("target/build.rs"), false, &[]));
+        assert!(filter.is_excluded(Path::new("src/old.bak.rs"), false, &[(PathBuf::new(), ignored)]));
+    }
+
+    #[test]
+    fn test_ignore_stack() {
+        let filter = filter(&[], &[]);
+        let root = build_matcher(["*.tmp"].into_iter(), Path::new("")).unwrap().unwrap();
+        let nested = build_matcher(["!important.tmp", "cache/"].into_iter(), Path::new("sub"))
+            .unwrap().unwrap();
+        let stack = vec![(PathBuf::new(), root), (PathBuf::from("sub"), nested)];
+        assert!(filter.is_excluded(Path::new("sub/file.tmp"), false, &stack));
+        assert!(!filter.is_excluded(Path::new("sub/important.tmp"), false, &stack));
+        assert!(filter.is_excluded(Path::new("sub/cache"), true, &stack));
+        assert!(!filter.is_excluded(Path::new("sub/file.txt"), false, &stack));
+    }
+}
@@ -0,0 +1,494 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::{Arc, Once};
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+use serde_json::json;
+
+use crate::arguments::Args;
+use crate::backup::BackupPolicy;
+use crate::bandwidth::{ControlSocket, JobLimit};
+use crate::copy::{complete_pending_moves, copy_directory, copy_file, notify_error, plan_copy, report_failure,
+                  PendingMoves};
+use crate::error::ErrorCallback;
+use crate::factories::{get_reader_proxy_by_backend, get_writer_proxy_by_backend, PROGRESS_FACTORY,
+                       READER_FACTORY, WRITER_FACTORY};
+use crate::location::Location;
+use crate::manifest::Manifest;
+use crate::plan::{Plan, PlanCallback};
+use crate::utils::format_size;
+use crate::progress::callback::ProgressCallback;
+use crate::registry::{register_progress_displays, register_readers, register_writers};
+use crate::transform;
+use crate::utils::runtime::init_tokio;
+
+/// Exit code when everything was copied
+pub const EXIT_SUCCESS: i32 = 0;
+/// Exit code when some objects could not be copied
+pub const EXIT_PARTIAL_FAILURE: i32 = 1;
+/// Exit code when job could not be done at all or was stopped, the same as for bad arguments
+pub const EXIT_FATAL: i32 = 2;
+
+/// Object which could not be copied
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopyFailure {
+    pub url: String,
+    pub reason: String,
+}
+
+/// Outcome of copy job
+#[derive(Debug, Default, Clone)]
+pub struct CopyReport {
+    /// Number of files which were copied or moved
+    pub copied: usize,
+    /// Number of files left as is because of overwrite policy, sync or missing recursive flag
+    pub skipped: usize,
+    /// Amount of bytes copied
+    pub bytes_copied: usize,
+    /// Objects which could not be copied
+    pub failed: Vec<CopyFailure>,
+    /// Whether job stopped before processing all sources
+    pub aborted: bool,
+    /// Time which job took
+    pub elapsed: Duration,
+}
+
+impl CopyReport {
+    ///
+    /// Records successfully copied file
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: size of file
+    ///
+    pub fn add_copied(&mut self, bytes: usize) {
+        self.copied += 1;
+        self.bytes_copied += bytes;
+    }
+
+    ///
+    /// Records object which could not be copied
+    ///
+    /// # Arguments
+    ///
+    /// * `url`: URL of object
+    /// * `reason`: description of error
+    ///
+    pub fn add_failure(&mut self, url: &str, reason: String) {
+        self.failed.push(CopyFailure {
+            url: url.to_string(),
+            reason,
+        });
+    }
+
+    /// Gets average copying rate in bytes per second
+    pub fn bytes_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes_copied as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Gets exit code which reflects outcome of job
+    pub fn exit_code(&self) -> i32 {
+        if self.aborted {
+            EXIT_FATAL
+        } else if !self.failed.is_empty() {
+            EXIT_PARTIAL_FAILURE
+        } else {
+            EXIT_SUCCESS
+        }
+    }
+
+    ///
+    /// Renders human-readable summary, one line of totals followed by failures
+    ///
+    pub fn render_text(&self) -> String {
+        let failed = format!("{} failed", self.failed.len());
+        let mut result = format!("{}{}: {} copied, {} skipped, {}, {} in {:.1} s ({}/s)\n",
+                                 "Summary".bold(), "".clear(), self.copied, self.skipped,
+                                 if self.failed.is_empty() { failed.normal() } else { failed.red().bold() },
+                                 format_size(self.bytes_copied), self.elapsed.as_secs_f64(),
+                                 format_size(self.bytes_per_second() as usize));
+        for failure in &self.failed {
+            result.push_str(&format!("  {}{}: {}\n", failure.url.red(), "".clear(), failure.reason));
+        }
+        result
+    }
+
+    ///
+    /// Renders summary as single-line JSON object
+    ///
+    pub fn render_json(&self) -> String {
+        let failures: Vec<_> = self.failed.iter()
+            .map(|failure| json!({"url": failure.url, "reason": failure.reason}))
+            .collect();
+        json!({
+            "copied": self.copied,
+            "skipped": self.skipped,
+            "failed": self.failed.len(),
+            "bytes": self.bytes_copied,
+            "elapsed_seconds": self.elapsed.as_secs_f64(),
+            "bytes_per_second": self.bytes_per_second(),
+            "aborted": self.aborted,
+            "exit_code": self.exit_code(),
+            "failures": failures,
+        }).to_string()
+    }
+
+    /// Checks that every source was copied
+    pub fn is_success(&self) -> bool {
+        !self.aborted && self.failed.is_empty()
+    }
+}
+
+///
+/// Registers built-in readers, writers and progress displays declared by their modules.
+/// Called by `CopyJob` itself, so it is only needed when factories are used directly.
+///
+pub fn register_backends() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        register_readers();
+        register_writers();
+        register_progress_displays();
+    });
+}
+
+///
+/// Copy of one or more sources to destination, configured in builder style
+///
+/// # Examples
+///
+/// ```no_run
+/// use pcp::{Args, CopyJob};
+///
+/// let report = CopyJob::new("/mnt/backup")
+///     .source("/home/user/photos")
+///     .options(Args { recursive: true, ..Args::default() })
+///     .on_progress(|status, bytes_out, bytes_total| println!("{}: {}/{}", status, bytes_out, bytes_total))
+///     .run();
+/// assert!(report.is_success());
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct CopyJob {
+    sources: Vec<Location>,
+    destination: Location,
+    options: Args,
+}
+
+impl CopyJob {
+    ///
+    /// Creates job with default options and no sources
+    ///
+    /// # Arguments
+    ///
+    /// * `destination`: URL of destination file or directory
+    ///
+    pub fn new(destination: impl AsRef<OsStr>) -> CopyJob {
+        CopyJob {
+            sources: Vec::new(),
+            destination: Location::parse_os(destination.as_ref()),
+            options: Args::default(),
+        }
+    }
+
+    /// Adds source file or directory
+    pub fn source(mut self, url: impl AsRef<OsStr>) -> CopyJob {
+        self.sources.push(Location::parse_os(url.as_ref()));
+        self
+    }
+
+    /// Adds several sources
+    pub fn sources<S: AsRef<OsStr>>(mut self, urls: impl IntoIterator<Item=S>) -> CopyJob {
+        self.sources.extend(urls.into_iter().map(|url| Location::parse_os(url.as_ref())));
+        self
+    }
+
+    ///
+    /// Sets options in the same form as they are parsed from command line.
+    /// Sources and destination of options are ignored, callbacks are kept if options
+    /// do not have their own.
+    ///
+    pub fn options(mut self, options: Args) -> CopyJob {
+        let previous = std::mem::replace(&mut self.options, options);
+        self.options.progress_callback = self.options.progress_callback.take().or(previous.progress_callback);
+        self.options.error_callback = self.options.error_callback.take().or(previous.error_callback);
+        self.options.plan_callback = self.options.plan_callback.take().or(previous.plan_callback);
+        self
+    }
+
+    ///
+    /// Sets function which receives progress of each file instead of terminal
+    ///
+    /// # Arguments
+    ///
+    /// * `callback`: receives status line, bytes processed and total size of current file
+    ///
+    pub fn on_progress(mut self, callback: impl Fn(&str, usize, usize) + Send + Sync + 'static) -> CopyJob {
+        self.options.progress_callback = Some(ProgressCallback(Arc::new(callback)));
+        self
+    }
+
+    ///
+    /// Sets function which learns about errors as they happen. Errors are collected in report
+    /// anyway, nothing is printed by job itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `callback`: receives URL of object and description of error or warning
+    ///
+    pub fn on_error(mut self, callback: impl Fn(&str, &str) + Send + Sync + 'static) -> CopyJob {
+        self.options.error_callback = Some(ErrorCallback(Arc::new(callback)));
+        self
+    }
+
+    ///
+    /// Sets function which receives plan of dry run, or plan of each synchronized directory
+    /// before it is carried out
+    ///
+    pub fn on_plan(mut self, callback: impl Fn(&Plan) + Send + Sync + 'static) -> CopyJob {
+        self.options.plan_callback = Some(PlanCallback(Arc::new(callback)));
+        self
+    }
+
+    ///
+    /// Filters out sources which can not be copied, reporting them
+    ///
+    /// returns: Option<Vec<Location>>: sources to copy or None if job can not be done at all
+    ///
+    fn check_sources(&self, report: &mut CopyReport) -> Option<Vec<Location>> {
+        let args = &self.options;
+        let known_readers = READER_FACTORY.lock().unwrap().keys();
+        let known_writers = WRITER_FACTORY.lock().unwrap().keys();
+        let known_displays = PROGRESS_FACTORY.lock().unwrap().keys();
+        for (backend, known, what) in [(&args.src_backend, known_readers, "backend"),
+                                       (&args.dst_backend, known_writers, "backend"),
+                                       (&args.progress, known_displays, "progress display")] {
+            if let Some(backend) = backend.as_deref().filter(|backend| {
+                !known.iter().any(|key| key.eq_ignore_ascii_case(backend))
+            }) {
+                report_failure(report, args, backend, format!("Unknown {}, available are: {}", what, known.join(", ")));
+                return None;
+            }
+        }
+        if let Err(error) = transform::check_args(args) {
+            report_failure(report, args, "Can not copy", error);
+            return None;
+        }
+        // Command line rejects it as well, backup would leave nothing to write into at offset
+        if args.seek.is_some() && BackupPolicy::from_args(args).is_some() {
+            report_failure(report, args, "Can not copy", "--seek can not be combined with backups");
+            return None;
+        }
+        let writer_proxy = match get_writer_proxy_by_backend(&self.destination, args.dst_backend.as_deref()) {
+            Some(proxy) => proxy,
+            None => {
+                // Local files are written to any existing directory, so only directory can be missing
+                let reason = match (self.destination.local_path(), &args.dst_backend) {
+                    (Some(path), None) if !path.exists() => "No such directory",
+                    _ => "No writer for URL",
+                };
+                report_failure(report, args, &self.destination, reason);
+                return None;
+            }
+        };
+        let mut sources = Vec::new();
+        for source in &self.sources {
+            let reader_proxy = match get_reader_proxy_by_backend(source, args.src_backend.as_deref()) {
+                Some(proxy) => proxy,
+                None => {
+                    let reason = match (source.local_path(), &args.src_backend) {
+                        (Some(path), None) if !path.exists() => "No such file or directory",
+                        _ => "No reader for URL",
+                    };
+                    report_failure(report, args, source, reason);
+                    if args.fail_fast {
+                        return None;
+                    }
+                    continue;
+                }
+            };
+            if reader_proxy.is_directory(source) && !args.recursive && !args.move_sources {
+                notify_error(args, &source.to_string(), "Is a directory, but recursive flag is not set, skipping");
+                report.skipped += 1;
+                continue;
+            }
+            if reader_proxy.can_read(source) {
+                sources.push(source.clone())
+            } else {
+                report_failure(report, args, source, "Can not read source");
+            }
+        }
+        if sources.len() > 1 && !writer_proxy.is_directory(&self.destination) {
+            report_failure(report, args, &self.destination, "Is not a directory");
+            return None;
+        }
+        Some(sources)
+    }
+
+    ///
+    /// Plans job without changing anything
+    ///
+    /// returns: Result<Plan, String>: operations which `run` would perform or description
+    /// of the reason why job can not be done
+    ///
+    pub fn plan(&self) -> Result<Plan, String> {
+        register_backends();
+        let mut report = CopyReport::default();
+        let sources = self.check_sources(&mut report).ok_or_else(|| match report.failed.last() {
+            Some(failure) => format!("{}: {}", failure.url, failure.reason),
+            None => String::from("Nothing can be copied"),
+        })?;
+        init_tokio();
+        self.plan_sources(&sources)
+    }
+
+    fn plan_sources(&self, sources: &[Location]) -> Result<Plan, String> {
+        let mut plan = Plan::default();
+        for source in sources {
+            plan.append(plan_copy(source, &self.destination, &self.options)?);
+        }
+        Ok(plan)
+    }
+
+    ///
+    /// Copies all sources. With dry-run option only passes plan to plan callback.
+    ///
+    /// returns: CopyReport
+    ///
+    pub fn run(self) -> CopyReport {
+        let start = Instant::now();
+        let mut report = self.run_sources();
+        report.elapsed = start.elapsed();
+        report
+    }
+
+    fn run_sources(&self) -> CopyReport {
+        register_backends();
+        let args = &self.options;
+        let mut report = CopyReport::default();
+        let sources = match self.check_sources(&mut report) {
+            Some(sources) => sources,
+            None => {
+                report.aborted = true;
+                return report;
+            }
+        };
+        init_tokio();
+        if args.dry_run {
+            match self.plan_sources(&sources) {
+                Ok(plan) => plan.notify(args),
+                Err(error) => {
+                    report_failure(&mut report, args, "Can not plan", error);
+                    report.aborted = true;
+                }
+            }
+            return report;
+        }
+        // Limit stays while job runs, jobs without one keep limits of the others
+        let _limit = args.bwlimit.map(JobLimit::install);
+        // Socket is removed when job is over
+        let _control_socket = match &args.bwlimit_socket {
+            Some(path) => match ControlSocket::bind(Path::new(path)) {
+                Ok(socket) => Some(socket),
+                Err(error) => {
+                    report_failure(&mut report, args, path.to_string_lossy(), format!("Can not listen: {}", error));
+                    report.aborted = true;
+                    return report;
+                }
+            },
+            None => None,
+        };
+        let mut manifest = args.manifest.as_ref().map(|_| Manifest::new());
+        let writer_proxy = get_writer_proxy_by_backend(&self.destination, args.dst_backend.as_deref()).unwrap();
+        let mut moves = PendingMoves::default();
+        for source in sources {
+            let proxy = get_reader_proxy_by_backend(&source, args.src_backend.as_deref()).unwrap();
+            let result = if proxy.is_directory(&source) {
+                copy_directory(&source, &self.destination, args, &mut report, manifest.as_mut(), &mut moves)
+            } else {
+                copy_file(&source, &self.destination, args, &mut report, manifest.as_mut(), &mut moves)
+            };
+            // Failed objects are already in report
+            if !result && args.fail_fast {
+                report.aborted = true;
+                break;
+            }
+        }
+        // Destinations like archives are not valid until they are completed, even after failures
+        if let Err(error) = writer_proxy.finish(&self.destination) {
+            report_failure(&mut report, args, error.url(), error.reason());
+            report.aborted = true;
+        }
+        // Sources are removed only when their copies can be read back from finished destination
+        complete_pending_moves(moves, !report.aborted, args, &mut report);
+        if report.aborted {
+            return report;
+        }
+        if let (Some(manifest_url), Some(manifest)) = (&args.manifest, manifest) {
+            if let Err(error) = manifest.write_to(&Location::parse_os(manifest_url)) {
+                report_failure(&mut report, args, error.url(), error.reason());
+                report.aborted = true;
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let mut report = CopyReport::default();
+        assert_eq!(report.exit_code(), EXIT_SUCCESS);
+        report.add_failure("a", String::from("broken"));
+        assert_eq!(report.exit_code(), EXIT_PARTIAL_FAILURE);
+        report.aborted = true;
+        assert_eq!(report.exit_code(), EXIT_FATAL);
+    }
+
+    #[test]
+    fn test_seek_with_backup() {
+        let directory = std::env::temp_dir().join(format!("pcp-job-seek-backup-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("source"), b"xy").unwrap();
+        std::fs::write(directory.join("destination"), b"AAAAAAAAAAAAAAAA").unwrap();
+        let report = CopyJob::new(directory.join("destination"))
+            .source(directory.join("source"))
+            .options(Args {
+                seek: Some(4),
+                backup: Some(Some(crate::arguments::BackupMode::Simple)),
+                no_progress: true,
+                ..Args::default()
+            })
+            .run();
+        let content = std::fs::read(directory.join("destination")).unwrap();
+        let backup_exists = directory.join("destination~").exists();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(!report.is_success());
+        assert_eq!(content, b"AAAAAAAAAAAAAAAA");
+        assert!(!backup_exists);
+    }
+
+    #[test]
+    fn test_render_json() {
+        let mut report = CopyReport {
+            elapsed: Duration::from_secs(2),
+            ..CopyReport::default()
+        };
+        report.add_copied(100);
+        report.add_failure("dir/\"quoted\"", String::from("Permission denied"));
+        let value: serde_json::Value = serde_json::from_str(&report.render_json()).unwrap();
+        assert_eq!(value["copied"], 1);
+        assert_eq!(value["bytes_per_second"], 50.0);
+        assert_eq!(value["exit_code"], EXIT_PARTIAL_FAILURE);
+        assert_eq!(value["failures"][0]["url"], "dir/\"quoted\"");
+    }
+}
@@ -0,0 +1,39 @@
+//!
+//! Copy engine of `pcp`: pluggable readers and writers, progress displays and
+//! `CopyJob` which ties them together
+//!
+
+pub mod reader;
+pub mod writer;
+pub mod progress;
+pub mod copy;
+pub mod utils;
+pub mod arguments;
+pub mod factories;
+pub mod manifest;
+pub mod plan;
+pub mod job;
+pub mod error;
+pub mod location;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod plugin;
+pub mod registry;
+pub mod bandwidth;
+pub mod transform;
+mod overwrite;
+mod backup;
+mod filter;
+
+pub use crate::arguments::Args;
+pub use crate::error::{PcpError, PcpResult};
+pub use crate::job::{register_backends, CopyJob, CopyReport};
+pub use crate::location::Location;
+pub use crate::progress::ProgressDisplay;
+pub use crate::reader::Reader;
+pub use crate::transform::Transform;
+pub use crate::writer::Writer;
+
+// Used by registration macros
+#[doc(hidden)]
+pub use inventory;
@@ -0,0 +1,275 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt::{Display, Formatter};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// Scheme of URLs which point to local files
+const FILE_SCHEME: &str = "file";
+
+///
+/// Parsed source or destination: either plain local path or URL like
+/// `scheme://authority/path?query`
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    scheme: Option<String>,
+    authority: Option<String>,
+    /// Path as is for plain paths, percent-decoded for `file://` URLs
+    path: OsString,
+    query: Option<String>,
+}
+
+#[inline]
+fn is_valid_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+///
+/// Decodes `%XX` sequences, leaving malformed ones as is
+///
+fn percent_decode(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let decoded = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(byte) => {
+                result.push(byte);
+                i += 3;
+            }
+            None => {
+                result.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    result
+}
+
+///
+/// Splits URL into scheme, authority, path and query
+///
+/// returns: Option<Location>: None if URL has no valid scheme, or its scheme, authority
+/// or query are not UTF-8
+///
+fn parse_url(url: &[u8]) -> Option<Location> {
+    let separator = url.windows(3).position(|window| window == b"://")?;
+    let scheme = std::str::from_utf8(&url[..separator]).ok().filter(|scheme| is_valid_scheme(scheme))?;
+    let scheme = scheme.to_ascii_lowercase();
+    let rest = &url[separator + 3..];
+    let (rest, query) = match rest.iter().position(|byte| *byte == b'?') {
+        Some(position) => (&rest[..position], Some(String::from_utf8(rest[position + 1..].to_vec()).ok()?)),
+        None => (rest, None),
+    };
+    let (authority, path) = rest.split_at(rest.iter().position(|byte| *byte == b'/').unwrap_or(rest.len()));
+    let path = if scheme == FILE_SCHEME {
+        percent_decode(path)
+    } else {
+        path.to_vec()
+    };
+    Some(Location {
+        scheme: Some(scheme),
+        authority: Some(std::str::from_utf8(authority).ok()?.to_string()),
+        path: OsString::from_vec(path),
+        query,
+    })
+}
+
+impl Location {
+    ///
+    /// Parses URL or path given by user. Anything without valid `scheme://` prefix is a local path.
+    ///
+    /// # Arguments
+    ///
+    /// * `url`: URL or path
+    ///
+    /// returns: Location
+    ///
+    pub fn parse(url: &str) -> Location {
+        parse_url(url.as_bytes()).unwrap_or_else(|| Location::from_path(url))
+    }
+
+    ///
+    /// Parses URL or path given as command line argument. Paths of `file://` URLs and plain
+    /// paths may be any bytes, while the rest of URL has to be UTF-8.
+    ///
+    pub fn parse_os(url: &OsStr) -> Location {
+        parse_url(url.as_bytes()).unwrap_or_else(|| Location::from_path(url))
+    }
+
+    ///
+    /// Creates location of local path
+    ///
+    pub fn from_path(path: impl Into<PathBuf>) -> Location {
+        Location {
+            scheme: None,
+            authority: None,
+            path: path.into().into_os_string(),
+            query: None,
+        }
+    }
+
+    /// Gets lowercase scheme, None for plain paths
+    #[inline]
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    /// Gets authority, i.e. host with optional user and port
+    #[inline]
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_deref()
+    }
+
+    /// Gets path part of URL
+    #[inline]
+    pub fn path(&self) -> &OsStr {
+        &self.path
+    }
+
+    /// Gets query part of URL
+    #[inline]
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    ///
+    /// Gets path in local filesystem for plain paths and `file://` URLs without remote host
+    ///
+    pub fn local_path(&self) -> Option<&Path> {
+        match (self.scheme(), self.authority()) {
+            (None, _) => Some(Path::new(&self.path)),
+            (Some(FILE_SCHEME), Some("" | "localhost")) => Some(Path::new(&self.path)),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Creates location with the same scheme, authority and query, but different path
+    ///
+    pub fn with_path(&self, path: impl Into<OsString>) -> Location {
+        Location {
+            path: path.into(),
+            ..self.clone()
+        }
+    }
+
+    ///
+    /// Appends relative path, component by component, so that absolute `relative`
+    /// does not replace path
+    ///
+    /// # Arguments
+    ///
+    /// * `relative`: path relative to this location
+    ///
+    /// returns: Location
+    ///
+    pub fn join(&self, relative: impl AsRef<Path>) -> Location {
+        let mut path = PathBuf::from(&self.path);
+        path.extend(relative.as_ref().iter().filter(|component| *component != "/"));
+        self.with_path(path)
+    }
+
+    ///
+    /// Appends text to the last component of path, e.g. suffix of backup
+    ///
+    pub fn with_suffix(&self, suffix: &str) -> Location {
+        let mut path = self.path.clone();
+        path.push(suffix);
+        self.with_path(path)
+    }
+
+    /// Gets last component of path, ignoring trailing separator
+    pub fn file_name(&self) -> Option<&OsStr> {
+        Path::new(&self.path).file_name()
+    }
+
+    ///
+    /// Converts location back to URL without losing bytes of path which are not valid UTF-8
+    ///
+    pub fn to_os_string(&self) -> OsString {
+        let mut result = OsString::new();
+        if let Some(scheme) = &self.scheme {
+            result.push(format!("{}://{}", scheme, self.authority.as_deref().unwrap_or("")));
+        }
+        result.push(&self.path);
+        if let Some(query) = &self.query {
+            result.push(format!("?{}", query));
+        }
+        result
+    }
+
+    /// Checks whether path ends with separator, which means contents of directory
+    #[inline]
+    pub fn has_trailing_separator(&self) -> bool {
+        self.path.as_bytes().last() == Some(&b'/')
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_os_string().to_string_lossy())
+    }
+}
+
+impl From<&str> for Location {
+    #[inline]
+    fn from(url: &str) -> Self {
+        Location::parse(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_paths() {
+        for path in ["/tmp/a+b@c(1),~:x", "relative/dir/", "name with spaces", "C:weird"] {
+            let location = Location::parse(path);
+            assert_eq!(location.scheme(), None);
+            assert_eq!(location.local_path(), Some(Path::new(path)));
+            assert_eq!(location.to_string(), path);
+        }
+    }
+
+    #[test]
+    fn test_url() {
+        let location = Location::parse("SFTP://user@host:22/srv/data?mode=fast");
+        assert_eq!(location.scheme(), Some("sftp"));
+        assert_eq!(location.authority(), Some("user@host:22"));
+        assert_eq!(location.path(), "/srv/data");
+        assert_eq!(location.query(), Some("mode=fast"));
+        assert_eq!(location.local_path(), None);
+        assert_eq!(location.join("sub/file").to_string(), "sftp://user@host:22/srv/data/sub/file?mode=fast");
+    }
+
+    #[test]
+    fn test_file_url() {
+        let location = Location::parse("file:///tmp/a%20b/%FF");
+        let expected = OsString::from_vec(b"/tmp/a b/\xff".to_vec());
+        assert_eq!(location.local_path(), Some(Path::new(&expected)));
+        assert_eq!(Location::parse("file://localhost/etc").local_path(), Some(Path::new("/etc")));
+        assert_eq!(Location::parse("file://server/share").local_path(), None);
+        assert_eq!(percent_decode(b"100%"), b"100%");
+        let latin1 = Path::new(OsStr::from_bytes(b"/tmp/caf\xe9"));
+        assert_eq!(Location::parse_os(OsStr::from_bytes(b"file:///tmp/caf\xe9")).local_path(), Some(latin1));
+        assert_eq!(Location::parse_os(OsStr::from_bytes(b"FILE:///tmp/caf%E9")).local_path(), Some(latin1));
+        assert_eq!(Location::parse_os(OsStr::from_bytes(b"caf\xe9")).local_path(), Some(Path::new(OsStr::from_bytes(b"caf\xe9"))));
+    }
+
+    #[test]
+    fn test_path_helpers() {
+        let location = Location::parse("/tmp/dir/");
+        assert!(location.has_trailing_separator());
+        assert_eq!(location.file_name(), Some(OsStr::new("dir")));
+        assert_eq!(location.join("/a/b").to_string(), "/tmp/dir/a/b");
+        assert_eq!(Location::parse("dir/file").with_suffix(".~1~").to_string(), "dir/file.~1~");
+    }
+}
@@ -1,72 +1,115 @@
-use crate::copy::{copy_directory, copy_file};
+use std::ffi::OsStr;
+use std::path::Path;
 use std::process::exit;
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use colored::Colorize;
 
-mod reader;
-mod writer;
-mod progress;
-mod copy;
-mod utils;
-mod arguments;
-mod factories;
+use pcp::factories::{PROGRESS_FACTORY, READER_FACTORY, WRITER_FACTORY};
+use pcp::job::{EXIT_FATAL, EXIT_PARTIAL_FAILURE, EXIT_SUCCESS};
+use pcp::manifest::check_manifest;
+use pcp::plugin::{load_plugins, loaded_plugins, plugin_directories};
+use pcp::registry::FEATURES;
+use pcp::utils::runtime::init_tokio;
+use pcp::arguments::SummaryFormat;
+use pcp::{register_backends, Args, CopyJob, Location};
 
-use crate::arguments::Args;
-use crate::factories::{get_reader_proxy_for_url, get_writer_proxy_for_url};
-use crate::reader::register_readers;
-use crate::writer::register_writers;
-use crate::utils::runtime::init_tokio;
+/// Name under which the program works as `mv`
+const MOVE_PROGRAM_NAME: &str = "pmv";
 
+/// Checks whether program was started via `pmv` link
+fn is_invoked_as_move() -> bool{
+    std::env::args_os().next()
+        .map(|program| Path::new(&program).file_name() == Some(OsStr::new(MOVE_PROGRAM_NAME)))
+        .unwrap_or(false)
+}
+
+///
+/// Prints version. Verbose output also lists optional features and backends compiled in,
+/// so it has to be printed before plugins are loaded.
+///
+fn print_version(verbose: bool){
+    println!("pcp {}", env!("CARGO_PKG_VERSION"));
+    if !verbose{
+        return;
+    }
+    let features = if FEATURES.is_empty() { String::from("none") } else { FEATURES.join(", ") };
+    println!("{}{}: {}", "Features".bold(), "".clear(), features);
+    print_registered();
+}
+
+/// Prints keys of registered readers, writers and progress displays
+fn print_registered(){
+    println!("{}{}: {}", "Readers".bold(), "".clear(), READER_FACTORY.lock().unwrap().keys().join(", "));
+    println!("{}{}: {}", "Writers".bold(), "".clear(), WRITER_FACTORY.lock().unwrap().keys().join(", "));
+    println!("{}{}: {}", "Progress displays".bold(), "".clear(), PROGRESS_FACTORY.lock().unwrap().keys().join(", "));
+}
+
+/// Prints registered backends and plugins they came from
+fn print_backends(){
+    print_registered();
+    let directories: Vec<_> = plugin_directories().iter().map(|path| path.display().to_string()).collect();
+    println!("{}{}: {}", "Plugin directories".bold(), "".clear(), directories.join(", "));
+    for plugin in loaded_plugins(){
+        println!("  {} ({})", plugin.name.green(), plugin.path.display());
+    }
+}
 
 fn main() {
-    register_readers();
-    register_writers();
-    let args = Args::parse();
-    let cloned_args = args.clone();
-    let mut sources = Vec::<String>::new();
-    let writer_proxy = get_writer_proxy_for_url(&args.dest);
-    if writer_proxy.is_none(){
-        println!("{}: {}No writer for URL", args.dest.red().bold(), "".clear());
-        exit(255);
+    register_backends();
+    // Version goes without sources and destination, so it is checked before they are required
+    let matches = Args::command().ignore_errors(true).get_matches();
+    if matches.get_flag("version"){
+        print_version(matches.get_flag("verbose"));
+        exit(0);
     }
-    let writer_proxy = writer_proxy.unwrap();
-    for source in args.srcs {
-        let reader_proxy = get_reader_proxy_for_url(&source);
-        if reader_proxy.is_none(){
-            println!("{}: {}No reader for URL", source.red().bold(), "".clear());
-            if args.fail_fast{
-                exit(255);
-            }
-            continue;
-        }
-        let reader_proxy = reader_proxy.unwrap();
-        if reader_proxy.is_directory(&source) && !args.recursive{
-            println!("{}{}: Is a directory, but recursive flag is not set, skipping", source.bold(), 
-                     "".clear());
-            continue;
-        }
-        if reader_proxy.can_read(&source){
-            sources.push(source)
-        } else {
-            println!("{}{}: Can not read source", source.bold(), "".clear());
-        }
+    for (path, error) in load_plugins(){
+        eprintln!("{}{}: Can not load plugin: {}", path.display().to_string().bold().yellow(), "".clear(), error);
+    }
+    let mut args = Args::parse();
+    if args.list_backends{
+        print_backends();
+        exit(0);
     }
-    if sources.len() > 1 && !writer_proxy.is_directory(&args.dest){
-        println!("{}{}: Is not a directory", args.dest.bold(), "".clear());
-        exit(255);
+    if is_invoked_as_move(){
+        args.move_sources = true;
     }
-    init_tokio();
-    for source in sources{
-        let proxy = get_reader_proxy_for_url(&source).unwrap();
-        if proxy.is_directory(&source) {
-            if !copy_directory(&source, &args.dest, &cloned_args) && args.fail_fast{
-                exit(255);
+    if let Some(manifest_url) = &args.check_manifest{
+        init_tokio();
+        let manifest_url = Location::parse_os(manifest_url);
+        match check_manifest(&manifest_url, &Location::parse_os(&args.dest), &args){
+            Ok(check) => {
+                print!("{}", check.render_text());
+                exit(if check.is_success() { EXIT_SUCCESS } else { EXIT_PARTIAL_FAILURE });
             }
-        } else {
-            if !copy_file(&source, &args.dest, &cloned_args) && args.fail_fast{
-                exit(255);
+            Err(error) => {
+                eprintln!("{}{}: {}", error.url().bold().red(), "".clear(), error.reason());
+                exit(EXIT_FATAL);
             }
         }
     }
+    let dry_run = args.dry_run;
+    let summary = args.summary;
+    let recursive = args.recursive;
+    let report = CopyJob::new(&args.dest)
+        .sources(args.srcs.clone())
+        .options(args)
+        .on_error(|url, reason| eprintln!("{}{}: {}", url.bold().red(), "".clear(), reason))
+        // Dry run shows everything, while sync only what is going to change
+        .on_plan(move |plan| print!("{}", plan.render_text(dry_run)))
+        .run();
+    let summary = match summary{
+        Some(format) => format,
+        // Plan of dry run tells everything already
+        None if dry_run => SummaryFormat::None,
+        // Recursive copies and failures are worth a summary even if it was not asked for
+        None if recursive || !report.failed.is_empty() => SummaryFormat::Text,
+        None => SummaryFormat::None,
+    };
+    match summary{
+        SummaryFormat::Text => print!("{}", report.render_text()),
+        SummaryFormat::Json => println!("{}", report.render_json()),
+        SummaryFormat::None => {}
+    }
+    exit(report.exit_code());
 }
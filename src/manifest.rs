@@ -0,0 +1,431 @@
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use sha2::{Digest, Sha256};
+
+use crate::arguments::Args;
+use crate::copy::{get_progress_from_args, Buffer, DynBuffer};
+use crate::error::{PcpError, PcpResult};
+use crate::factories::{get_reader_proxy_for_url, get_writer_proxy_for_url};
+use crate::location::Location;
+use crate::progress::ProgressDisplay;
+use crate::reader::{read_to_end, Reader};
+use crate::utils::runtime::tokio_block_on;
+
+/// Single line of sha256sum-compatible manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub digest: String,
+    pub path: PathBuf,
+}
+
+/// Collects checksums of copied files
+#[derive(Default)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>,
+}
+
+///
+/// Formats finished hash as lowercase hex string
+///
+/// # Arguments
+///
+/// * `hasher`: hasher which consumed all data
+///
+/// returns: String: hex digest
+///
+pub fn hex_digest(hasher: Sha256) -> String {
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn escape_path(path: &[u8]) -> (bool, Vec<u8>) {
+    if !path.iter().any(|byte| matches!(byte, b'\\' | b'\n' | b'\r')) {
+        return (false, path.to_vec());
+    }
+    let mut escaped = Vec::with_capacity(path.len() + 2);
+    for &byte in path {
+        match byte {
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            b'\r' => escaped.extend_from_slice(b"\\r"),
+            _ => escaped.push(byte),
+        }
+    }
+    (true, escaped)
+}
+
+fn unescape_path(path: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(path.len());
+    let mut bytes = path.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'\\' {
+            result.push(byte);
+            continue;
+        }
+        match bytes.next()? {
+            b'\\' => result.push(b'\\'),
+            b'n' => result.push(b'\n'),
+            b'r' => result.push(b'\r'),
+            _ => return None,
+        }
+    }
+    Some(result)
+}
+
+impl Manifest {
+    pub fn new() -> Manifest {
+        Manifest {
+            entries: Vec::new(),
+        }
+    }
+
+    ///
+    /// Records checksum of copied file
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: path of file relative to destination
+    /// * `digest`: hex digest of file contents
+    ///
+    pub fn add_entry(&mut self, path: &Path, digest: String) {
+        self.entries.push(ManifestEntry {
+            digest,
+            path: path.to_path_buf(),
+        });
+    }
+
+    ///
+    /// Renders manifest in format understood by `sha256sum -c`. Paths are written as raw bytes,
+    /// so names which are not valid UTF-8 are kept intact.
+    ///
+    pub fn render(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        for entry in &self.entries {
+            let (escaped, path) = escape_path(entry.path.as_os_str().as_bytes());
+            if escaped {
+                result.push(b'\\');
+            }
+            result.extend_from_slice(entry.digest.as_bytes());
+            result.extend_from_slice(b"  ");
+            result.extend_from_slice(&path);
+            result.push(b'\n');
+        }
+        result
+    }
+
+    ///
+    /// Parses manifest produced by `sha256sum` or by this program
+    ///
+    /// # Arguments
+    ///
+    /// * `content`: contents of manifest file
+    ///
+    /// returns: Result<Vec<ManifestEntry>, String>: entries or description of malformed line
+    ///
+    pub fn parse(content: &[u8]) -> Result<Vec<ManifestEntry>, String> {
+        let mut result = Vec::new();
+        for (number, line) in content.split(|byte| *byte == b'\n').enumerate() {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let malformed = || format!("line {}: improperly formatted SHA256 checksum line", number + 1);
+            let (escaped, line) = match line.strip_prefix(b"\\") {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            if line.len() < 66 {
+                return Err(malformed());
+            }
+            let (digest, rest) = line.split_at(64);
+            if !digest.iter().all(u8::is_ascii_hexdigit) {
+                return Err(malformed());
+            }
+            // Two spaces for text mode, space and asterisk for binary mode
+            let path = match rest.strip_prefix(b"  ").or_else(|| rest.strip_prefix(b" *")) {
+                Some(path) if !path.is_empty() => path,
+                _ => return Err(malformed()),
+            };
+            let path = if escaped {
+                unescape_path(path).ok_or_else(malformed)?
+            } else {
+                path.to_vec()
+            };
+            result.push(ManifestEntry {
+                digest: String::from_utf8_lossy(digest).to_ascii_lowercase(),
+                path: PathBuf::from(OsString::from_vec(path)),
+            });
+        }
+        Ok(result)
+    }
+
+    ///
+    /// Writes manifest to given URL using registered writers
+    ///
+    /// # Arguments
+    ///
+    /// * `url`: URL of manifest file
+    ///
+    /// returns: PcpResult<()>: error if manifest could not be written
+    ///
+    pub fn write_to(&self, url: &Location) -> PcpResult<()> {
+        let writer_proxy = get_writer_proxy_for_url(url)
+            .ok_or_else(|| PcpError::Unsupported(url.to_string(), String::from("No writer for URL")))?;
+        let mut content = self.render();
+        let mut writer = writer_proxy.produce(url)?;
+        writer.set_size(content.len())?;
+        tokio_block_on(async {
+            // Writers may write only part of chunk
+            while !content.is_empty() {
+                match writer.write_chunk(&content, content.len()).await? {
+                    0 => return Err(PcpError::other(url, "Nothing was written")),
+                    written => {
+                        content.drain(..written);
+                    }
+                }
+            }
+            writer.close()
+        }).map_err(|error| PcpError::other(url, format!("Can not write manifest: {}", error.reason())))?;
+        // Manifest may be the only object of its destination, like member of archive
+        writer_proxy.finish(url)
+    }
+}
+
+async fn hash_reader(mut reader: Box<dyn Reader>,
+                     progress: &mut Box<dyn ProgressDisplay>) -> PcpResult<String> {
+    let chunk_size = reader.get_blocksize();
+    let mut buffer = DynBuffer::make_buffer(chunk_size);
+    let mut hasher = Sha256::new();
+    progress.set_size(reader.get_size()?);
+    let result = loop {
+        let bytes_read = match reader.read_chunk(&mut buffer, chunk_size).await {
+            Ok(0) => break Ok(hex_digest(hasher)),
+            Ok(bytes_read) => bytes_read,
+            Err(error) => break Err(error),
+        };
+        hasher.update(&buffer[..bytes_read]);
+        progress.add_bytes_written(bytes_read);
+    };
+    progress.flush();
+    result
+}
+
+///
+/// Computes SHA256 of object using registered readers, showing progress
+///
+/// # Arguments
+///
+/// * `url`: URL of file to hash
+/// * `args`: program-wide arguments
+///
+/// returns: Option<String>: hex digest or None if file can not be read
+///
+pub fn hash_url(url: &Location, args: &Args) -> Option<String> {
+    let reader_proxy = get_reader_proxy_for_url(url)?;
+    if reader_proxy.is_directory(url) {
+        return None;
+    }
+    let reader = reader_proxy.produce(url).ok()?;
+    let mut progress = get_progress_from_args(args);
+    progress.set_progress(&url.to_os_string(), 0);
+    tokio_block_on(hash_reader(reader, &mut progress)).ok()
+}
+
+///
+/// Gets location relative to which paths of manifest are recorded and resolved: destination
+/// directory, or directory which contains destination file
+///
+/// # Arguments
+///
+/// * `destination`: destination given by user
+/// * `is_directory`: whether destination is directory
+///
+/// returns: Location
+///
+pub fn manifest_base(destination: &Location, is_directory: bool) -> Location {
+    if is_directory {
+        return destination.clone();
+    }
+    let parent = Path::new(destination.path()).parent().unwrap_or(Path::new(""));
+    destination.with_path(parent.as_os_str())
+}
+
+/// Outcome of checking single file against manifest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileCheck {
+    /// Checksum matched
+    Ok,
+    /// Checksum did not match
+    Failed,
+    /// File could not be opened or read
+    Unreadable,
+}
+
+/// Result of checking files against manifest, in order of manifest
+#[derive(Debug, Default)]
+pub struct ManifestCheck {
+    pub files: Vec<(PathBuf, FileCheck)>,
+}
+
+impl ManifestCheck {
+    /// Counts files with given outcome
+    pub fn count(&self, outcome: FileCheck) -> usize {
+        self.files.iter().filter(|(_, check)| *check == outcome).count()
+    }
+
+    /// Whether all files matched their checksums
+    pub fn is_success(&self) -> bool {
+        self.files.iter().all(|(_, check)| *check == FileCheck::Ok)
+    }
+
+    ///
+    /// Renders result of each file followed by warnings, in the same way as `sha256sum -c` does
+    ///
+    pub fn render_text(&self) -> String {
+        let mut result = String::new();
+        for (path, check) in &self.files {
+            let outcome = match check {
+                FileCheck::Ok => "OK".green(),
+                FileCheck::Failed => "FAILED".red().bold(),
+                FileCheck::Unreadable => "FAILED open or read".red().bold(),
+            };
+            result.push_str(&format!("{}: {}\n", path.display(), outcome));
+        }
+        let unreadable = self.count(FileCheck::Unreadable);
+        if unreadable > 0 {
+            result.push_str(&format!("{}{}: {} listed files could not be read\n", "WARNING".yellow().bold(),
+                                     "".clear(), unreadable));
+        }
+        let failed = self.count(FileCheck::Failed);
+        if failed > 0 {
+            result.push_str(&format!("{}{}: {} computed checksums did NOT match\n", "WARNING".yellow().bold(),
+                                     "".clear(), failed));
+        }
+        result
+    }
+}
+
+///
+/// Verifies files against manifest
+///
+/// # Arguments
+///
+/// * `manifest_url`: URL of manifest
+/// * `base`: destination, paths from manifest are resolved relative to it or to its directory
+/// * `args`: program-wide arguments
+///
+/// returns: PcpResult<ManifestCheck>: result of each file, error if manifest can not be read
+///
+pub fn check_manifest(manifest_url: &Location, base: &Location, args: &Args) -> PcpResult<ManifestCheck> {
+    let manifest_reader = get_reader_proxy_for_url(manifest_url)
+        .ok_or_else(|| match manifest_url.local_path() {
+            Some(path) if !path.exists() => PcpError::NotFound(manifest_url.to_string()),
+            _ => PcpError::Unsupported(manifest_url.to_string(), String::from("No reader for URL")),
+        })?
+        .produce(manifest_url)?;
+    let content = tokio_block_on(read_to_end(manifest_reader))?;
+    let entries = Manifest::parse(&content).map_err(|error| PcpError::Protocol(manifest_url.to_string(), error))?;
+    let is_directory = get_reader_proxy_for_url(base).is_some_and(|proxy| proxy.is_directory(base));
+    let base = manifest_base(base, is_directory);
+    let mut check = ManifestCheck::default();
+    for entry in entries {
+        let url = if entry.path.is_absolute() {
+            Location::from_path(&entry.path)
+        } else {
+            base.join(&entry.path)
+        };
+        let outcome = match hash_url(&url, args) {
+            Some(digest) if digest == entry.digest => FileCheck::Ok,
+            Some(_) => FileCheck::Failed,
+            None => FileCheck::Unreadable,
+        };
+        check.files.push((entry.path, outcome));
+    }
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    const DIGEST: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_hex_digest_empty() {
+        assert_eq!(hex_digest(Sha256::new()), DIGEST);
+    }
+
+    #[test]
+    fn test_render_and_parse_roundtrip() {
+        let mut manifest = Manifest::new();
+        manifest.add_entry(Path::new("dist/file.txt"), DIGEST.to_string());
+        manifest.add_entry(Path::new("dist/back\\slash\nnewline"), DIGEST.to_string());
+        manifest.add_entry(Path::new(OsStr::from_bytes(b"latin1/caf\xe9")), DIGEST.to_string());
+        let rendered = manifest.render();
+        let lines: Vec<_> = rendered.split(|byte| *byte == b'\n').collect();
+        assert_eq!(lines[0], format!("{}  dist/file.txt", DIGEST).as_bytes());
+        assert!(lines[1].starts_with(b"\\"));
+        assert!(lines[2].ends_with(b"caf\xe9"));
+        assert_eq!(Manifest::parse(&rendered).unwrap(), manifest.entries);
+    }
+
+    #[test]
+    fn test_parse_binary_mode() {
+        let entries = Manifest::parse(format!("{} *file name.bin\r\n", DIGEST.to_uppercase()).as_bytes()).unwrap();
+        assert_eq!(entries, vec![ManifestEntry {
+            digest: DIGEST.to_string(),
+            path: PathBuf::from("file name.bin"),
+        }]);
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(Manifest::parse(b"not a checksum line").is_err());
+        assert!(Manifest::parse(format!("{} file", DIGEST).as_bytes()).is_err());
+        assert!(Manifest::parse(format!("{}  ", DIGEST).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_check_file_destination() {
+        let directory = std::env::temp_dir().join(format!("pcp-manifest-file-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("source"), b"hello").unwrap();
+        let options = Args {
+            no_progress: true,
+            manifest: Some(directory.join("SHA256SUMS").into_os_string()),
+            ..Args::default()
+        };
+        let report = crate::CopyJob::new(directory.join("copy"))
+            .source(directory.join("source"))
+            .options(options.clone())
+            .run();
+        assert!(report.is_success());
+        let check = check_manifest(&Location::from_path(directory.join("SHA256SUMS")),
+                                   &Location::from_path(directory.join("copy")), &options).unwrap();
+        assert!(check.is_success());
+        assert_eq!(check.files, [(PathBuf::from("copy"), FileCheck::Ok)]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_write_into_archive() {
+        let archive = std::env::temp_dir().join(format!("pcp-manifest-archive-{}.tar", std::process::id()));
+        crate::register_backends();
+        crate::utils::runtime::init_tokio();
+        let mut manifest = Manifest::new();
+        manifest.add_entry(Path::new("file"), DIGEST.to_string());
+        manifest.write_to(&Location::from_path(format!("{}//SHA256SUMS", archive.display()))).unwrap();
+
+        let mut entries = tar::Archive::new(std::fs::File::open(&archive).unwrap());
+        let mut entry = entries.entries().unwrap().next().unwrap().unwrap();
+        let mut content = Vec::new();
+        std::io::Read::read_to_end(&mut entry, &mut content).unwrap();
+        std::fs::remove_file(&archive).unwrap();
+        assert_eq!(content, manifest.render());
+    }
+}
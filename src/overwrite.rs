@@ -0,0 +1,172 @@
+use std::time::SystemTime;
+
+use colored::Colorize;
+
+use crate::arguments::{Args, UpdateMode};
+use crate::location::Location;
+use crate::progress::ProgressDisplay;
+use crate::utils::stat::ObjectStat;
+use crate::utils::term::confirm;
+
+/// Outcome of checking destination against overwrite policy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverwriteDecision {
+    /// Destination does not exist or may be replaced
+    Write,
+    /// Destination exists and must be kept
+    Skip,
+    /// Destination exists and user should be asked
+    Ask,
+}
+
+///
+/// Decides whether existing destination files may be replaced.
+/// Policy is the same for all writers since it only relies on `Writer::stat`.
+///
+#[derive(Debug, Clone)]
+pub struct OverwritePolicy {
+    update: UpdateMode,
+    no_clobber: bool,
+    interactive: bool,
+}
+
+impl OverwritePolicy {
+    pub fn from_args(args: &Args) -> OverwritePolicy {
+        OverwritePolicy {
+            update: args.update.unwrap_or(UpdateMode::All),
+            no_clobber: args.no_clobber,
+            interactive: args.interactive,
+        }
+    }
+
+    ///
+    /// Checks destination against policy without interacting with user
+    ///
+    /// # Arguments
+    ///
+    /// * `existing`: metadata of destination if it exists
+    /// * `source_modified`: modification time of source
+    ///
+    /// returns: OverwriteDecision
+    ///
+    pub fn decide(&self, existing: Option<&ObjectStat>,
+                  source_modified: Option<SystemTime>) -> OverwriteDecision {
+        let existing = match existing {
+            Some(existing) => existing,
+            None => return OverwriteDecision::Write,
+        };
+        if self.no_clobber {
+            return OverwriteDecision::Skip;
+        }
+        let replace = match self.update {
+            UpdateMode::All => true,
+            UpdateMode::None => false,
+            UpdateMode::Older => match (existing.modified, source_modified) {
+                (Some(destination), Some(source)) => destination < source,
+                // Can not compare, so prefer having fresh copy
+                _ => true,
+            },
+        };
+        if !replace {
+            OverwriteDecision::Skip
+        } else if self.interactive {
+            OverwriteDecision::Ask
+        } else {
+            OverwriteDecision::Write
+        }
+    }
+
+    ///
+    /// Checks whether destination may be written, prompting user if needed.
+    /// Progress line is cleaned before prompt so they do not mix.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination`: URL of destination
+    /// * `existing`: metadata of destination if it exists
+    /// * `source_modified`: modification time of source
+    /// * `progress`: progress display which may have an unfinished line
+    ///
+    /// returns: bool: whether destination should be written
+    ///
+    pub fn should_write(&self, destination: &Location, existing: Option<&ObjectStat>,
+                        source_modified: Option<SystemTime>,
+                        progress: &dyn ProgressDisplay) -> bool {
+        match self.decide(existing, source_modified) {
+            OverwriteDecision::Write => true,
+            OverwriteDecision::Skip => false,
+            OverwriteDecision::Ask => {
+                progress.flush();
+                confirm(&format!("{}{}: overwrite? [y/N]", destination.to_string().bold(), "".clear()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn policy(update: UpdateMode, no_clobber: bool, interactive: bool) -> OverwritePolicy {
+        OverwritePolicy {
+            update,
+            no_clobber,
+            interactive,
+        }
+    }
+
+    fn stat_modified_at(modified: SystemTime) -> ObjectStat {
+        ObjectStat {
+            size: 0,
+            modified: Some(modified),
+            is_directory: false,
+            permissions: None,
+        }
+    }
+
+    #[test]
+    fn test_missing_destination_is_always_written() {
+        let policy = policy(UpdateMode::None, true, true);
+        assert_eq!(policy.decide(None, None), OverwriteDecision::Write);
+    }
+
+    #[test]
+    fn test_no_clobber() {
+        let existing = stat_modified_at(SystemTime::now());
+        assert_eq!(policy(UpdateMode::All, true, false).decide(Some(&existing), None),
+                   OverwriteDecision::Skip);
+        assert_eq!(policy(UpdateMode::All, false, false).decide(Some(&existing), None),
+                   OverwriteDecision::Write);
+    }
+
+    #[test]
+    fn test_force_keeps_update_mode() {
+        // As with `cp -f`, force only cancels -n and -i, update mode still applies
+        let args = Args { force: true, update: Some(UpdateMode::None), ..Args::default() };
+        let existing = stat_modified_at(SystemTime::now());
+        assert_eq!(OverwritePolicy::from_args(&args).decide(Some(&existing), None), OverwriteDecision::Skip);
+    }
+
+    #[test]
+    fn test_update_older() {
+        let now = SystemTime::now();
+        let earlier = now - Duration::from_secs(60);
+        let policy = policy(UpdateMode::Older, false, false);
+        assert_eq!(policy.decide(Some(&stat_modified_at(earlier)), Some(now)), OverwriteDecision::Write);
+        assert_eq!(policy.decide(Some(&stat_modified_at(now)), Some(now)), OverwriteDecision::Skip);
+        assert_eq!(policy.decide(Some(&stat_modified_at(now)), Some(earlier)), OverwriteDecision::Skip);
+    }
+
+    #[test]
+    fn test_interactive_asks_only_when_update_allows() {
+        let now = SystemTime::now();
+        let earlier = now - Duration::from_secs(60);
+        assert_eq!(policy(UpdateMode::All, false, true).decide(Some(&stat_modified_at(now)), None),
+                   OverwriteDecision::Ask);
+        assert_eq!(policy(UpdateMode::Older, false, true)
+                       .decide(Some(&stat_modified_at(now)), Some(earlier)),
+                   OverwriteDecision::Skip);
+    }
+}
@@ -0,0 +1,528 @@
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use colored::Colorize;
+
+use crate::arguments::Args;
+use crate::backup::BackupPolicy;
+use crate::error::PcpError;
+use crate::factories::{get_reader_proxy_by_backend, ReaderProxy, WriterProxy};
+use crate::filter::{FilteredIterator, PathFilter};
+use crate::location::Location;
+use crate::manifest::hash_url;
+use crate::overwrite::{OverwriteDecision, OverwritePolicy};
+use crate::transform;
+use crate::utils::format_size;
+use crate::utils::stat::ObjectStat;
+
+/// What is going to happen with object during copy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanAction {
+    /// Directory is created in destination
+    MakeDirectory,
+    /// File is copied to a new destination
+    Create,
+    /// File replaces existing destination
+    Overwrite,
+    /// Object is left as is
+    Skip,
+    /// Destination object which is absent in source is removed
+    Delete,
+}
+
+/// Single planned operation
+#[derive(Debug, Clone)]
+pub struct PlanEntry {
+    pub action: PlanAction,
+    /// URL of source object, None for objects which exist only in destination
+    pub source: Option<Location>,
+    /// URL of destination object
+    pub destination: Location,
+    /// Path of object relative to copied directory, as it is named in destination
+    pub relative: PathBuf,
+    pub is_directory: bool,
+    pub source_stat: Option<ObjectStat>,
+    /// Existing object which is renamed with `--backup` before it is replaced, and its new name
+    pub backup: Option<(Location, Location)>,
+    /// Whether source is removed with `--move` once it is copied
+    pub removes_source: bool,
+}
+
+type PlanFn = dyn Fn(&Plan) + Send + Sync;
+
+/// Function which receives plan before it is carried out, or instead of that in dry run
+#[derive(Clone)]
+pub struct PlanCallback(pub Arc<PlanFn>);
+
+impl Debug for PlanCallback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PlanCallback")
+    }
+}
+
+///
+/// List of operations which copy is going to perform, in order of execution.
+/// Deletions go first and directory which receives copied objects is the first entry after them.
+///
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub entries: Vec<PlanEntry>,
+    /// Directories which could not be listed, so their contents are missing from plan
+    pub errors: Vec<PcpError>,
+}
+
+#[inline]
+fn seconds(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|duration| duration.as_secs())
+}
+
+///
+/// Checks whether destination already has the same contents as source, as far as sync is
+/// concerned: same size and modification time or, with `--checksum`, same SHA256
+///
+/// # Arguments
+///
+/// * `source`: URL of source file
+/// * `source_stat`: metadata of source file
+/// * `destination`: URL of destination file
+/// * `destination_stat`: metadata of destination file
+/// * `args`: program-wide arguments
+///
+/// returns: bool: whether copying may be skipped
+///
+pub fn is_up_to_date(source: &Location, source_stat: &ObjectStat, destination: &Location,
+                     destination_stat: &ObjectStat, args: &Args) -> bool {
+    if destination_stat.is_directory || source_stat.size != destination_stat.size {
+        return false;
+    }
+    if args.checksum {
+        let source_digest = hash_url(source, args);
+        return source_digest.is_some() && source_digest == hash_url(destination, args);
+    }
+    // Not all filesystems keep sub-second precision, so it is not compared
+    match (source_stat.modified.and_then(seconds), destination_stat.modified.and_then(seconds)) {
+        (Some(source), Some(destination)) => source == destination,
+        _ => false,
+    }
+}
+
+///
+/// Decides what happens with single source file, consulting sync mode and overwrite policy.
+/// Files which user would be asked about are planned to be overwritten.
+///
+fn file_action(source: &Location, source_stat: Option<&ObjectStat>, destination: &Location,
+               writer_proxy: &WriterProxy, policy: &OverwritePolicy, args: &Args) -> PlanAction {
+    let existing = match writer_proxy.stat(destination) {
+        Some(existing) => existing,
+        None => return PlanAction::Create,
+    };
+    if let (true, Some(stat)) = (args.sync, source_stat) {
+        if is_up_to_date(source, stat, destination, &existing, args) {
+            return PlanAction::Skip;
+        }
+    }
+    match policy.decide(Some(&existing), source_stat.and_then(|stat| stat.modified)) {
+        OverwriteDecision::Skip => PlanAction::Skip,
+        OverwriteDecision::Write | OverwriteDecision::Ask => PlanAction::Overwrite,
+    }
+}
+
+///
+/// Gets existing archive which holds destination and is replaced as a whole, along with
+/// whether overwrite policy keeps it
+///
+fn existing_container(destination: &Location, source_stat: Option<&ObjectStat>, writer_proxy: &WriterProxy,
+                      policy: &OverwritePolicy) -> Option<(Location, bool)> {
+    let container = writer_proxy.container(destination)?;
+    let existing = writer_proxy.stat(&container)?;
+    let kept = policy.decide(Some(&existing), source_stat.and_then(|stat| stat.modified)) == OverwriteDecision::Skip;
+    Some((container, kept))
+}
+
+///
+/// Names backup of object which is going to be replaced, None without `--backup` or if name
+/// can not be chosen
+///
+fn plan_backup(replaced: Option<Location>, writer_proxy: &WriterProxy,
+               backup: Option<&BackupPolicy>) -> Option<(Location, Location)> {
+    let replaced = replaced?;
+    let name = backup?.backup_location(&replaced, writer_proxy).ok()?;
+    Some((replaced, name))
+}
+
+impl Plan {
+    ///
+    /// Plans copy of single file
+    ///
+    /// # Arguments
+    ///
+    /// * `source`: URL of source file
+    /// * `destination`: URL of destination file
+    /// * `reader_proxy`: reader of source
+    /// * `writer_proxy`: writer of destination
+    /// * `args`: program-wide arguments
+    ///
+    /// returns: Plan
+    ///
+    pub fn for_file(source: &Location, destination: &Location, reader_proxy: &ReaderProxy,
+                    writer_proxy: &WriterProxy, args: &Args) -> Plan {
+        let policy = OverwritePolicy::from_args(args);
+        let source_stat = reader_proxy.stat(source);
+        let mut action = file_action(source, source_stat.as_ref(), destination, writer_proxy, &policy, args);
+        let mut replaced = (action == PlanAction::Overwrite).then(|| destination.clone());
+        match existing_container(destination, source_stat.as_ref(), writer_proxy, &policy) {
+            Some((_, true)) => {
+                action = PlanAction::Skip;
+                replaced = None;
+            }
+            Some((container, false)) => replaced = Some(container),
+            None => {}
+        }
+        Plan {
+            entries: vec![PlanEntry {
+                action,
+                source: Some(source.clone()),
+                destination: destination.clone(),
+                relative: PathBuf::from(reader_proxy.filename(source)),
+                is_directory: false,
+                source_stat,
+                backup: plan_backup(replaced, writer_proxy, BackupPolicy::from_args(args).as_ref()),
+                removes_source: args.move_sources && action != PlanAction::Skip,
+            }],
+            errors: Vec::new(),
+        }
+    }
+
+    ///
+    /// Plans recursive copy of directory
+    ///
+    /// # Arguments
+    ///
+    /// * `source`: URL of source directory
+    /// * `target_path`: URL of directory which receives contents of source
+    /// * `reader_proxy`: reader of source
+    /// * `writer_proxy`: writer of destination
+    /// * `filter`: filter of copied objects
+    /// * `args`: program-wide arguments
+    ///
+    /// returns: Plan
+    ///
+    pub fn for_directory(source: &Location, target_path: &Location, reader_proxy: &ReaderProxy,
+                         writer_proxy: &WriterProxy, filter: Option<&PathFilter>,
+                         args: &Args) -> Plan {
+        let policy = OverwritePolicy::from_args(args);
+        let backup = BackupPolicy::from_args(args);
+        let root_action = if writer_proxy.stat(target_path).is_some() {
+            PlanAction::Skip
+        } else {
+            PlanAction::MakeDirectory
+        };
+        let mut entries = vec![PlanEntry {
+            action: root_action,
+            source: Some(source.clone()),
+            destination: target_path.clone(),
+            relative: PathBuf::new(),
+            is_directory: true,
+            source_stat: reader_proxy.stat(source),
+            backup: None,
+            removes_source: false,
+        }];
+        let mut objects = reader_proxy.iter_directory(source);
+        if let Some(filter) = filter {
+            objects = Box::new(FilteredIterator::new(objects, filter.clone(),
+                                                     reader_proxy.clone(), source));
+        }
+        while let Some(object) = objects.internal_next() {
+            let is_directory = reader_proxy.is_directory(&object);
+            let mut relative = reader_proxy.relative_path(source, &object);
+            if let (false, Some(name)) = (is_directory, relative.file_name()) {
+                relative.set_file_name(transform::destination_name(name, args));
+            }
+            let destination = writer_proxy.join_path(target_path, &relative);
+            let source_stat = reader_proxy.stat(&object);
+            let action = if is_directory {
+                match writer_proxy.stat(&destination) {
+                    Some(_) => PlanAction::Skip,
+                    None => PlanAction::MakeDirectory,
+                }
+            } else {
+                file_action(&object, source_stat.as_ref(), &destination, writer_proxy, &policy, args)
+            };
+            let replaced = (action == PlanAction::Overwrite).then(|| destination.clone());
+            entries.push(PlanEntry {
+                action,
+                source: Some(object),
+                destination,
+                relative,
+                is_directory,
+                source_stat,
+                backup: plan_backup(replaced, writer_proxy, backup.as_ref()),
+                removes_source: false,
+            });
+        }
+        // Existing archive is replaced as a whole, so it is backed up instead of its members and
+        // everything is kept when policy keeps it
+        match existing_container(target_path, entries[0].source_stat.as_ref(), writer_proxy, &policy) {
+            Some((_, true)) => entries.iter_mut().for_each(|entry| {
+                entry.action = PlanAction::Skip;
+                entry.backup = None;
+            }),
+            Some((container, false)) => {
+                entries.iter_mut().for_each(|entry| entry.backup = None);
+                entries[0].backup = plan_backup(Some(container), writer_proxy, backup.as_ref());
+            }
+            None => {}
+        }
+        if args.move_sources {
+            // Skipped files stay in source, and so do directories which hold them
+            let kept: Vec<PathBuf> = entries.iter()
+                .filter(|entry| !entry.is_directory && entry.action == PlanAction::Skip)
+                .map(|entry| entry.relative.clone())
+                .collect();
+            for entry in entries.iter_mut() {
+                entry.removes_source = if entry.is_directory {
+                    !kept.iter().any(|file| file.starts_with(&entry.relative))
+                } else {
+                    entry.action != PlanAction::Skip
+                };
+            }
+        }
+        let mut plan = Plan {
+            entries,
+            errors: objects.take_errors(),
+        };
+        // Objects of directories which could not be listed would look extraneous, so like
+        // rsync nothing is deleted after listing errors
+        if args.sync && args.delete && !plan.errors.is_empty() {
+            plan.errors.push(PcpError::other(target_path, "IO error encountered, skipping file deletion"));
+        } else if args.sync && args.delete {
+            plan.add_deletions(target_path, writer_proxy, filter, args);
+        }
+        plan
+    }
+
+    ///
+    /// Plans removal of destination objects which are absent in source. Destination is listed
+    /// with reader registered for its URL or under the key of forced destination backend,
+    /// without such reader nothing can be deleted and error is planned. Excluded objects are
+    /// protected from deletion.
+    ///
+    fn add_deletions(&mut self, target_path: &Location, writer_proxy: &WriterProxy,
+                     filter: Option<&PathFilter>, args: &Args) {
+        if !writer_proxy.stat(target_path).is_some_and(|stat| stat.is_directory) {
+            return;
+        }
+        let destination_proxy = match get_reader_proxy_by_backend(target_path, args.dst_backend.as_deref()) {
+            Some(proxy) => proxy,
+            None => {
+                self.errors.push(PcpError::Unsupported(target_path.to_string(),
+                                                       String::from("Destination can not be listed to delete files")));
+                return;
+            }
+        };
+        let known: HashSet<&Path> = self.entries.iter().map(|entry| entry.relative.as_path()).collect();
+        let mut objects = destination_proxy.iter_directory(target_path);
+        if let Some(filter) = filter {
+            objects = Box::new(FilteredIterator::new(objects, filter.clone(),
+                                                     destination_proxy.clone(), target_path));
+        }
+        let mut deletions = Vec::new();
+        while let Some(object) = objects.internal_next() {
+            let relative = destination_proxy.relative_path(target_path, &object);
+            if known.contains(relative.as_path()) {
+                continue;
+            }
+            deletions.push(PlanEntry {
+                action: PlanAction::Delete,
+                source: None,
+                is_directory: destination_proxy.is_directory(&object),
+                destination: object,
+                relative,
+                source_stat: None,
+                backup: None,
+                removes_source: false,
+            });
+        }
+        self.errors.append(&mut objects.take_errors());
+        // Contents of directories have to be removed before directories themselves
+        deletions.reverse();
+        deletions.append(&mut self.entries);
+        self.entries = deletions;
+    }
+
+    ///
+    /// Appends entries of another plan, so that plans of several sources are reported together
+    ///
+    pub fn append(&mut self, other: Plan) {
+        self.entries.extend(other.entries);
+        self.errors.extend(other.errors);
+    }
+
+    ///
+    /// Counts entries with given action
+    ///
+    pub fn count(&self, action: PlanAction) -> usize {
+        self.entries.iter().filter(|entry| entry.action == action).count()
+    }
+
+    ///
+    /// Gets amount of bytes which are going to be copied
+    ///
+    pub fn bytes_to_copy(&self) -> usize {
+        self.entries.iter()
+            .filter(|entry| matches!(entry.action, PlanAction::Create | PlanAction::Overwrite))
+            .filter_map(|entry| entry.source_stat.as_ref())
+            .map(|stat| stat.size)
+            .sum()
+    }
+
+    /// Passes plan to plan callback of job, if there is one
+    pub(crate) fn notify(&self, args: &Args) {
+        if let Some(callback) = &args.plan_callback {
+            (callback.0)(self);
+        }
+    }
+
+    ///
+    /// Renders planned operations, one per line, followed by their totals. Backups are listed
+    /// before objects which replace them and removals of moved files after them. Moved
+    /// directories are removed last, once they are empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `show_skipped`: whether files which are left as is are listed
+    ///
+    pub fn render_text(&self, show_skipped: bool) -> String {
+        let mut result = String::new();
+        for entry in &self.entries {
+            if let Some((replaced, name)) = &entry.backup {
+                result.push_str(&format!("{:>9}{} {} -> {}\n", "backup".cyan(), "".clear(), replaced, name));
+            }
+            let size = entry.source_stat.as_ref().map(|stat| stat.size).unwrap_or(0);
+            let line = match entry.action {
+                PlanAction::MakeDirectory => Some(("mkdir".blue(), String::new())),
+                PlanAction::Create => Some(("create".green(), format!(" ({})", format_size(size)))),
+                PlanAction::Overwrite => Some(("overwrite".yellow(), format!(" ({})", format_size(size)))),
+                PlanAction::Skip if show_skipped && !entry.is_directory => Some(("skip".dimmed(), String::new())),
+                PlanAction::Skip => None,
+                PlanAction::Delete => Some(("delete".red(), String::new())),
+            };
+            if let Some((label, details)) = line {
+                result.push_str(&format!("{:>9}{} {}{}\n", label, "".clear(), entry.destination, details));
+            }
+            if let (true, false, Some(source)) = (entry.removes_source, entry.is_directory, &entry.source) {
+                result.push_str(&format!("{:>9}{} {}\n", "remove".red(), "".clear(), source));
+            }
+        }
+        let directories = self.entries.iter().rev().filter(|entry| entry.removes_source && entry.is_directory);
+        for source in directories.filter_map(|entry| entry.source.as_ref()) {
+            result.push_str(&format!("{:>9}{} {}\n", "remove".red(), "".clear(), source));
+        }
+        let backups = self.entries.iter().filter(|entry| entry.backup.is_some()).count();
+        let removals = self.entries.iter().filter(|entry| entry.removes_source).count();
+        let mut side_effects = String::new();
+        if backups > 0 {
+            side_effects.push_str(&format!(", {} to back up", backups));
+        }
+        if removals > 0 {
+            side_effects.push_str(&format!(", {} sources to remove", removals));
+        }
+        result.push_str(&format!("{}{}: {} to create, {} to overwrite, {} to skip, {} to delete, {} directories to make, {} to copy{}\n",
+                                 "Plan".bold(), "".clear(),
+                                 self.count(PlanAction::Create), self.count(PlanAction::Overwrite),
+                                 self.entries.iter().filter(|entry| entry.action == PlanAction::Skip && !entry.is_directory).count(),
+                                 self.count(PlanAction::Delete), self.count(PlanAction::MakeDirectory),
+                                 format_size(self.bytes_to_copy()), side_effects));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(action: PlanAction, size: usize, is_directory: bool) -> PlanEntry {
+        PlanEntry {
+            action,
+            source: None,
+            destination: Location::from_path(""),
+            relative: PathBuf::new(),
+            is_directory,
+            source_stat: Some(ObjectStat {
+                size,
+                modified: None,
+                is_directory,
+                permissions: None,
+            }),
+            backup: None,
+            removes_source: false,
+        }
+    }
+
+    #[test]
+    fn test_totals() {
+        let mut plan = Plan {
+            entries: vec![entry(PlanAction::MakeDirectory, 4096, true),
+                          entry(PlanAction::Create, 10, false)],
+            ..Plan::default()
+        };
+        plan.append(Plan {
+            entries: vec![entry(PlanAction::Overwrite, 5, false), entry(PlanAction::Skip, 100, false)],
+            ..Plan::default()
+        });
+        assert_eq!(plan.count(PlanAction::Create), 1);
+        assert_eq!(plan.count(PlanAction::Skip), 1);
+        assert_eq!(plan.bytes_to_copy(), 15);
+    }
+
+    #[test]
+    fn test_move_with_backup() {
+        let directory = std::env::temp_dir().join(format!("pcp-plan-move-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("source")).unwrap();
+        std::fs::create_dir_all(directory.join("destination/source")).unwrap();
+        std::fs::write(directory.join("source/new"), b"new").unwrap();
+        std::fs::write(directory.join("source/old"), b"old").unwrap();
+        std::fs::write(directory.join("destination/source/old"), b"older").unwrap();
+        let args = Args {
+            recursive: true,
+            move_sources: true,
+            backup: Some(Some(crate::arguments::BackupMode::Simple)),
+            ..Args::default()
+        };
+        let plan = crate::CopyJob::new(directory.join("destination"))
+            .source(directory.join("source"))
+            .options(args)
+            .plan()
+            .unwrap();
+        let text = plan.render_text(false);
+        std::fs::remove_dir_all(&directory).unwrap();
+        let old = plan.entries.iter().find(|entry| entry.relative == Path::new("old")).unwrap();
+        let (replaced, name) = old.backup.as_ref().unwrap();
+        assert_eq!(replaced.path(), directory.join("destination/source/old").as_os_str());
+        assert_eq!(name.path(), directory.join("destination/source/old~").as_os_str());
+        assert!(plan.entries.iter().all(|entry| entry.removes_source));
+        assert!(text.contains(&format!("{}/destination/source/old~", directory.display())));
+        assert!(text.contains(&format!("{}/source/new", directory.display())));
+        assert!(text.contains("1 to back up, 3 sources to remove"));
+    }
+
+    #[cfg(feature = "block")]
+    #[test]
+    fn test_delete_without_destination_reader() {
+        let directory = std::env::temp_dir().join(format!("pcp-plan-delete-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("source")).unwrap();
+        std::fs::create_dir_all(directory.join("destination")).unwrap();
+        // Block devices have only writer
+        let args = Args { recursive: true, sync: true, delete: true, ..Args::default() };
+        let plan = crate::CopyJob::new(format!("block://{}", directory.join("destination").display()))
+            .source(format!("{}/", directory.join("source").display()))
+            .options(args)
+            .plan()
+            .unwrap();
+        assert!(matches!(plan.errors[..], [PcpError::Unsupported(..)]));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//!
+//! Loading of third-party backends from shared libraries.
+//!
+//! Plugin is a `cdylib` crate which depends on `pcp` and declares itself with
+//! [`declare_plugin!`](crate::declare_plugin):
+//!
+//! ```ignore
+//! use pcp::plugin::PluginRegistrar;
+//!
+//! fn register(registrar: &mut PluginRegistrar) {
+//!     registrar.add_reader::<MyReader>("mystore", 10);
+//!     registrar.add_writer::<MyWriter>("mystore", 10);
+//! }
+//!
+//! pcp::declare_plugin!("mystore", register);
+//! ```
+//!
+//! Rust has no stable ABI, so plugin is accepted only if it was built with the same plugin ABI
+//! version, `pcp` version and compiler as the program itself. Factories of plugin are separate
+//! from those of the program, so backends are registered only via `PluginRegistrar`.
+//!
+
+use std::ffi::{c_char, CStr, OsStr};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+
+use lazy_static::lazy_static;
+use libloading::{Library, Symbol};
+
+use crate::factories::{ProgressConstructor, ReaderProxy, WriterProxy, PROGRESS_FACTORY, READER_FACTORY, WRITER_FACTORY};
+use crate::progress::ProgressDisplay;
+use crate::reader::Reader;
+use crate::writer::Writer;
+
+/// Version of plugin interface, changed whenever `PluginDeclaration` or `PluginRegistrar` change
+pub const PLUGIN_ABI_VERSION: u32 = 2;
+/// Version of `pcp` as nul-terminated string
+pub const PCP_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+/// Version of compiler as nul-terminated string
+pub const RUSTC_VERSION: &str = concat!(env!("PCP_RUSTC_VERSION"), "\0");
+
+/// Environment variable with list of plugin directories, separated like `PATH`
+const PLUGIN_PATH_VARIABLE: &str = "PCP_PLUGIN_PATH";
+/// Directory of plugins relative to home directory, used when variable is not set
+const USER_PLUGIN_DIRECTORY: &str = ".local/lib/pcp/plugins";
+/// Name of static which every plugin exports
+const DECLARATION_SYMBOL: &[u8] = b"PCP_PLUGIN_DECLARATION\0";
+
+///
+/// Description of plugin exported by shared library. Layout is fixed, so that versions can be
+/// checked before anything else from library is touched.
+///
+#[repr(C)]
+pub struct PluginDeclaration {
+    pub abi_version: u32,
+    pub pcp_version: *const c_char,
+    pub rustc_version: *const c_char,
+    pub name: *const c_char,
+    pub register: fn(&mut PluginRegistrar),
+}
+
+// Declaration only points to static strings of library
+unsafe impl Sync for PluginDeclaration {}
+
+///
+/// Exports plugin declaration from `cdylib` crate
+///
+/// # Arguments
+///
+/// * `name`: name of plugin, string literal
+/// * `register`: function which receives `&mut PluginRegistrar`
+///
+#[macro_export]
+macro_rules! declare_plugin {
+    ($name:literal, $register:path) => {
+        #[no_mangle]
+        pub static PCP_PLUGIN_DECLARATION: $crate::plugin::PluginDeclaration = $crate::plugin::PluginDeclaration {
+            abi_version: $crate::plugin::PLUGIN_ABI_VERSION,
+            pcp_version: $crate::plugin::PCP_VERSION.as_ptr() as *const ::std::ffi::c_char,
+            rustc_version: $crate::plugin::RUSTC_VERSION.as_ptr() as *const ::std::ffi::c_char,
+            name: concat!($name, "\0").as_ptr() as *const ::std::ffi::c_char,
+            register: $register,
+        };
+    };
+}
+
+///
+/// Collects backends of plugin, which are added to factories of the program afterwards
+///
+#[derive(Default)]
+pub struct PluginRegistrar {
+    readers: Vec<(String, i32, ReaderProxy)>,
+    writers: Vec<(String, i32, WriterProxy)>,
+    progress_displays: Vec<(String, ProgressConstructor)>,
+}
+
+impl PluginRegistrar {
+    ///
+    /// Registers reader
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: name of backend and URL scheme it handles
+    /// * `priority`: order of probing URLs without known scheme, higher goes first
+    ///
+    pub fn add_reader<T: Reader + 'static>(&mut self, key: &str, priority: i32) {
+        self.readers.push((key.to_string(), priority, ReaderProxy::from_type::<T>()));
+    }
+
+    ///
+    /// Registers writer
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: name of backend and URL scheme it handles
+    /// * `priority`: order of probing URLs without known scheme, higher goes first
+    ///
+    pub fn add_writer<T: Writer + 'static>(&mut self, key: &str, priority: i32) {
+        self.writers.push((key.to_string(), priority, WriterProxy::from_type::<T>()));
+    }
+
+    ///
+    /// Registers progress display, which is selected with `--progress`
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: name of display
+    ///
+    pub fn add_progress<T: ProgressDisplay + 'static>(&mut self, key: &str) {
+        self.progress_displays.push((key.to_string(), || Box::new(T::new())));
+    }
+
+    /// Adds collected backends to factories of the program
+    fn install(self) {
+        let mut readers = READER_FACTORY.lock().unwrap();
+        for (key, priority, proxy) in self.readers {
+            readers.add_reader_proxy(&key, priority, proxy);
+        }
+        let mut writers = WRITER_FACTORY.lock().unwrap();
+        for (key, priority, proxy) in self.writers {
+            writers.add_writer_proxy(&key, priority, proxy);
+        }
+        let mut progress_displays = PROGRESS_FACTORY.lock().unwrap();
+        for (key, constructor) in self.progress_displays {
+            progress_displays.add_progress_constructor(&key, constructor);
+        }
+    }
+}
+
+/// Plugin which was loaded successfully
+#[derive(Debug, Clone)]
+pub struct LoadedPlugin {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+lazy_static! {
+    static ref LOADED_PLUGINS: Mutex<Vec<LoadedPlugin>> = Mutex::new(Vec::new());
+    /// Libraries are never unloaded, since factories keep pointers to their functions
+    static ref LIBRARIES: Mutex<Vec<Library>> = Mutex::new(Vec::new());
+}
+
+///
+/// Gets directories which are searched for plugins: those from `$PCP_PLUGIN_PATH` if it is set,
+/// otherwise `~/.local/lib/pcp/plugins`
+///
+pub fn plugin_directories() -> Vec<PathBuf> {
+    match std::env::var_os(PLUGIN_PATH_VARIABLE) {
+        Some(paths) => std::env::split_paths(&paths).filter(|path| !path.as_os_str().is_empty()).collect(),
+        None => std::env::var_os("HOME")
+            .map(|home| vec![Path::new(&home).join(USER_PLUGIN_DIRECTORY)])
+            .unwrap_or_default(),
+    }
+}
+
+///
+/// Reads C string of plugin declaration
+///
+/// # Safety
+///
+/// Pointer must be null or point to nul-terminated string
+///
+unsafe fn declaration_string(pointer: *const c_char) -> String {
+    if pointer.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(pointer).to_string_lossy().into_owned()
+}
+
+///
+/// Loads single plugin and registers its backends
+///
+/// # Arguments
+///
+/// * `path`: path of shared library
+///
+/// returns: Result<String, String>: name of plugin or description of the reason it was rejected
+///
+pub fn load_plugin(path: &Path) -> Result<String, String> {
+    // Libraries run their initializers when loaded, trusting them is the point of plugin directory
+    let library = unsafe { Library::new(path) }.map_err(|error| match std::error::Error::source(&error) {
+        Some(source) => format!("{}: {}", error, source),
+        None => error.to_string(),
+    })?;
+    let (name, register) = unsafe {
+        let symbol: Symbol<*const PluginDeclaration> = library.get(DECLARATION_SYMBOL)
+            .map_err(|_| String::from("Not a pcp plugin"))?;
+        let declaration = &**symbol;
+        if declaration.abi_version != PLUGIN_ABI_VERSION {
+            return Err(format!("Plugin ABI version is {}, expected {}", declaration.abi_version,
+                               PLUGIN_ABI_VERSION));
+        }
+        for (actual, expected, what) in [(declaration.pcp_version, PCP_VERSION, "pcp"),
+                                         (declaration.rustc_version, RUSTC_VERSION, "compiler")] {
+            let actual = declaration_string(actual);
+            let expected = expected.trim_end_matches('\0');
+            if actual != expected {
+                return Err(format!("Built for {} {}, expected {}", what, actual, expected));
+            }
+        }
+        (declaration_string(declaration.name), declaration.register)
+    };
+    let mut registrar = PluginRegistrar::default();
+    register(&mut registrar);
+    registrar.install();
+    LIBRARIES.lock().unwrap().push(library);
+    LOADED_PLUGINS.lock().unwrap().push(LoadedPlugin {
+        name: name.clone(),
+        path: path.to_path_buf(),
+    });
+    Ok(name)
+}
+
+///
+/// Loads all plugins from plugin directories once. Plugins are loaded in order of their file names
+/// and may replace built-in backends.
+///
+/// returns: Vec<(PathBuf, String)>: plugins which could not be loaded and reasons, always empty
+/// after the first call
+///
+pub fn load_plugins() -> Vec<(PathBuf, String)> {
+    static LOAD: Once = Once::new();
+    let mut failures = Vec::new();
+    LOAD.call_once(|| {
+        for directory in plugin_directories() {
+            let mut paths: Vec<_> = match std::fs::read_dir(&directory) {
+                Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path())
+                    .filter(|path| path.extension() == Some(OsStr::new(std::env::consts::DLL_EXTENSION)))
+                    .collect(),
+                Err(_) => continue,
+            };
+            paths.sort();
+            for path in paths {
+                if let Err(error) = load_plugin(&path) {
+                    failures.push((path, error));
+                }
+            }
+        }
+    });
+    failures
+}
+
+/// Gets plugins which were loaded
+pub fn loaded_plugins() -> Vec<LoadedPlugin> {
+    LOADED_PLUGINS.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_non_plugins() {
+        let path = std::env::temp_dir().join(format!("pcp-not-a-plugin-{}.so", std::process::id()));
+        std::fs::write(&path, b"not a library").unwrap();
+        let result = load_plugin(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+        assert!(loaded_plugins().is_empty());
+    }
+
+    #[test]
+    fn test_versions_are_terminated() {
+        assert!(PCP_VERSION.ends_with('\0') && RUSTC_VERSION.ends_with('\0'));
+        assert!(RUSTC_VERSION.starts_with("rustc"));
+    }
+}
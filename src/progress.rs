@@ -1,12 +1,21 @@
+#[cfg(feature = "tui")]
 pub mod console;
 pub mod dummy;
+pub mod callback;
 
+use std::ffi::OsStr;
+
+///
+/// Shows progress of copying single file. Status is usually made of source and destination paths,
+/// which may be not valid UTF-8, so displays convert it only when printing.
+///
 pub trait ProgressDisplay {
      fn new() -> Self where Self: Sized;
-     fn set_progress(&mut self, status: &str, bytes_out: usize);
+     fn set_progress(&mut self, status: &OsStr, bytes_out: usize);
      #[allow(dead_code)]
-     fn update_status(&mut self, new_status: &str);
+     fn update_status(&mut self, new_status: &OsStr);
      fn add_bytes_written(&mut self, bytes_written: usize);
      fn set_size(&mut self, bytes_total: usize);
      fn flush(&self);
- }
\ No newline at end of file
+ }
+
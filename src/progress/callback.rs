@@ -0,0 +1,76 @@
+use std::ffi::OsStr;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use crate::progress::ProgressDisplay;
+
+type ProgressFn = dyn Fn(&str, usize, usize) + Send + Sync;
+
+///
+/// Function which receives progress of copying: status line, bytes processed and total size
+/// of current file. Names which are not valid UTF-8 are replaced in status line.
+///
+#[derive(Clone)]
+pub struct ProgressCallback(pub Arc<ProgressFn>);
+
+impl Debug for ProgressCallback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// Reports progress to user-supplied function instead of terminal
+pub struct CallbackProgress{
+    callback: ProgressCallback,
+    bytes_total: usize,
+    bytes_out: usize,
+    status: String,
+}
+
+impl CallbackProgress {
+    pub fn with_callback(callback: ProgressCallback) -> CallbackProgress{
+        CallbackProgress{
+            callback,
+            bytes_total: 0,
+            bytes_out: 0,
+            status: String::new(),
+        }
+    }
+
+    #[inline]
+    fn report(&self){
+        (self.callback.0)(&self.status, self.bytes_out, self.bytes_total);
+    }
+}
+
+impl ProgressDisplay for CallbackProgress {
+    fn new() -> Self where Self: Sized {
+        CallbackProgress::with_callback(ProgressCallback(Arc::new(|_, _, _| {})))
+    }
+
+    fn set_progress(&mut self, status: &OsStr, bytes_out: usize) {
+        self.status = status.to_string_lossy().into_owned();
+        self.bytes_out = bytes_out;
+        self.report();
+    }
+
+    fn update_status(&mut self, new_status: &OsStr) {
+        self.status = new_status.to_string_lossy().into_owned();
+        self.report();
+    }
+
+    fn add_bytes_written(&mut self, bytes_written: usize) {
+        self.bytes_out += bytes_written;
+        self.report();
+    }
+
+    fn set_size(&mut self, bytes_total: usize) {
+        self.bytes_total = bytes_total;
+        self.report();
+    }
+
+    #[inline(always)]
+    fn flush(&self) {
+        /*stub*/
+    }
+}
@@ -1,4 +1,6 @@
 use std::cmp::min;
+use std::ffi::OsStr;
+
 use colored::Colorize;
 use termion::terminal_size;
 
@@ -16,6 +18,8 @@ pub struct ConsoleProgress{
     last_precise_update: u128,
 }
 
+crate::register_progress!(ConsoleProgress, "console");
+
 fn pad_status(status: String, max_width: u16) -> String {
     let status_len = status.len();
     let max_width = max_width as usize;
@@ -77,14 +81,15 @@ impl ProgressDisplay for ConsoleProgress {
             last_precise_update: 0,
         }
     }
-    fn set_progress(&mut self, status: &str, bytes_out: usize) {
-        self.status = String::from(status);
+    fn set_progress(&mut self, status: &OsStr, bytes_out: usize) {
+        // Terminal is the only place where names have to be valid UTF-8
+        self.status = status.to_string_lossy().into_owned();
         self.bytes_out = bytes_out;
         self.print_progress();
     }
 
-    fn update_status(&mut self, new_status: &str) {
-        self.status = String::from(new_status);
+    fn update_status(&mut self, new_status: &OsStr) {
+        self.status = new_status.to_string_lossy().into_owned();
         self.print_progress();
     }
 
@@ -1,20 +1,24 @@
+use std::ffi::OsStr;
+
 use crate::progress::ProgressDisplay;
 
 /// Progress which is never displayed in any way
 pub struct DummyProgress{}
 
+crate::register_progress!(DummyProgress, "none");
+
 impl ProgressDisplay for DummyProgress{
     fn new() -> Self where Self: Sized {
         DummyProgress{}
     }
 
     #[inline(always)]
-    fn set_progress(&mut self, _status: &str, _bytes_out: usize) {
+    fn set_progress(&mut self, _status: &OsStr, _bytes_out: usize) {
         /*stub*/
     }
 
     #[inline(always)]
-    fn update_status(&mut self, _new_status: &str) {
+    fn update_status(&mut self, _new_status: &OsStr) {
         /*stub*/
     }
 
@@ -1,9 +1,19 @@
 pub mod file;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "archive")]
+pub mod zip;
+
+use std::ffi::OsString;
+use std::path::PathBuf;
 
 use async_trait::async_trait;
-use crate::factories::READER_FACTORY;
+use crate::copy::{Buffer, DynBuffer};
+use crate::error::PcpResult;
+use crate::location::Location;
 
 use crate::utils::generic_iterator::GenericIterator;
+use crate::utils::stat::ObjectStat;
 
 #[async_trait]
 pub trait Reader{
@@ -18,12 +28,12 @@ pub trait Reader{
     /// 
     /// # Examples 
     /// 
-    /// ```
-    /// if !MyReader::can_read("scheme://path/to/file"){
+    /// ```ignore
+    /// if !MyReader::can_read(&Location::parse("scheme://path/to/file")){
     ///     // Use another reader
     /// }
     /// ```
-    fn can_read(url: &str) -> bool where Self: Sized;
+    fn can_read(url: &Location) -> bool where Self: Sized;
     
     /// 
     /// Creates new instance of reader from given URL
@@ -31,9 +41,9 @@ pub trait Reader{
     /// 
     /// * `url`: URL of file to read
     /// 
-    /// returns: Self 
+    /// returns: PcpResult<Self>: reader or error if file can not be opened
     ///
-    fn new(url: &str) -> Self where Self: Sized;
+    fn new(url: &Location) -> PcpResult<Self> where Self: Sized;
     
     /// 
     /// Checks that given URL is directory
@@ -45,22 +55,22 @@ pub trait Reader{
     /// 
     /// # Examples 
     /// 
+    /// ```ignore
+    ///  assert!(!MyReader::is_directory(&Location::parse("scheme://path/to/some/file")));
     /// ```
-    ///  assert!(!MyReader::is_directory("scheme://path/to/some/file"));
-    /// ```
-    fn is_directory(url: &str) -> bool where Self: Sized;
+    fn is_directory(url: &Location) -> bool where Self: Sized;
     
     ///
     /// Returns size of file
     ///
-    /// returns: Self 
+    /// returns: PcpResult<usize>: size or error if it can not be determined
     ///
     /// # Examples 
     ///
+    /// ```ignore
+    ///  let file_size = MyReader::new("scheme://path/to/file")?.get_size()?;
     /// ```
-    ///  let file_size = MyReader::new("scheme://path/to/file").get_blocksize();
-    /// ```
-    fn get_size(&self) -> usize;
+    fn get_size(&self) -> PcpResult<usize>;
 
     ///
     /// Gets blocksize for filesystem in which source file is stored
@@ -70,10 +80,42 @@ pub trait Reader{
     ///
     /// # Examples 
     ///
-    /// ```
-    ///  let io_block_size = MyReader::new("scheme://path/to/file").get_blocksize();
+    /// ```ignore
+    ///  let io_block_size = MyReader::new("scheme://path/to/file")?.get_blocksize();
     /// ```
     fn get_blocksize(&self) -> usize;
+
+    ///
+    /// Gets metadata of source file/directory
+    /// # Arguments
+    ///
+    /// * `url`: URL of object
+    ///
+    /// returns: Option<ObjectStat>: metadata if object exists
+    ///
+    fn stat(url: &Location) -> Option<ObjectStat> where Self: Sized;
+
+    ///
+    /// Atomically renames source to target when both are handled by this backend
+    /// # Arguments
+    ///
+    /// * `from`: URL of source object
+    /// * `to`: URL which object should get
+    ///
+    /// returns: std::io::Result<()>: error if rename is not possible, e.g. target is on
+    /// another filesystem or is handled by another backend
+    ///
+    fn rename(from: &Location, to: &Location) -> std::io::Result<()> where Self: Sized;
+
+    ///
+    /// Removes source file or empty directory
+    /// # Arguments
+    ///
+    /// * `url`: URL of object to remove
+    ///
+    /// returns: std::io::Result<()>
+    ///
+    fn remove(url: &Location) -> std::io::Result<()> where Self: Sized;
     
     ///
     /// Creates directory iterator
@@ -81,32 +123,34 @@ pub trait Reader{
     /// # Arguments
     /// * `url`: url to directory
     /// 
-    fn iter_directory(url: &str) -> Box<dyn GenericIterator<String>> where Self: Sized;
+    fn iter_directory(url: &Location) -> Box<dyn GenericIterator<Location>> where Self: Sized;
 
     ///
     /// Gets a relative path from URL given argument
     ///
     /// # Arguments
     ///
-    /// * `src_arg`: str containing source directory
-    /// * `url`: str containing url from iter_directory
+    /// * `src_arg`: location of source directory
+    /// * `url`: location from iter_directory
     ///
     /// # Examples
-    /// ```
-    /// let src_arg = "/tmp/foo";
-    /// let url = "/tmp/foo/bar/file";
+    /// ```ignore
+    /// let src_arg = Location::parse("/tmp/foo");
+    /// let url = Location::parse("/tmp/foo/bar/file");
     ///
-    /// assert_eq!(MyReader::relative_path2(src_arg, url), "foo/bar/file");
+    /// assert_eq!(MyReader::relative_path(&src_arg, &url), PathBuf::from("bar/file"));
     ///```
     ///
-    /// ```
-    /// let src_arg = "ftp://my-server/some-dir/foo";
-    /// let url = "ftp://my-server/some-dir/foo/bar/file";
+    /// ```ignore
+    /// let src_arg = Location::parse("ftp://my-server/some-dir/foo");
+    /// let url = Location::parse("ftp://my-server/some-dir/foo/bar/file");
     ///
-    /// assert_eq!(MyFTPReader::relative_path2(src_arg, url), "foo/bar/file");
+    /// assert_eq!(MyFTPReader::relative_path(&src_arg, &url), PathBuf::from("bar/file"));
     ///```
     ///
-    fn relative_path(src_arg: &str, url: &str) -> String where Self: Sized;
+    /// Names are not required to be valid UTF-8, so they are kept as `PathBuf`
+    ///
+    fn relative_path(src_arg: &Location, url: &Location) -> PathBuf where Self: Sized;
     
     ///
     /// Gets dirname from URL of directory
@@ -115,7 +159,7 @@ pub trait Reader{
     /// 
     /// * `url` url to extract dirname from
     /// 
-    fn dirname(url: &str) -> String where Self: Sized;
+    fn dirname(url: &Location) -> OsString where Self: Sized;
 
     ///
     /// Gets filename from URL 
@@ -124,7 +168,7 @@ pub trait Reader{
     ///
     /// * `url` url to extract filename from
     ///
-    fn filename(url: &str) -> String where Self: Sized;
+    fn filename(url: &Location) -> OsString where Self: Sized;
     
     ///
     /// Reads chunk from file. Chunk is limited by given maximum size
@@ -133,24 +177,55 @@ pub trait Reader{
     /// * `buffer`: Mutable reference to buffer where data would be put
     /// * `max_size`: Maximum amount of bytes to read
     ///
-    /// returns: usize: amount of bytes read
+    /// returns: PcpResult<usize>: amount of bytes read, 0 at the end of file
     ///
     /// # Examples 
     ///
-    /// ```
-    /// let reader = MyReader::new("scheme://path/to/file");
+    /// ```ignore
+    /// let reader = MyReader::new("scheme://path/to/file")?;
     /// let buffer = Vec::<u8>::new();
     /// for _ in 0..128 {
     ///     buffer.push(0)
     /// }
-    /// let bytes_read = reader.read_chunk(&mut buffer, 128).await;
+    /// let bytes_read = reader.read_chunk(&mut buffer, 128).await?;
     /// ```
-    async fn read_chunk(&mut self, buffer: &mut [u8], max_size: usize) -> usize;
+    async fn read_chunk(&mut self, buffer: &mut [u8], max_size: usize) -> PcpResult<usize>;
 
+    ///
+    /// Moves to given offset from the beginning of file before reading starts, so that
+    /// only part of file is read
+    ///
+    /// # Arguments
+    ///
+    /// * `offset`: offset of the next byte to read
+    ///
+    /// returns: std::io::Result<()>: error of kind `Unsupported` if reader can not seek,
+    /// then data before offset is read and dropped
+    ///
+    fn seek(&mut self, _offset: usize) -> std::io::Result<()> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Seeking is not supported"))
+    }
 }
 
-//FUTURE: refactor this to be done via macros
-pub fn register_readers(){
-    let mut factory = READER_FACTORY.lock().unwrap();
-    factory.add_reader::<crate::reader::file::FileReader>("file");
-}
\ No newline at end of file
+///
+/// Reads whole file into memory. Intended for small files like manifests and pattern lists.
+///
+/// # Arguments
+///
+/// * `reader`: reader of file
+///
+/// returns: PcpResult<Vec<u8>>: file contents
+///
+pub async fn read_to_end(mut reader: Box<dyn Reader>) -> PcpResult<Vec<u8>> {
+    let chunk_size = reader.get_blocksize();
+    let mut buffer = DynBuffer::make_buffer(chunk_size);
+    let mut content = Vec::new();
+    loop {
+        let bytes_read = reader.read_chunk(&mut buffer, chunk_size).await?;
+        if bytes_read == 0 {
+            break;
+        }
+        content.extend_from_slice(&buffer[..bytes_read]);
+    }
+    Ok(content)
+}
@@ -0,0 +1,694 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
+use crate::reader::zip::{read_zip_members, ZipEntry};
+use crate::reader::Reader;
+use crate::utils::generic_iterator::GenericIterator;
+use crate::utils::stat::ObjectStat;
+
+/// Separator of archive and member in plain paths like `release.tar.gz//usr/bin`
+const PATH_SEPARATOR: &[u8] = b"//";
+/// Separator of archive and member in `tar://` and `zip://` URLs
+const URL_SEPARATOR: u8 = b'!';
+/// File names which are recognized as archives in plain paths, longest first
+const EXTENSIONS: [(ArchiveFormat, &str); 11] = [
+    (ArchiveFormat::Tar, ".tar.gz"),
+    (ArchiveFormat::Tar, ".tar.zst"),
+    (ArchiveFormat::Tar, ".tar.xz"),
+    (ArchiveFormat::Tar, ".tar.bz2"),
+    (ArchiveFormat::Tar, ".tar"),
+    (ArchiveFormat::Tar, ".tgz"),
+    (ArchiveFormat::Tar, ".tzst"),
+    (ArchiveFormat::Tar, ".txz"),
+    (ArchiveFormat::Tar, ".tbz2"),
+    (ArchiveFormat::Tar, ".tbz"),
+    (ArchiveFormat::Zip, ".zip"),
+];
+/// Size of chunks read from archive
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Kind of archive, told by extension in plain paths and by scheme in URLs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    fn from_scheme(scheme: &str) -> Option<ArchiveFormat> {
+        match scheme {
+            "tar" => Some(ArchiveFormat::Tar),
+            "zip" => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Location of object inside archive
+///
+pub(crate) struct ArchivePath {
+    pub format: ArchiveFormat,
+    /// Archive in local filesystem
+    pub archive: PathBuf,
+    /// Path of member inside archive, empty for its root
+    pub member: PathBuf,
+    /// Part of location path which precedes member, so that locations of other members can be made
+    prefix: OsString,
+}
+
+/// Gets format of archive by its name and the name without extension
+pub(crate) fn split_archive_name(name: &OsStr) -> Option<(ArchiveFormat, &OsStr)> {
+    let bytes = name.as_bytes();
+    EXTENSIONS.iter()
+        .find(|(_, extension)| bytes.len() > extension.len()
+            && bytes[bytes.len() - extension.len()..].eq_ignore_ascii_case(extension.as_bytes()))
+        .map(|(format, extension)| (*format, OsStr::from_bytes(&bytes[..bytes.len() - extension.len()])))
+}
+
+/// Drops `.`, root and `..` components, so that member paths of archive can be compared
+pub(crate) fn normalize_member(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+impl ArchivePath {
+    ///
+    /// Splits location into archive and member. Plain paths need archive extension before `//`,
+    /// while `tar://` and `zip://` URLs separate archive with `!`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url`: location of member
+    ///
+    /// returns: Option<ArchivePath>: None if location does not point inside archive
+    ///
+    pub fn parse(url: &Location) -> Option<ArchivePath> {
+        let path = url.path().as_bytes();
+        match url.scheme() {
+            None => {
+                let position = path.windows(PATH_SEPARATOR.len())
+                    .enumerate()
+                    .filter(|(_, window)| *window == PATH_SEPARATOR)
+                    .map(|(position, _)| position)
+                    .find(|position| split_archive_name(OsStr::from_bytes(&path[..*position])).is_some())?;
+                let (format, _) = split_archive_name(OsStr::from_bytes(&path[..position]))?;
+                Some(ArchivePath {
+                    format,
+                    archive: PathBuf::from(OsStr::from_bytes(&path[..position])),
+                    member: normalize_member(Path::new(OsStr::from_bytes(&path[position..]))),
+                    prefix: OsString::from(OsStr::from_bytes(&path[..position + 1])),
+                })
+            }
+            Some(scheme) => {
+                let format = ArchiveFormat::from_scheme(scheme)?;
+                let authority = url.authority().unwrap_or("");
+                let (archive, member, prefix) = match authority.strip_suffix(URL_SEPARATOR as char) {
+                    // Relative archive like `tar://release.tar!/usr`
+                    Some(archive) => (OsStr::new(archive).to_os_string(), path, &path[..0]),
+                    None => {
+                        let position = path.iter().position(|byte| *byte == URL_SEPARATOR)?;
+                        let mut archive = OsString::from(authority);
+                        archive.push(OsStr::from_bytes(&path[..position]));
+                        (archive, &path[position + 1..], &path[..position + 1])
+                    }
+                };
+                Some(ArchivePath {
+                    format,
+                    archive: PathBuf::from(archive),
+                    member: normalize_member(Path::new(OsStr::from_bytes(member))),
+                    prefix: OsStr::from_bytes(prefix).to_os_string(),
+                })
+            }
+        }
+    }
+
+    /// Makes location of another member of the same archive
+    pub fn member_location(&self, url: &Location, member: &Path) -> Location {
+        let mut path = self.prefix.clone();
+        path.push("/");
+        path.push(member);
+        url.with_path(path)
+    }
+}
+
+/// Metadata of archive member, taken from its header
+#[derive(Debug, Clone)]
+pub(crate) struct Member {
+    pub is_directory: bool,
+    pub size: usize,
+    pub modified: Option<SystemTime>,
+    pub permissions: Option<u32>,
+    pub storage: Storage,
+}
+
+/// Where data of member is found
+#[derive(Debug, Clone)]
+pub(crate) enum Storage {
+    /// Directories have no data
+    None,
+    /// Position of member data in decompressed tarball
+    Tar(u64),
+    Zip(ZipEntry),
+}
+
+impl Member {
+    /// Directory which has no header of its own, but contains other members
+    fn implicit_directory() -> Member {
+        Member {
+            is_directory: true,
+            size: 0,
+            modified: None,
+            permissions: None,
+            storage: Storage::None,
+        }
+    }
+
+    fn stat(&self) -> ObjectStat {
+        ObjectStat {
+            size: self.size,
+            modified: self.modified,
+            is_directory: self.is_directory,
+            permissions: self.permissions,
+        }
+    }
+}
+
+///
+/// Members of archive by their paths. Paths are ordered component-wise, so each directory
+/// is followed by its descendants.
+///
+struct ArchiveIndex {
+    /// Size and modification time of archive when it was indexed
+    version: (u64, Option<SystemTime>),
+    members: BTreeMap<PathBuf, Member>,
+}
+
+///
+/// Decompressed archive which remembers its position, so that sequential members are read
+/// without decompressing archive from the beginning every time
+///
+struct ArchiveStream {
+    input: ArchiveInput,
+    position: u64,
+}
+
+enum ArchiveInput {
+    /// Uncompressed tarball, which can be seeked
+    Plain(File),
+    #[cfg_attr(not(feature = "compression"), allow(dead_code))]
+    Compressed(Box<dyn Read + Send>),
+}
+
+lazy_static! {
+    static ref INDEXES: Mutex<HashMap<PathBuf, Arc<ArchiveIndex>>> = Mutex::new(HashMap::new());
+    /// Streams left after reading members, one per archive
+    static ref STREAMS: Mutex<HashMap<PathBuf, ArchiveStream>> = Mutex::new(HashMap::new());
+}
+
+impl ArchiveStream {
+    fn open(archive: &Path) -> std::io::Result<ArchiveStream> {
+        let file = File::open(archive)?;
+        #[cfg(feature = "compression")]
+        let input = match crate::compression::auto_decoder(file.try_clone()?)? {
+            // Clone shares position with file, which was moved by looking for magic bytes
+            (_, None) => {
+                let mut file = file;
+                file.rewind()?;
+                ArchiveInput::Plain(file)
+            }
+            (decoder, Some(_)) => ArchiveInput::Compressed(decoder),
+        };
+        #[cfg(not(feature = "compression"))]
+        let input = ArchiveInput::Plain(file);
+        Ok(ArchiveStream {
+            input,
+            position: 0,
+        })
+    }
+
+    ///
+    /// Takes stream left by previous reader if it has not passed offset yet, otherwise opens archive
+    ///
+    fn take(archive: &Path, offset: u64) -> std::io::Result<ArchiveStream> {
+        let mut stream = match STREAMS.lock().unwrap().remove(archive) {
+            Some(stream) if stream.position <= offset || matches!(stream.input, ArchiveInput::Plain(_)) => stream,
+            _ => ArchiveStream::open(archive)?,
+        };
+        stream.advance(offset)?;
+        Ok(stream)
+    }
+
+    /// Moves to given position, decompressed data in between is skipped
+    fn advance(&mut self, offset: u64) -> std::io::Result<()> {
+        match &mut self.input {
+            ArchiveInput::Plain(file) => {
+                file.seek(SeekFrom::Start(offset))?;
+            }
+            ArchiveInput::Compressed(input) => {
+                let skipped = std::io::copy(&mut input.take(offset - self.position), &mut std::io::sink())?;
+                if self.position + skipped < offset {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Archive is truncated"));
+                }
+            }
+        }
+        self.position = offset;
+        Ok(())
+    }
+}
+
+impl Read for ArchiveStream {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = match &mut self.input {
+            ArchiveInput::Plain(file) => file.read(buffer)?,
+            ArchiveInput::Compressed(input) => input.read(buffer)?,
+        };
+        self.position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+/// Gets size and modification time of archive, which tell whether its index is outdated
+fn archive_version(archive: &Path) -> std::io::Result<(u64, Option<SystemTime>)> {
+    let metadata = std::fs::metadata(archive)?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
+
+///
+/// Reads headers of all members of tarball. Only regular files and directories are indexed,
+/// links and special files are left out.
+///
+fn read_tar_members(archive: &Path) -> std::io::Result<Vec<(PathBuf, Member)>> {
+    let mut members = Vec::new();
+    let mut stream = ArchiveStream::open(archive)?;
+    let input: &mut dyn Read = &mut stream;
+    for entry in tar::Archive::new(input).entries()? {
+        let entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+        members.push((normalize_member(&entry.path()?), Member {
+            is_directory: entry_type.is_dir(),
+            size: if entry_type.is_dir() { 0 } else { entry.size() as usize },
+            modified: entry.header().mtime().ok().map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime)),
+            permissions: entry.header().mode().ok(),
+            storage: if entry_type.is_dir() { Storage::None } else { Storage::Tar(entry.raw_file_position()) },
+        }));
+    }
+    Ok(members)
+}
+
+///
+/// Indexes members of archive, adding directories which have no entries of their own
+///
+fn build_index(archive: &Path, format: ArchiveFormat) -> std::io::Result<ArchiveIndex> {
+    let version = archive_version(archive)?;
+    let entries = match format {
+        ArchiveFormat::Tar => read_tar_members(archive)?,
+        ArchiveFormat::Zip => read_zip_members(archive)?,
+    };
+    let mut members = BTreeMap::new();
+    members.insert(PathBuf::new(), Member::implicit_directory());
+    for (path, member) in entries {
+        // Only root directory may have empty path
+        if path.as_os_str().is_empty() && !member.is_directory {
+            continue;
+        }
+        for ancestor in path.ancestors().skip(1) {
+            members.entry(ancestor.to_path_buf()).or_insert_with(Member::implicit_directory);
+        }
+        members.insert(path, member);
+    }
+    Ok(ArchiveIndex {
+        version,
+        members,
+    })
+}
+
+///
+/// Gets index of archive, building it when archive is seen first or has changed since
+///
+fn archive_index(archive: &Path, format: ArchiveFormat) -> std::io::Result<Arc<ArchiveIndex>> {
+    let version = archive_version(archive)?;
+    if let Some(index) = INDEXES.lock().unwrap().get(archive).filter(|index| index.version == version) {
+        return Ok(index.clone());
+    }
+    // Stream of previous version would read stale data
+    STREAMS.lock().unwrap().remove(archive);
+    let index = Arc::new(build_index(archive, format)?);
+    INDEXES.lock().unwrap().insert(archive.to_path_buf(), index.clone());
+    Ok(index)
+}
+
+/// Finds member which location points to
+fn find_member(url: &Location) -> Option<Member> {
+    let path = ArchivePath::parse(url)?;
+    archive_index(&path.archive, path.format).ok()?.members.get(&path.member).cloned()
+}
+
+///
+/// Finds member which is about to be read
+///
+/// # Arguments
+///
+/// * `url`: location of member
+/// * `format`: format which reader handles
+///
+/// returns: PcpResult<(PathBuf, Member)>: path of archive and member, which is not a directory
+///
+pub(crate) fn find_file_member(url: &Location, format: ArchiveFormat) -> PcpResult<(PathBuf, Member)> {
+    let archive_path = ArchivePath::parse(url)
+        .filter(|path| path.format == format)
+        .ok_or_else(|| PcpError::Unsupported(url.to_string(), String::from("Not an archive")))?;
+    let index = archive_index(&archive_path.archive, format)
+        .map_err(|error| PcpError::from_io(archive_path.archive.display(), error))?;
+    match index.members.get(&archive_path.member) {
+        Some(member) if member.is_directory => Err(PcpError::other(url, "Is a directory")),
+        Some(member) => Ok((archive_path.archive, member.clone())),
+        None => Err(PcpError::NotFound(url.to_string())),
+    }
+}
+
+///
+/// Lists descendants of archive directory in the order they are stored in index
+///
+struct MemberIterator {
+    base: Location,
+    archive_path: Option<ArchivePath>,
+    members: Vec<(PathBuf, bool)>,
+    current_member: usize,
+    /// Directory returned last, its descendants are dropped if it is skipped
+    last_directory: Option<PathBuf>,
+    errors: Vec<PcpError>,
+}
+
+impl MemberIterator {
+    fn new(url: &Location) -> MemberIterator {
+        let mut iterator = MemberIterator {
+            base: url.clone(),
+            archive_path: None,
+            members: Vec::new(),
+            current_member: 0,
+            last_directory: None,
+            errors: Vec::new(),
+        };
+        let archive_path = match ArchivePath::parse(url) {
+            Some(archive_path) => archive_path,
+            None => {
+                iterator.errors.push(PcpError::Unsupported(url.to_string(), String::from("Not an archive")));
+                return iterator;
+            }
+        };
+        match archive_index(&archive_path.archive, archive_path.format) {
+            Ok(index) => match index.members.get(&archive_path.member) {
+                Some(member) if member.is_directory => {
+                    iterator.members = index.members.range(archive_path.member.clone()..)
+                        .skip(1)
+                        .take_while(|(path, _)| path.starts_with(&archive_path.member))
+                        .map(|(path, member)| (path.clone(), member.is_directory))
+                        .collect();
+                }
+                Some(_) => iterator.members.push((archive_path.member.clone(), false)),
+                None => iterator.errors.push(PcpError::NotFound(url.to_string())),
+            },
+            Err(error) => iterator.errors.push(PcpError::from_io(archive_path.archive.display(), error)),
+        }
+        iterator.archive_path = Some(archive_path);
+        iterator
+    }
+}
+
+impl GenericIterator<Location> for MemberIterator {
+    fn internal_next(&mut self) -> Option<Location> {
+        let (path, is_directory) = self.members.get(self.current_member)?.clone();
+        self.current_member += 1;
+        self.last_directory = is_directory.then(|| path.clone());
+        Some(self.archive_path.as_ref()?.member_location(&self.base, &path))
+    }
+
+    fn skip_directory(&mut self) {
+        if let Some(directory) = self.last_directory.take() {
+            while self.members.get(self.current_member).is_some_and(|(path, _)| path.starts_with(&directory)) {
+                self.current_member += 1;
+            }
+        }
+    }
+
+    #[inline]
+    fn take_errors(&mut self) -> Vec<PcpError> {
+        std::mem::take(&mut self.errors)
+    }
+}
+
+/// Error of operations which would change archive
+pub(crate) fn read_only_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, "Archive is read-only")
+}
+
+//
+// Operations on locations inside archives, which readers of all formats share
+//
+
+#[inline]
+pub(crate) fn is_member_directory(url: &Location) -> bool {
+    find_member(url).is_some_and(|member| member.is_directory)
+}
+
+#[inline]
+pub(crate) fn member_stat(url: &Location) -> Option<ObjectStat> {
+    find_member(url).map(|member| member.stat())
+}
+
+#[inline]
+pub(crate) fn iter_members(url: &Location) -> Box<dyn GenericIterator<Location>> {
+    Box::new(MemberIterator::new(url))
+}
+
+pub(crate) fn member_relative_path(src_arg: &Location, url: &Location) -> PathBuf {
+    match (ArchivePath::parse(src_arg), ArchivePath::parse(url)) {
+        (Some(base), Some(path)) => path.member.strip_prefix(&base.member)
+            .map(Path::to_path_buf)
+            .unwrap_or(path.member),
+        _ => PathBuf::from(url.path()),
+    }
+}
+
+/// Root of archive is named after archive itself, e.g. `release` for `release.tar.gz//`
+pub(crate) fn member_dirname(url: &Location) -> OsString {
+    let path = match ArchivePath::parse(url) {
+        Some(path) => path,
+        None => return OsString::new(),
+    };
+    match path.member.file_name() {
+        Some(name) => name.to_os_string(),
+        None => path.archive.file_name()
+            .map(|name| split_archive_name(name).map_or(name, |(_, stem)| stem).to_os_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[inline]
+pub(crate) fn member_filename(url: &Location) -> OsString {
+    ArchivePath::parse(url)
+        .and_then(|path| path.member.file_name().map(OsStr::to_os_string))
+        .unwrap_or_default()
+}
+
+///
+/// Reads members of tarballs, both plain paths like `release.tar.gz//usr/bin` and URLs like
+/// `tar://release.tar.gz!/usr/bin`. Compressed tarballs need `compression` feature.
+///
+pub struct TarReader {
+    path: Location,
+    archive: PathBuf,
+    /// Taken out of stream cache while member is read
+    stream: Option<ArchiveStream>,
+    size: usize,
+    remaining: usize,
+}
+
+// Archives are probed before local files, since file reader would reject paths inside them anyway
+crate::register_reader!(TarReader, "tar", 10);
+
+impl Drop for TarReader {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            STREAMS.lock().unwrap().insert(self.archive.clone(), stream);
+        }
+    }
+}
+
+#[async_trait]
+impl Reader for TarReader {
+    fn can_read(url: &Location) -> bool where Self: Sized {
+        ArchivePath::parse(url).is_some_and(|path| path.format == ArchiveFormat::Tar && path.archive.is_file())
+    }
+
+    fn new(url: &Location) -> PcpResult<Self> where Self: Sized {
+        let (archive, member) = find_file_member(url, ArchiveFormat::Tar)?;
+        let offset = match member.storage {
+            Storage::Tar(offset) => offset,
+            _ => return Err(PcpError::Unsupported(url.to_string(), String::from("Not a tarball"))),
+        };
+        let stream = ArchiveStream::take(&archive, offset).map_err(|error| PcpError::from_io(url, error))?;
+        Ok(TarReader {
+            path: url.clone(),
+            archive,
+            stream: Some(stream),
+            size: member.size,
+            remaining: member.size,
+        })
+    }
+
+    #[inline]
+    fn is_directory(url: &Location) -> bool where Self: Sized {
+        is_member_directory(url)
+    }
+
+    #[inline]
+    fn get_size(&self) -> PcpResult<usize> {
+        Ok(self.size)
+    }
+
+    #[inline]
+    fn get_blocksize(&self) -> usize {
+        CHUNK_SIZE
+    }
+
+    #[inline]
+    fn stat(url: &Location) -> Option<ObjectStat> where Self: Sized {
+        member_stat(url)
+    }
+
+    fn rename(_from: &Location, _to: &Location) -> std::io::Result<()> where Self: Sized {
+        Err(read_only_error())
+    }
+
+    fn remove(_url: &Location) -> std::io::Result<()> where Self: Sized {
+        Err(read_only_error())
+    }
+
+    #[inline]
+    fn iter_directory(url: &Location) -> Box<dyn GenericIterator<Location>> where Self: Sized {
+        iter_members(url)
+    }
+
+    #[inline]
+    fn relative_path(src_arg: &Location, url: &Location) -> PathBuf where Self: Sized {
+        member_relative_path(src_arg, url)
+    }
+
+    #[inline]
+    fn dirname(url: &Location) -> OsString where Self: Sized {
+        member_dirname(url)
+    }
+
+    #[inline]
+    fn filename(url: &Location) -> OsString where Self: Sized {
+        member_filename(url)
+    }
+
+    async fn read_chunk(&mut self, buffer: &mut [u8], max_size: usize) -> PcpResult<usize> {
+        let size = max_size.min(buffer.len()).min(self.remaining);
+        let stream = match (&mut self.stream, size) {
+            (_, 0) | (None, _) => return Ok(0),
+            (Some(stream), _) => stream,
+        };
+        let bytes_read = stream.read(&mut buffer[..size]).map_err(|error| PcpError::from_io(&self.path, error))?;
+        if bytes_read == 0 {
+            // Stream is left in unknown state, so it is not reused
+            self.stream = None;
+            return Err(PcpError::Protocol(self.path.to_string(), String::from("Archive is truncated")));
+        }
+        self.remaining -= bytes_read;
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::read_to_end;
+    use crate::utils::runtime::{init_tokio, tokio_block_on};
+
+    fn parse(url: &str) -> (PathBuf, PathBuf) {
+        let path = ArchivePath::parse(&Location::parse(url)).unwrap();
+        (path.archive, path.member)
+    }
+
+    #[test]
+    fn test_archive_paths() {
+        assert_eq!(parse("release.tar.gz//usr/bin"), (PathBuf::from("release.tar.gz"), PathBuf::from("usr/bin")));
+        assert_eq!(parse("/tmp/a//b.TGZ//"), (PathBuf::from("/tmp/a//b.TGZ"), PathBuf::new()));
+        assert_eq!(parse("tar://release.tar!/usr/bin"), (PathBuf::from("release.tar"), PathBuf::from("usr/bin")));
+        assert_eq!(parse("tar:///tmp/r.tar.zst!/./usr/"), (PathBuf::from("/tmp/r.tar.zst"), PathBuf::from("usr")));
+        assert!(ArchivePath::parse(&Location::parse("/tmp/dir//file")).is_none());
+        assert!(ArchivePath::parse(&Location::parse("/tmp/release.tar.gz")).is_none());
+        assert!(ArchivePath::parse(&Location::parse("http://host/r.tar!/usr")).is_none());
+        assert_eq!(ArchivePath::parse(&Location::parse("r.zip//a")).unwrap().format, ArchiveFormat::Zip);
+        assert_eq!(parse("zip://r.zip!/a"), (PathBuf::from("r.zip"), PathBuf::from("a")));
+        let url = Location::parse("tar:///tmp/r.tar!/usr");
+        let path = ArchivePath::parse(&url).unwrap();
+        assert_eq!(path.member_location(&url, Path::new("usr/bin")).to_string(), "tar:///tmp/r.tar!/usr/bin");
+        let url = Location::parse("r.tar//usr");
+        let path = ArchivePath::parse(&url).unwrap();
+        assert_eq!(path.member_location(&url, Path::new("usr/bin")).to_string(), "r.tar//usr/bin");
+    }
+
+    #[test]
+    fn test_read_members() {
+        let base = std::env::temp_dir().join(format!("pcp-tar-reader-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        let archive = base.join("test.tar");
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        for (name, content) in [("dir/a", "first"), ("dir/sub/b", "second"), ("c", "third")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content.as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+
+        let root = Location::from_path(format!("{}//dir", archive.display()));
+        assert!(TarReader::can_read(&root) && TarReader::is_directory(&root));
+        let members: Vec<_> = TarReader::iter_directory(&root)
+            .map(|member| TarReader::relative_path(&root, &member))
+            .collect();
+        assert_eq!(members, [PathBuf::from("a"), PathBuf::from("sub"), PathBuf::from("sub/b")]);
+        let mut iterator = TarReader::iter_directory(&root);
+        iterator.internal_next();
+        iterator.internal_next();
+        iterator.skip_directory();
+        assert!(iterator.internal_next().is_none());
+
+        // Members are read in reverse order, so stream has to be reopened
+        init_tokio();
+        for (name, expected) in [("c", "third"), ("dir/sub/b", "second"), ("dir/a", "first")] {
+            let url = Location::parse(&format!("tar://{}!/{}", archive.display(), name));
+            let content = tokio_block_on(async {
+                read_to_end(Box::new(TarReader::new(&url).unwrap())).await.unwrap()
+            });
+            assert_eq!(content, expected.as_bytes());
+        }
+        assert_eq!(TarReader::stat(&Location::parse(&format!("{}//c", archive.display()))).unwrap().size, 5);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
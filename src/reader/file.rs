@@ -4,20 +4,27 @@ use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
 use tokio::fs::File;
-use regex::Regex;
-use tokio::io::AsyncReadExt;
-use colored::Colorize;
+use std::io::SeekFrom;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
 use crate::reader::Reader;
 use crate::utils::generic_iterator::GenericIterator;
 use crate::utils::runtime::tokio_block_on;
+use crate::utils::stat::ObjectStat;
 
 /// Implements standard file reading from local FS
 pub struct FileReader {
-    path: String,
+    path: Location,
     file: File,
+    size: usize,
+    blocksize: usize,
 }
 
+// Local paths are accepted by file reader as is, so it is probed last
+crate::register_reader!(FileReader, "file", 0);
+
 struct DirectoryIteratorState {
     objects: Vec<OsString>,
     current_object: usize,
@@ -25,21 +32,22 @@ struct DirectoryIteratorState {
 }
 
 impl DirectoryIteratorState {
-    pub fn new(path: String) -> DirectoryIteratorState {
-        let path = Path::new(&path);
+    pub fn new(path: PathBuf) -> PcpResult<DirectoryIteratorState> {
+        let path = path.as_path();
         let objects = if path.is_file() {
             vec![OsString::from(&path)]
         } else {
-            std::fs::read_dir(path).expect("Can not read directory")
-                .map(|x| { x.unwrap().path().into_os_string() })
+            std::fs::read_dir(path).map_err(|error| PcpError::from_io(path.display(), error))?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().into_os_string())
                 .collect()
         };
         //println!("path {:?} has objects {:?}", path, objects);
-        DirectoryIteratorState {
+        Ok(DirectoryIteratorState {
             objects,
             current_object: 0,
             _full_path: OsString::from(&path),
-        }
+        })
     }
 
     #[inline]
@@ -60,22 +68,44 @@ impl DirectoryIteratorState {
 }
 
 struct DirectoryIterator {
-    _base_directory: String,
+    /// Location of listed directory, its scheme and authority are kept by produced objects
+    base_directory: Location,
     state_stack: Vec<DirectoryIteratorState>,
+    /// Directory returned last, it is entered only on next call, so it may be skipped
+    pending_directory: Option<PathBuf>,
+    /// Directories which could not be listed
+    errors: Vec<PcpError>,
 }
 
 impl DirectoryIterator {
-    pub fn new(url: &str) -> DirectoryIterator {
-        DirectoryIterator {
-            _base_directory: url.to_string(),
-            state_stack: vec![DirectoryIteratorState::new(url.to_string())],
+    pub fn new(url: &Location) -> DirectoryIterator {
+        let mut iterator = DirectoryIterator {
+            base_directory: url.clone(),
+            state_stack: Vec::new(),
+            pending_directory: None,
+            errors: Vec::new(),
+        };
+        match local_path(url) {
+            Ok(path) => iterator.enter(path.to_path_buf()),
+            Err(error) => iterator.errors.push(error),
+        }
+        iterator
+    }
+
+    fn enter(&mut self, directory: PathBuf) {
+        match DirectoryIteratorState::new(directory) {
+            Ok(state) => self.state_stack.push(state),
+            Err(error) => self.errors.push(error),
         }
     }
 }
 
-impl GenericIterator<String> for DirectoryIterator {
-    fn internal_next(&mut self) -> Option<String> {
-        if self.state_stack.len() == 0 {
+impl GenericIterator<Location> for DirectoryIterator {
+    fn internal_next(&mut self) -> Option<Location> {
+        if let Some(directory) = self.pending_directory.take() {
+            self.enter(directory);
+        }
+        if self.state_stack.is_empty() {
             return None;
         }
         let mut next_object = self.state_stack.last_mut().unwrap().next_object();
@@ -84,95 +114,130 @@ impl GenericIterator<String> for DirectoryIterator {
             self.state_stack.pop();
             next_object = self.state_stack.last_mut().unwrap().next_object();
         }
-        if next_object.is_none() {
-            //println!("No more objects through stack");
-            return None;
-        }
-        let path_os_string = next_object.unwrap();
-        let path_string = path_os_string.to_str().unwrap().to_string();
-        if Path::new(&path_os_string).is_dir() {
-            self.state_stack.push(DirectoryIteratorState::new(path_string.clone()));
+        //No more objects through stack if next_object is None
+        let path = PathBuf::from(next_object?);
+        if path.is_dir() {
+            self.pending_directory = Some(path.clone());
         }
-        Some(path_string)
+        Some(self.base_directory.with_path(path))
+    }
+
+    #[inline]
+    fn skip_directory(&mut self) {
+        self.pending_directory = None;
+    }
+
+    #[inline]
+    fn take_errors(&mut self) -> Vec<PcpError> {
+        std::mem::take(&mut self.errors)
     }
 }
 
 
-#[inline]
-fn check_valid_url(url: &str) -> bool {
-    let re = Regex::new(r"^(/?[\s\w'.-]+)+(/)?$").unwrap();
-    re.is_match(url)
+///
+/// Gets path of location in local filesystem
+///
+/// # Arguments
+///
+/// * `url`: location of object
+///
+/// returns: PcpResult<&Path>: path or error if location points to another host or scheme
+///
+pub(crate) fn local_path(url: &Location) -> PcpResult<&Path> {
+    url.local_path().ok_or_else(|| PcpError::Unsupported(url.to_string(), String::from("Not a local path")))
+}
+
+/// Same as `local_path` for operations which report plain input/output errors
+pub(crate) fn local_io_path(url: &Location) -> std::io::Result<&Path> {
+    url.local_path().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Unsupported, "Not a local path"))
 }
 
 #[async_trait]
 impl Reader for FileReader {
-    fn can_read(url: &str) -> bool where Self: Sized {
-        if !check_valid_url(url) {
-            return false;
-        }
-        let path = Path::new(url);
-        if !path.is_file() && !path.is_dir() {
-            println!("{}:{} No such file or directory", url.bold().red(), "".clear());
-            return false;
-        }
-        true
+    fn can_read(url: &Location) -> bool where Self: Sized {
+        let path = match url.local_path() {
+            Some(path) => path,
+            None => return false,
+        };
+        path.is_file() || path.is_dir()
     }
-    fn new(url: &str) -> Self where Self: Sized {
-        if !Self::can_read(url) {
-            //panic!("Can not read url {url}");
-        }
+    fn new(url: &Location) -> PcpResult<Self> where Self: Sized {
+        let path = local_path(url)?;
         let open_coroutine = async {
-            File::open(url).await
+            let file = File::open(path).await?;
+            let metadata = file.metadata().await?;
+            Ok((file, metadata))
         };
-
-        FileReader {
-            path: String::from(url),
-            file: tokio_block_on(open_coroutine).expect("Can not open file"),
-        }
+        let (file, metadata) = tokio_block_on(open_coroutine)
+            .map_err(|error| PcpError::from_io(url, error))?;
+        Ok(FileReader {
+            path: url.clone(),
+            file,
+            size: metadata.size() as usize,
+            blocksize: metadata.blksize() as usize,
+        })
     }
 
     #[inline]
-    fn is_directory(url: &str) -> bool where Self: Sized {
-        let path = Path::new(url);
-        path.is_dir() && path.exists()
+    fn is_directory(url: &Location) -> bool where Self: Sized {
+        url.local_path().is_some_and(Path::is_dir)
     }
 
-    fn get_size(&self) -> usize {
-        let metadata = std::fs::metadata(&self.path).expect("Can not read metadata");
-        metadata.size() as usize
+    #[inline]
+    fn get_size(&self) -> PcpResult<usize> {
+        Ok(self.size)
     }
 
+    #[inline]
     fn get_blocksize(&self) -> usize {
-        let metadata = std::fs::metadata(&self.path).expect("Can not read metadata");
-        metadata.blksize() as usize
+        self.blocksize
     }
 
     #[inline]
-    fn iter_directory(url: &str) -> Box<dyn GenericIterator<String>> {
+    fn stat(url: &Location) -> Option<ObjectStat> where Self: Sized {
+        std::fs::metadata(url.local_path()?).ok().map(ObjectStat::from)
+    }
+
+    fn rename(from: &Location, to: &Location) -> std::io::Result<()> where Self: Sized {
+        std::fs::rename(local_io_path(from)?, local_io_path(to)?)
+    }
+
+    fn remove(url: &Location) -> std::io::Result<()> where Self: Sized {
+        let path = local_io_path(url)?;
+        if path.is_dir() {
+            std::fs::remove_dir(path)
+        } else {
+            std::fs::remove_file(path)
+        }
+    }
+
+    #[inline]
+    fn iter_directory(url: &Location) -> Box<dyn GenericIterator<Location>> {
         Box::new(DirectoryIterator::new(url))
     }
 
     #[inline]
-    fn relative_path(src_arg: &str, url: &str) -> String {
-        let src_path = Path::new(src_arg);
-        let url = Path::new(url);
-        url.strip_prefix(src_path).unwrap().to_str().unwrap().to_string()
+    fn relative_path(src_arg: &Location, url: &Location) -> PathBuf {
+        let url = Path::new(url.path());
+        url.strip_prefix(src_arg.path()).unwrap_or(url).to_path_buf()
     }
 
     #[inline]
-    fn dirname(url: &str) -> String where Self: Sized {
-        let path = PathBuf::from(url);
-        path.iter().last().unwrap().to_str().unwrap().to_string()
+    fn dirname(url: &Location) -> OsString where Self: Sized {
+        Path::new(url.path()).iter().next_back().unwrap_or_default().to_os_string()
     }
 
     #[inline]
-    fn filename(url: &str) -> String where Self: Sized {
-        let path = PathBuf::from(url);
-        path.iter().last().unwrap().to_str().unwrap().to_string()
+    fn filename(url: &Location) -> OsString where Self: Sized {
+        Path::new(url.path()).iter().next_back().unwrap_or_default().to_os_string()
+    }
+
+    async fn read_chunk(&mut self, buffer: &mut [u8], _max_size: usize) -> PcpResult<usize> {
+        self.file.read(buffer).await.map_err(|error| PcpError::from_io(&self.path, error))
     }
 
-    async fn read_chunk(&mut self, buffer: &mut [u8], _max_size: usize) -> usize {
-        self.file.read(buffer).await.expect("Can not read file")
+    fn seek(&mut self, offset: usize) -> std::io::Result<()> {
+        tokio_block_on(self.file.seek(SeekFrom::Start(offset as u64))).map(|_| ())
     }
 }
 
@@ -181,40 +246,45 @@ impl Reader for FileReader {
 mod tests {
     use super::*;
 
+    fn relative_path(src_arg: &str, url: &str) -> PathBuf {
+        FileReader::relative_path(&Location::parse(src_arg), &Location::parse(url))
+    }
+
     #[test]
     fn test_relative_path() {
-        let src_arg = "/tmp/foo";
-        let url = "/tmp/foo/bar/file";
-        assert_eq!(FileReader::relative_path(src_arg, url), "bar/file");
-        let src_arg = "localdir/foo";
-        let url = "localdir/foo/bar/file";
-        assert_eq!(FileReader::relative_path(src_arg, url), "bar/file");
-        let src_arg = "/";
-        let url = "/tmp/foo/bar/file";
-        assert_eq!(FileReader::relative_path(src_arg, url), "tmp/foo/bar/file");
+        assert_eq!(relative_path("/tmp/foo", "/tmp/foo/bar/file"), Path::new("bar/file"));
+        assert_eq!(relative_path("localdir/foo", "localdir/foo/bar/file"), Path::new("bar/file"));
+        assert_eq!(relative_path("/", "/tmp/foo/bar/file"), Path::new("tmp/foo/bar/file"));
+        assert_eq!(relative_path("file:///tmp/foo", "file:///tmp/foo/bar/file"), Path::new("bar/file"));
     }
 
     #[test]
     fn test_relative_path_unicode() {
-        let src_arg = "/tmp/документи";
-        let url = "/tmp/документи/bar/file";
-        assert_eq!(FileReader::relative_path(src_arg, url), "bar/file");
-        let src_arg = "localdir/документи";
-        let url = "localdir/документи/bar/file";
-        assert_eq!(FileReader::relative_path(src_arg, url), "bar/file");
-        let src_arg = "/";
-        let url = "/tmp/документи/bar/file";
-        assert_eq!(FileReader::relative_path(src_arg, url), "tmp/документи/bar/file");
+        assert_eq!(relative_path("/tmp/документи", "/tmp/документи/bar/file"), Path::new("bar/file"));
+        assert_eq!(relative_path("localdir/документи", "localdir/документи/bar/file"), Path::new("bar/file"));
+        assert_eq!(relative_path("/", "/tmp/документи/bar/file"), Path::new("tmp/документи/bar/file"));
     }
 
     #[test]
-    fn test_url_verifier() {
-        assert!(check_valid_url("The Folder/The File"));
-        assert!(check_valid_url("Teka/Łukasz"));
-        assert!(check_valid_url("/home/Łukasz/Pobrane/file.txt"));
-        assert!(check_valid_url("just_file_name"));
-        assert!(check_valid_url("просто_файл"));
-        assert!(check_valid_url("file' name"));
-        assert!(!check_valid_url("F:\\windows\\not_supported"));
-    }
-}
\ No newline at end of file
+    fn test_iterate_non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+        let base = std::env::temp_dir().join(format!("pcp-non-utf8-{}", std::process::id()));
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9");
+        std::fs::create_dir_all(base.join(name)).unwrap();
+        std::fs::write(base.join(name).join(name), b"").unwrap();
+        let location = Location::from_path(&base);
+        let mut objects: Vec<_> = FileReader::iter_directory(&location)
+            .map(|object| FileReader::relative_path(&location, &object))
+            .collect();
+        objects.sort();
+        std::fs::remove_dir_all(&base).unwrap();
+        assert_eq!(objects, [PathBuf::from(name), Path::new(name).join(name)]);
+    }
+
+    #[test]
+    fn test_local_paths_only() {
+        assert!(local_path(&Location::parse("/home/Łukasz/a+b@c (1),~.txt")).is_ok());
+        assert!(local_path(&Location::parse("file:///home/user")).is_ok());
+        assert!(local_path(&Location::parse("ftp://server/file")).is_err());
+    }
+}
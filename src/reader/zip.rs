@@ -0,0 +1,293 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use zip::{CompressionMethod, DateTime};
+
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
+use crate::reader::archive::{find_file_member, is_member_directory, iter_members, member_dirname, member_filename,
+                             member_relative_path, member_stat, normalize_member, read_only_error, ArchiveFormat,
+                             ArchivePath, Member, Storage, CHUNK_SIZE};
+use crate::reader::Reader;
+use crate::utils::generic_iterator::GenericIterator;
+use crate::utils::stat::ObjectStat;
+
+/// Seconds in a day, zip timestamps are split into date and time of day
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+///
+/// Data of zip entry, each of which is compressed on its own
+///
+#[derive(Debug, Clone)]
+pub(crate) struct ZipEntry {
+    /// Position of compressed data in zip file
+    offset: u64,
+    compressed_size: u64,
+    method: CompressionMethod,
+    crc32: u32,
+    encrypted: bool,
+}
+
+///
+/// Counts days since Unix epoch of civil date, proleptic Gregorian calendar is assumed
+///
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Gets civil date of day since Unix epoch, reverse of `days_from_civil`
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = (month_index + 2) % 12 + 1;
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+///
+/// Converts timestamp of zip entry to system time. Zip stores local time without zone,
+/// it is taken as UTC like other tools do when extended timestamps are absent.
+///
+fn to_system_time(time: DateTime) -> Option<SystemTime> {
+    if !time.is_valid() {
+        return None;
+    }
+    let days = days_from_civil(time.year() as u64, time.month() as u64, time.day() as u64);
+    let seconds = time.hour() as u64 * 3600 + time.minute() as u64 * 60 + time.second() as u64;
+    Some(UNIX_EPOCH + Duration::from_secs(days * SECONDS_PER_DAY + seconds))
+}
+
+///
+/// Converts system time to timestamp of zip entry
+///
+/// # Arguments
+///
+/// * `time`: time to convert
+///
+/// returns: Option<DateTime>: None if time is outside of years 1980-2107 which zip can store
+///
+pub(crate) fn from_system_time(time: SystemTime) -> Option<DateTime> {
+    let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let (year, month, day) = civil_from_days(seconds / SECONDS_PER_DAY);
+    let seconds = seconds % SECONDS_PER_DAY;
+    DateTime::from_date_and_time(u16::try_from(year).ok()?, month as u8, day as u8, (seconds / 3600) as u8,
+                                 (seconds / 60 % 60) as u8, (seconds % 60) as u8).ok()
+}
+
+///
+/// Reads central directory of zip file. Symbolic links and entries with names
+/// which would escape archive are left out.
+///
+pub(crate) fn read_zip_members(archive: &std::path::Path) -> std::io::Result<Vec<(PathBuf, Member)>> {
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(archive)?))?;
+    let mut members = Vec::with_capacity(zip.len());
+    for index in 0..zip.len() {
+        let entry = zip.by_index_raw(index)?;
+        let path = match entry.enclosed_name() {
+            Some(path) if !entry.is_symlink() => normalize_member(&path),
+            _ => continue,
+        };
+        let storage = match entry.data_start() {
+            Some(offset) if !entry.is_dir() => Storage::Zip(ZipEntry {
+                offset,
+                compressed_size: entry.compressed_size(),
+                method: entry.compression(),
+                crc32: entry.crc32(),
+                encrypted: entry.encrypted(),
+            }),
+            _ => Storage::None,
+        };
+        members.push((path, Member {
+            is_directory: entry.is_dir(),
+            size: if entry.is_dir() { 0 } else { entry.size() as usize },
+            modified: entry.last_modified().and_then(to_system_time),
+            permissions: entry.unix_mode().map(|mode| mode & 0o7777),
+            storage,
+        }));
+    }
+    Ok(members)
+}
+
+///
+/// Reads members of zip files, both plain paths like `release.zip//bin` and URLs like
+/// `zip://release.zip!/bin`. Stored and deflated entries are supported, checksums of them
+/// are verified when the last chunk is read.
+///
+pub struct ZipReader {
+    path: Location,
+    input: Box<dyn Read + Send>,
+    checksum: flate2::Crc,
+    expected_checksum: u32,
+    size: usize,
+    remaining: usize,
+}
+
+// Probed before local files like tarballs
+crate::register_reader!(ZipReader, "zip", 10);
+
+#[async_trait]
+impl Reader for ZipReader {
+    fn can_read(url: &Location) -> bool where Self: Sized {
+        ArchivePath::parse(url).is_some_and(|path| path.format == ArchiveFormat::Zip && path.archive.is_file())
+    }
+
+    fn new(url: &Location) -> PcpResult<Self> where Self: Sized {
+        let (archive, member) = find_file_member(url, ArchiveFormat::Zip)?;
+        let entry = match member.storage {
+            Storage::Zip(entry) => entry,
+            _ => return Err(PcpError::Unsupported(url.to_string(), String::from("Not a zip entry"))),
+        };
+        if entry.encrypted {
+            return Err(PcpError::Unsupported(url.to_string(), String::from("Encrypted entries can not be read")));
+        }
+        let mut file = File::open(&archive).map_err(|error| PcpError::from_io(archive.display(), error))?;
+        file.seek(SeekFrom::Start(entry.offset)).map_err(|error| PcpError::from_io(url, error))?;
+        let data = BufReader::new(file).take(entry.compressed_size);
+        let input: Box<dyn Read + Send> = match entry.method {
+            CompressionMethod::Stored => Box::new(data),
+            CompressionMethod::Deflated => Box::new(flate2::read::DeflateDecoder::new(data)),
+            method => {
+                return Err(PcpError::Unsupported(url.to_string(),
+                                                 format!("Compression method {} is not supported", method)));
+            }
+        };
+        Ok(ZipReader {
+            path: url.clone(),
+            input,
+            checksum: flate2::Crc::new(),
+            expected_checksum: entry.crc32,
+            size: member.size,
+            remaining: member.size,
+        })
+    }
+
+    #[inline]
+    fn is_directory(url: &Location) -> bool where Self: Sized {
+        is_member_directory(url)
+    }
+
+    #[inline]
+    fn get_size(&self) -> PcpResult<usize> {
+        Ok(self.size)
+    }
+
+    #[inline]
+    fn get_blocksize(&self) -> usize {
+        CHUNK_SIZE
+    }
+
+    #[inline]
+    fn stat(url: &Location) -> Option<ObjectStat> where Self: Sized {
+        member_stat(url)
+    }
+
+    fn rename(_from: &Location, _to: &Location) -> std::io::Result<()> where Self: Sized {
+        Err(read_only_error())
+    }
+
+    fn remove(_url: &Location) -> std::io::Result<()> where Self: Sized {
+        Err(read_only_error())
+    }
+
+    #[inline]
+    fn iter_directory(url: &Location) -> Box<dyn GenericIterator<Location>> where Self: Sized {
+        iter_members(url)
+    }
+
+    #[inline]
+    fn relative_path(src_arg: &Location, url: &Location) -> PathBuf where Self: Sized {
+        member_relative_path(src_arg, url)
+    }
+
+    #[inline]
+    fn dirname(url: &Location) -> OsString where Self: Sized {
+        member_dirname(url)
+    }
+
+    #[inline]
+    fn filename(url: &Location) -> OsString where Self: Sized {
+        member_filename(url)
+    }
+
+    async fn read_chunk(&mut self, buffer: &mut [u8], max_size: usize) -> PcpResult<usize> {
+        let size = max_size.min(buffer.len()).min(self.remaining);
+        if size == 0 {
+            return Ok(0);
+        }
+        let bytes_read = self.input.read(&mut buffer[..size]).map_err(|error| PcpError::from_io(&self.path, error))?;
+        if bytes_read == 0 {
+            return Err(PcpError::Protocol(self.path.to_string(), String::from("Archive is truncated")));
+        }
+        self.checksum.update(&buffer[..bytes_read]);
+        self.remaining -= bytes_read;
+        if self.remaining == 0 && self.checksum.sum() != self.expected_checksum {
+            return Err(PcpError::Protocol(self.path.to_string(), String::from("Checksum of entry does not match")));
+        }
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+    use crate::reader::read_to_end;
+    use crate::utils::runtime::{init_tokio, tokio_block_on};
+
+    #[test]
+    fn test_timestamps() {
+        let time = DateTime::from_date_and_time(2024, 2, 29, 13, 45, 30).unwrap();
+        let system_time = to_system_time(time).unwrap();
+        assert_eq!(system_time.duration_since(UNIX_EPOCH).unwrap().as_secs(), 1709214330);
+        assert_eq!(from_system_time(system_time), Some(time));
+        assert_eq!(from_system_time(UNIX_EPOCH), None);
+    }
+
+    #[test]
+    fn test_read_entries() {
+        let archive = std::env::temp_dir().join(format!("pcp-zip-reader-{}.zip", std::process::id()));
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.add_directory("dir/", stored).unwrap();
+        zip.start_file("dir/a", stored).unwrap();
+        zip.write_all(b"stored").unwrap();
+        zip.start_file("dir/sub/b", SimpleFileOptions::default()).unwrap();
+        zip.write_all(&b"deflated ".repeat(1000)).unwrap();
+        zip.finish().unwrap();
+
+        let root = Location::parse(&format!("zip://{}!/", archive.display()));
+        assert!(ZipReader::can_read(&root) && ZipReader::is_directory(&root));
+        let members: Vec<_> = ZipReader::iter_directory(&root)
+            .map(|member| ZipReader::relative_path(&root, &member))
+            .collect();
+        assert_eq!(members, [PathBuf::from("dir"), PathBuf::from("dir/a"), PathBuf::from("dir/sub"),
+                             PathBuf::from("dir/sub/b")]);
+
+        init_tokio();
+        for (name, expected) in [("dir/sub/b", b"deflated ".repeat(1000)), ("dir/a", b"stored".to_vec())] {
+            let url = Location::from_path(format!("{}//{}", archive.display(), name));
+            let content = tokio_block_on(async {
+                read_to_end(Box::new(ZipReader::new(&url).unwrap())).await.unwrap()
+            });
+            assert_eq!(content, expected);
+        }
+        std::fs::remove_file(&archive).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//!
+//! Built-in backends register themselves next to their implementation with
+//! [`register_reader!`](crate::register_reader), [`register_writer!`](crate::register_writer) and
+//! [`register_progress!`](crate::register_progress), so that the list of them is not kept by hand.
+//! Registrations are collected at link time and added to factories by `register_backends`.
+//!
+
+use crate::factories::{ProgressConstructor, ReaderProxy, WriterProxy, PROGRESS_FACTORY, READER_FACTORY,
+                       WRITER_FACTORY};
+
+/// Reader declared with `register_reader!`
+pub struct ReaderRegistration {
+    pub key: &'static str,
+    pub priority: i32,
+    pub proxy: fn() -> ReaderProxy,
+}
+
+/// Writer declared with `register_writer!`
+pub struct WriterRegistration {
+    pub key: &'static str,
+    pub priority: i32,
+    pub proxy: fn() -> WriterProxy,
+}
+
+/// Progress display declared with `register_progress!`
+pub struct ProgressRegistration {
+    pub key: &'static str,
+    pub constructor: ProgressConstructor,
+}
+
+/// Optional cargo features this build was compiled with
+pub const FEATURES: &[&str] = &[
+    #[cfg(feature = "archive")]
+    "archive",
+    #[cfg(feature = "compression")]
+    "compression",
+    #[cfg(feature = "block")]
+    "block",
+    #[cfg(feature = "encryption")]
+    "encryption",
+    #[cfg(feature = "tui")]
+    "tui",
+];
+
+inventory::collect!(ReaderRegistration);
+inventory::collect!(WriterRegistration);
+inventory::collect!(ProgressRegistration);
+
+///
+/// Registers reader in the module which implements it
+///
+/// # Arguments
+///
+/// * `reader`: type implementing `Reader`
+/// * `key`: name of backend and URL scheme it handles
+/// * `priority`: order of probing URLs without known scheme, higher goes first
+///
+/// # Examples
+///
+/// ```ignore
+/// register_reader!(FileReader, "file", 0);
+/// ```
+///
+#[macro_export]
+macro_rules! register_reader {
+    ($reader:ty, $key:literal, $priority:expr) => {
+        $crate::inventory::submit! {
+            $crate::registry::ReaderRegistration {
+                key: $key,
+                priority: $priority,
+                proxy: $crate::factories::ReaderProxy::from_type::<$reader>,
+            }
+        }
+    };
+}
+
+///
+/// Registers writer in the module which implements it
+///
+/// # Arguments
+///
+/// * `writer`: type implementing `Writer`
+/// * `key`: name of backend and URL scheme it handles
+/// * `priority`: order of probing URLs without known scheme, higher goes first
+///
+#[macro_export]
+macro_rules! register_writer {
+    ($writer:ty, $key:literal, $priority:expr) => {
+        $crate::inventory::submit! {
+            $crate::registry::WriterRegistration {
+                key: $key,
+                priority: $priority,
+                proxy: $crate::factories::WriterProxy::from_type::<$writer>,
+            }
+        }
+    };
+}
+
+///
+/// Registers progress display, which is selected with `--progress`
+///
+/// # Arguments
+///
+/// * `display`: type implementing `ProgressDisplay`
+/// * `key`: name of display
+///
+#[macro_export]
+macro_rules! register_progress {
+    ($display:ty, $key:literal) => {
+        $crate::inventory::submit! {
+            $crate::registry::ProgressRegistration {
+                key: $key,
+                constructor: || ::std::boxed::Box::new(<$display as $crate::progress::ProgressDisplay>::new()),
+            }
+        }
+    };
+}
+
+///
+/// Adds registered readers to `READER_FACTORY`. Link order is arbitrary, so readers are added
+/// sorted by key to keep order of readers with equal priority stable.
+///
+pub fn register_readers() {
+    let mut registrations: Vec<_> = inventory::iter::<ReaderRegistration>.into_iter().collect();
+    registrations.sort_by_key(|registration| registration.key);
+    let mut factory = READER_FACTORY.lock().unwrap();
+    for registration in registrations {
+        factory.add_reader_proxy(registration.key, registration.priority, (registration.proxy)());
+    }
+}
+
+/// Adds registered writers to `WRITER_FACTORY`, sorted by key like readers
+pub fn register_writers() {
+    let mut registrations: Vec<_> = inventory::iter::<WriterRegistration>.into_iter().collect();
+    registrations.sort_by_key(|registration| registration.key);
+    let mut factory = WRITER_FACTORY.lock().unwrap();
+    for registration in registrations {
+        factory.add_writer_proxy(registration.key, registration.priority, (registration.proxy)());
+    }
+}
+
+/// Adds registered progress displays to `PROGRESS_FACTORY`, sorted by key
+pub fn register_progress_displays() {
+    let mut registrations: Vec<_> = inventory::iter::<ProgressRegistration>.into_iter().collect();
+    registrations.sort_by_key(|registration| registration.key);
+    let mut factory = PROGRESS_FACTORY.lock().unwrap();
+    for registration in registrations {
+        factory.add_progress_constructor(registration.key, registration.constructor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_backends_are_collected() {
+        let readers: Vec<_> = inventory::iter::<ReaderRegistration>.into_iter().map(|r| r.key).collect();
+        let writers: Vec<_> = inventory::iter::<WriterRegistration>.into_iter().map(|w| w.key).collect();
+        let mut displays: Vec<_> = inventory::iter::<ProgressRegistration>.into_iter().map(|p| p.key).collect();
+        displays.sort();
+        assert!(readers.contains(&"file"));
+        assert!(writers.contains(&"file"));
+        assert!(displays.contains(&"none"));
+        assert_eq!(displays.contains(&"console"), FEATURES.contains(&"tui"));
+    }
+}
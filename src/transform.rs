@@ -0,0 +1,179 @@
+//!
+//! Stages which change data on its way from reader to writer, e.g. compress or hash it.
+//! `do_copy` chains them between reader and writer, every stage runs concurrently with
+//! the others and receives chunks over bounded channel.
+//!
+
+#[cfg(feature = "compression")]
+pub mod codec;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod hash;
+
+use std::ffi::{OsStr, OsString};
+#[cfg(feature = "encryption")]
+use std::path::Path;
+
+use async_trait::async_trait;
+
+use crate::arguments::Args;
+use crate::copy::DynBuffer;
+use crate::error::PcpResult;
+use crate::location::Location;
+#[cfg(feature = "compression")]
+use crate::compression::{decompressed_name, Codec};
+
+#[async_trait]
+pub trait Transform: Send {
+    ///
+    /// Processes chunk of data
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk`: data which previous stage has produced
+    ///
+    /// returns: PcpResult<Vec<DynBuffer>>: chunks for the next stage, may be empty while transform
+    /// holds data back, e.g. until compressed block is complete
+    ///
+    async fn process(&mut self, chunk: DynBuffer) -> PcpResult<Vec<DynBuffer>>;
+
+    ///
+    /// Completes stream after the last chunk, e.g. writes trailer of format
+    ///
+    /// returns: PcpResult<Vec<DynBuffer>>: chunks which transform still holds
+    ///
+    async fn finish(&mut self) -> PcpResult<Vec<DynBuffer>> {
+        Ok(Vec::new())
+    }
+
+    ///
+    /// Predicts amount of data transform produces, writers like tarballs need it in advance
+    ///
+    /// # Arguments
+    ///
+    /// * `size`: amount of data transform receives
+    ///
+    /// returns: Option<usize>: None if it is not known before data is processed
+    ///
+    fn output_size(&self, _size: usize) -> Option<usize> {
+        None
+    }
+
+    ///
+    /// Tells whether progress is measured by output of transform instead of source, like
+    /// plaintext of decrypted data
+    ///
+    /// # Arguments
+    ///
+    /// * `size`: amount of data transform receives
+    ///
+    /// returns: Option<usize>: expected amount of output which progress is measured by, None
+    /// if progress follows source
+    ///
+    fn progress_size(&self, _size: usize) -> Option<usize> {
+        None
+    }
+}
+
+///
+/// Checks whether transforms asked for are available in this build
+///
+/// returns: Result<(), String>: description of missing feature
+///
+pub(crate) fn check_args(args: &Args) -> Result<(), String> {
+    if (args.compress.is_some() || args.decompress) && !cfg!(feature = "compression") {
+        return Err(String::from("Compression is not available, pcp is built without compression feature"));
+    }
+    if args.encrypt_to.is_empty() && args.decrypt.is_none() {
+        return Ok(());
+    }
+    #[cfg(feature = "encryption")]
+    {
+        // Keys are checked and passphrase is asked before anything is copied
+        encryption::load_recipients(&args.encrypt_to)?;
+        if let Some(identity) = &args.decrypt {
+            encryption::load_identities(identity)?;
+        }
+        Ok(())
+    }
+    #[cfg(not(feature = "encryption"))]
+    Err(String::from("Encryption is not available, pcp is built without encryption feature"))
+}
+
+/// Whether data of files is changed while copying, so that files can not be renamed instead
+#[inline]
+pub(crate) fn is_active(args: &Args) -> bool {
+    args.compress.is_some() || args.decompress || !args.encrypt_to.is_empty() || args.decrypt.is_some()
+}
+
+///
+/// Gets name of destination file, which gets extensions of compression format and encryption
+/// or loses them
+///
+/// # Arguments
+///
+/// * `name`: name of source file
+/// * `args`: program-wide arguments
+///
+/// returns: OsString
+///
+pub(crate) fn destination_name(name: &OsStr, args: &Args) -> OsString {
+    #[allow(unused_mut)]
+    let mut name = name.to_os_string();
+    #[cfg(feature = "encryption")]
+    if args.decrypt.is_some() {
+        if let Some(stem) = Path::new(&name).extension()
+            .filter(|extension| *extension == encryption::EXTENSION)
+            .and_then(|_| Path::new(&name).file_stem()) {
+            name = stem.to_os_string();
+        }
+    }
+    #[cfg(feature = "compression")]
+    if let Some(format) = args.compress {
+        name.push(".");
+        name.push(Codec::from(format).extension());
+    } else if args.decompress {
+        name = decompressed_name(&name).unwrap_or(name);
+    }
+    #[cfg(feature = "encryption")]
+    if !args.encrypt_to.is_empty() {
+        name.push(".");
+        name.push(encryption::EXTENSION);
+    }
+    #[cfg(not(any(feature = "compression", feature = "encryption")))]
+    let _ = args;
+    name
+}
+
+///
+/// Makes transforms which arguments ask for, in order data passes them
+///
+/// # Arguments
+///
+/// * `source`: source file, which errors of transforms refer to
+/// * `args`: program-wide arguments
+///
+/// returns: PcpResult<Vec<Box<dyn Transform>>>: empty if data is copied as is
+///
+pub(crate) fn from_args(source: &Location, args: &Args) -> PcpResult<Vec<Box<dyn Transform>>> {
+    #[allow(unused_mut)]
+    let mut transforms: Vec<Box<dyn Transform>> = Vec::new();
+    // Data is decrypted before it is decompressed, and compressed before it is encrypted
+    #[cfg(feature = "encryption")]
+    if let Some(identity) = &args.decrypt {
+        transforms.push(Box::new(encryption::DecryptTransform::new(source, identity)?));
+    }
+    #[cfg(feature = "compression")]
+    if let Some(format) = args.compress {
+        transforms.push(Box::new(codec::CodecTransform::compress(source, format.into())?));
+    } else if args.decompress {
+        transforms.push(Box::new(codec::CodecTransform::decompress(source)));
+    }
+    #[cfg(feature = "encryption")]
+    if !args.encrypt_to.is_empty() {
+        transforms.push(Box::new(encryption::EncryptTransform::new(source, &args.encrypt_to)?));
+    }
+    #[cfg(not(any(feature = "compression", feature = "encryption")))]
+    let _ = (source, args);
+    Ok(transforms)
+}
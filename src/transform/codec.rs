@@ -0,0 +1,86 @@
+use async_trait::async_trait;
+
+use crate::compression::{Codec, CodecStage};
+use crate::copy::DynBuffer;
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
+use crate::transform::Transform;
+
+/// Largest chunk of output, so that memory taken by chain is bounded by number of staged chunks
+const CHUNK_SIZE: usize = 64 * 1024;
+
+///
+/// Compresses or decompresses data, see `CodecStage`
+///
+pub struct CodecTransform {
+    /// Source file, which errors refer to
+    url: Location,
+    stage: Option<CodecStage>,
+}
+
+impl CodecTransform {
+    /// Makes transform which compresses data of source with given format
+    pub fn compress(source: &Location, codec: Codec) -> PcpResult<CodecTransform> {
+        Ok(CodecTransform {
+            url: source.clone(),
+            stage: Some(CodecStage::compress(codec).map_err(|error| PcpError::from_io(source, error))?),
+        })
+    }
+
+    /// Makes transform which decompresses data of source, detecting its format
+    pub fn decompress(source: &Location) -> CodecTransform {
+        CodecTransform {
+            url: source.clone(),
+            stage: Some(CodecStage::decompress()),
+        }
+    }
+
+    fn to_chunks(&self, result: std::io::Result<Vec<u8>>) -> PcpResult<Vec<DynBuffer>> {
+        let data = result.map_err(|error| PcpError::from_io(&self.url, error))?;
+        // Decompressed data may be many times larger than chunk it comes from
+        Ok(data.chunks(CHUNK_SIZE).map(<[u8]>::to_vec).collect())
+    }
+}
+
+#[async_trait]
+impl Transform for CodecTransform {
+    async fn process(&mut self, chunk: DynBuffer) -> PcpResult<Vec<DynBuffer>> {
+        let result = match &mut self.stage {
+            Some(stage) => stage.process(&chunk),
+            None => return Err(PcpError::other(&self.url, "Stream is already finished")),
+        };
+        self.to_chunks(result)
+    }
+
+    async fn finish(&mut self) -> PcpResult<Vec<DynBuffer>> {
+        match self.stage.take() {
+            Some(stage) => self.to_chunks(stage.finish()),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::utils::runtime::{init_tokio, tokio_block_on};
+
+    #[test]
+    fn test_bounded_chunks() {
+        let data = vec![0; 16 * CHUNK_SIZE];
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        init_tokio();
+        let mut transform = CodecTransform::decompress(&Location::from_path("zeros.gz"));
+        let output = tokio_block_on(async {
+            let mut output = transform.process(compressed).await.unwrap();
+            output.extend(transform.finish().await.unwrap());
+            output
+        });
+        assert!(output.iter().all(|chunk| chunk.len() <= CHUNK_SIZE));
+        assert_eq!(output.concat(), data);
+    }
+}
@@ -0,0 +1,439 @@
+use std::io::{BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use age::secrecy::SecretString;
+use age::stream::StreamWriter;
+use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender};
+
+use crate::copy::DynBuffer;
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
+use crate::transform::Transform;
+use crate::utils::shared_buffer::SharedBuffer;
+use crate::utils::term::read_secret;
+
+/// Extension of encrypted files
+pub const EXTENSION: &str = "age";
+
+/// Recipient or identity which stands for passphrase instead of key
+const PASSPHRASE: &str = "passphrase";
+
+/// Environment variable which passphrase is taken from before asking user
+const PASSPHRASE_VARIABLE: &str = "PCP_PASSPHRASE";
+
+/// Size of plaintext chunks which decryption produces, the same as age payload chunks
+const PLAINTEXT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Nonce which starts payload of age file, as well as tag which follows each payload chunk
+const PAYLOAD_OVERHEAD: usize = 16;
+
+/// Number of encrypted chunks which wait for decryption thread
+const MAX_PENDING_CHUNKS: usize = 16;
+
+/// Passphrase is asked once and used for every copied file
+static PASSPHRASE_CACHE: OnceCell<String> = OnceCell::new();
+
+/// Recipients shared by transforms of all copied files
+pub(crate) type Recipients = Arc<Vec<Box<dyn age::Recipient + Send + Sync>>>;
+
+/// Recipients parsed by `load_recipients` along with values of `--encrypt-to` they came from
+static RECIPIENTS_CACHE: Mutex<Option<(Vec<String>, Recipients)>> = Mutex::new(None);
+
+/// Identities shared by transforms of all copied files
+pub(crate) type Identities = Arc<Vec<Box<dyn age::Identity + Send + Sync>>>;
+
+/// Identities loaded by `load_identities` along with value of `--decrypt` they came from
+static IDENTITIES_CACHE: Mutex<Option<(String, Identities)>> = Mutex::new(None);
+
+///
+/// Gets passphrase from environment or asks user for it. Passphrase for encryption is
+/// asked twice, so that typo does not make files unreadable.
+///
+fn passphrase(confirm: bool) -> Result<SecretString, String> {
+    let passphrase = PASSPHRASE_CACHE.get_or_try_init(|| {
+        if let Ok(passphrase) = std::env::var(PASSPHRASE_VARIABLE) {
+            return Ok(passphrase);
+        }
+        let passphrase = read_secret("Passphrase:").map_err(|error| error.to_string())?;
+        if confirm && read_secret("Confirm passphrase:").map_err(|error| error.to_string())? != passphrase {
+            return Err(String::from("Passphrases do not match"));
+        }
+        Ok(passphrase)
+    })?;
+    if passphrase.is_empty() {
+        return Err(String::from("Passphrase is empty"));
+    }
+    Ok(SecretString::from(passphrase.clone()))
+}
+
+///
+/// Parses recipients of `--encrypt-to`: `age1...` keys, files which list such keys one per line,
+/// or `passphrase`, which can not be combined with keys. Recipients are kept for transforms
+/// of copied files, so that key files are read once per job.
+///
+/// # Arguments
+///
+/// * `specs`: values of `--encrypt-to`
+///
+/// returns: Result<Recipients, String>: recipients or description of bad one
+///
+pub(crate) fn load_recipients(specs: &[String]) -> Result<Recipients, String> {
+    let recipients = Arc::new(parse_recipients(specs)?);
+    *RECIPIENTS_CACHE.lock().unwrap() = Some((specs.to_vec(), recipients.clone()));
+    Ok(recipients)
+}
+
+/// Gets recipients loaded for the same values of `--encrypt-to`, loading them if there are none
+fn recipients(specs: &[String]) -> Result<Recipients, String> {
+    if let Some((_, recipients)) = RECIPIENTS_CACHE.lock().unwrap().as_ref().filter(|(cached, _)| cached == specs) {
+        return Ok(recipients.clone());
+    }
+    load_recipients(specs)
+}
+
+fn parse_recipients(specs: &[String]) -> Result<Vec<Box<dyn age::Recipient + Send + Sync>>, String> {
+    let mut recipients: Vec<Box<dyn age::Recipient + Send + Sync>> = Vec::new();
+    for spec in specs {
+        if spec == PASSPHRASE {
+            if specs.len() > 1 {
+                return Err(String::from("Passphrase can not be combined with other recipients"));
+            }
+            recipients.push(Box::new(age::scrypt::Recipient::new(passphrase(true)?)));
+            continue;
+        }
+        let keys = match std::fs::read_to_string(spec) {
+            Ok(content) => content.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect(),
+            Err(_) => vec![spec.clone()],
+        };
+        for key in keys {
+            let recipient = key.parse::<age::x25519::Recipient>()
+                .map_err(|error| format!("Bad recipient {}: {}", key, error))?;
+            recipients.push(Box::new(recipient));
+        }
+    }
+    Ok(recipients)
+}
+
+///
+/// Loads identities of `--decrypt`: file like one made by `age-keygen`, or `passphrase`.
+/// Identities are kept for transforms of copied files, so that identity file is read and
+/// passphrase is asked once per job.
+///
+/// # Arguments
+///
+/// * `spec`: value of `--decrypt`
+///
+/// returns: Result<Identities, String>: identities or description of error
+///
+pub(crate) fn load_identities(spec: &str) -> Result<Identities, String> {
+    let identities = Arc::new(parse_identities(spec)?);
+    *IDENTITIES_CACHE.lock().unwrap() = Some((spec.to_string(), identities.clone()));
+    Ok(identities)
+}
+
+/// Gets identities loaded for the same value of `--decrypt`, loading them if there are none
+fn identities(spec: &str) -> Result<Identities, String> {
+    if let Some((_, identities)) = IDENTITIES_CACHE.lock().unwrap().as_ref().filter(|(cached, _)| cached == spec) {
+        return Ok(identities.clone());
+    }
+    load_identities(spec)
+}
+
+fn parse_identities(spec: &str) -> Result<Vec<Box<dyn age::Identity + Send + Sync>>, String> {
+    if spec == PASSPHRASE {
+        return Ok(vec![Box::new(age::scrypt::Identity::new(passphrase(false)?))]);
+    }
+    let content = std::fs::read_to_string(spec)
+        .map_err(|error| format!("Can not read identity file {}: {}", spec, error))?;
+    let mut identities: Vec<Box<dyn age::Identity + Send + Sync>> = Vec::new();
+    for line in content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let identity = line.parse::<age::x25519::Identity>()
+            .map_err(|error| format!("Bad identity file {}: {}", spec, error))?;
+        identities.push(Box::new(identity));
+    }
+    if identities.is_empty() {
+        return Err(format!("Bad identity file {}: no identities found", spec));
+    }
+    Ok(identities)
+}
+
+///
+/// Encrypts data in age format, which `age` tool decrypts
+///
+pub struct EncryptTransform {
+    /// Source file, which errors refer to
+    url: Location,
+    writer: Option<StreamWriter<SharedBuffer>>,
+    output: SharedBuffer,
+}
+
+impl EncryptTransform {
+    ///
+    /// Makes transform which encrypts data of source to recipients
+    ///
+    /// # Arguments
+    ///
+    /// * `source`: source file
+    /// * `specs`: values of `--encrypt-to`
+    ///
+    /// returns: PcpResult<EncryptTransform>
+    ///
+    pub fn new(source: &Location, specs: &[String]) -> PcpResult<EncryptTransform> {
+        let recipients = recipients(specs).map_err(|error| PcpError::other(source, error))?;
+        let recipients = recipients.iter().map(|recipient| recipient.as_ref() as &dyn age::Recipient);
+        let encryptor = age::Encryptor::with_recipients(recipients)
+            .map_err(|error| PcpError::other(source, error.to_string()))?;
+        let output = SharedBuffer::default();
+        Ok(EncryptTransform {
+            url: source.clone(),
+            writer: Some(encryptor.wrap_output(output.clone()).map_err(|error| PcpError::from_io(source, error))?),
+            output,
+        })
+    }
+}
+
+#[async_trait]
+impl Transform for EncryptTransform {
+    async fn process(&mut self, chunk: DynBuffer) -> PcpResult<Vec<DynBuffer>> {
+        let writer = self.writer.as_mut().ok_or_else(|| PcpError::other(&self.url, "Stream is already finished"))?;
+        writer.write_all(&chunk).map_err(|error| PcpError::from_io(&self.url, error))?;
+        let data = self.output.take();
+        Ok(if data.is_empty() { Vec::new() } else { vec![data] })
+    }
+
+    async fn finish(&mut self) -> PcpResult<Vec<DynBuffer>> {
+        if let Some(writer) = self.writer.take() {
+            writer.finish().map_err(|error| PcpError::from_io(&self.url, error))?;
+        }
+        Ok(vec![self.output.take()])
+    }
+}
+
+/// Encrypted stream which decryption thread reads from chunks sent to it
+struct ChunkReader {
+    chunks: Receiver<DynBuffer>,
+    chunk: DynBuffer,
+    position: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                // Sender is dropped when stream ends
+                None => return Ok(0),
+            }
+        }
+        let size = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..size].copy_from_slice(&self.chunk[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+/// Decrypts stream which comes from `chunks` and sends plaintext to `output`
+fn decrypt_stream(identities: Identities, chunks: Receiver<DynBuffer>,
+                  output: &UnboundedSender<Result<DynBuffer, String>>) -> Result<(), String> {
+    let input = BufReader::new(ChunkReader { chunks, chunk: DynBuffer::new(), position: 0 });
+    let decryptor = age::Decryptor::new_buffered(input).map_err(|error| error.to_string())?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|identity| identity.as_ref() as _))
+        .map_err(|error| error.to_string())?;
+    let mut buffer = vec![0; PLAINTEXT_CHUNK_SIZE];
+    loop {
+        let size = reader.read(&mut buffer).map_err(|error| error.to_string())?;
+        if size == 0 {
+            return Ok(());
+        }
+        if output.send(Ok(buffer[..size].to_vec())).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+///
+/// Decrypts data in age format. Decryptor of age pulls data from reader, so it runs on
+/// a thread of its own which receives encrypted chunks and sends back plaintext. Transform
+/// only awaits channels, so it does not block runtime. Progress is measured by plaintext.
+///
+pub struct DecryptTransform {
+    /// Source file, which errors refer to
+    url: Location,
+    /// None after stream is finished, which ends encrypted stream for decryption thread
+    input: Option<Sender<DynBuffer>>,
+    output: UnboundedReceiver<Result<DynBuffer, String>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DecryptTransform {
+    ///
+    /// Makes transform which decrypts data of source
+    ///
+    /// # Arguments
+    ///
+    /// * `source`: source file
+    /// * `spec`: value of `--decrypt`
+    ///
+    /// returns: PcpResult<DecryptTransform>
+    ///
+    pub fn new(source: &Location, spec: &str) -> PcpResult<DecryptTransform> {
+        let identities = identities(spec).map_err(|error| PcpError::other(source, error))?;
+        let (input, chunks) = tokio::sync::mpsc::channel(MAX_PENDING_CHUNKS);
+        let (output_tx, output) = tokio::sync::mpsc::unbounded_channel();
+        let thread = std::thread::spawn(move || {
+            if let Err(error) = decrypt_stream(identities, chunks, &output_tx) {
+                let _ = output_tx.send(Err(error));
+            }
+        });
+        Ok(DecryptTransform { url: source.clone(), input: Some(input), output, thread: Some(thread) })
+    }
+
+    /// Collects plaintext which decryption thread has produced so far, waiting for all of it if asked
+    async fn collect(&mut self, wait: bool) -> PcpResult<Vec<DynBuffer>> {
+        let mut chunks = Vec::new();
+        loop {
+            let result = if wait {
+                self.output.recv().await.ok_or(TryRecvError::Disconnected)
+            } else {
+                self.output.try_recv()
+            };
+            match result {
+                Ok(Ok(chunk)) => chunks.push(chunk),
+                Ok(Err(error)) => return Err(PcpError::Protocol(self.url.to_string(), error)),
+                Err(_) => return Ok(chunks),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for DecryptTransform {
+    async fn process(&mut self, chunk: DynBuffer) -> PcpResult<Vec<DynBuffer>> {
+        let input = self.input.as_ref().ok_or_else(|| PcpError::other(&self.url, "Stream is already finished"))?;
+        if input.send(chunk).await.is_err() {
+            // Thread stops only after error, which is waiting in output
+            self.collect(true).await?;
+            return Err(PcpError::other(&self.url, "Decryption has stopped"));
+        }
+        self.collect(false).await
+    }
+
+    async fn finish(&mut self) -> PcpResult<Vec<DynBuffer>> {
+        self.input = None;
+        let chunks = self.collect(true).await;
+        // Output is closed, so thread has already returned
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        chunks
+    }
+
+    fn progress_size(&self, size: usize) -> Option<usize> {
+        // Header is not known in advance, so it is counted as payload
+        let payload = size.saturating_sub(PAYLOAD_OVERHEAD);
+        let chunks = payload.div_ceil(PLAINTEXT_CHUNK_SIZE + PAYLOAD_OVERHEAD);
+        Some(payload - chunks * PAYLOAD_OVERHEAD)
+    }
+}
+
+impl Drop for DecryptTransform {
+    fn drop(&mut self) {
+        // Thread waits for the rest of stream otherwise
+        self.input = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::runtime::{init_tokio, tokio_block_on};
+
+    async fn run(transform: &mut dyn Transform, chunks: Vec<DynBuffer>) -> PcpResult<DynBuffer> {
+        let mut output = Vec::new();
+        for chunk in chunks {
+            output.extend(transform.process(chunk).await?);
+        }
+        output.extend(transform.finish().await?);
+        Ok(output.concat())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let key = age::x25519::Identity::generate();
+        let identity = std::env::temp_dir().join(format!("pcp-age-identity-{}", std::process::id()));
+        std::fs::write(&identity, format!("# public key: {}\n{}\n", key.to_public(),
+                                          age::secrecy::ExposeSecret::expose_secret(&key.to_string()))).unwrap();
+        let source = Location::from_path("dump.sql");
+        let plaintext: Vec<DynBuffer> = (0..100u8).map(|index| vec![index; 10_000]).collect();
+
+        init_tokio();
+        let encrypted = tokio_block_on(async {
+            let mut transform = EncryptTransform::new(&source, &[key.to_public().to_string()]).unwrap();
+            run(&mut transform, plaintext.clone()).await.unwrap()
+        });
+        assert!(encrypted.starts_with(b"age-encryption.org/v1\n"));
+        // Age file can be decrypted by the library as well as by transform
+        let decryptor = age::Decryptor::new(&encrypted[..]).unwrap();
+        let mut decrypted = Vec::new();
+        decryptor.decrypt(std::iter::once(&key as _)).unwrap().read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext.concat());
+
+        let chunks: Vec<DynBuffer> = encrypted.chunks(4096).map(|chunk| chunk.to_vec()).collect();
+        let decrypted = tokio_block_on(async {
+            let mut transform = DecryptTransform::new(&source, identity.to_str().unwrap()).unwrap();
+            run(&mut transform, chunks.clone()).await.unwrap()
+        });
+        assert_eq!(decrypted, plaintext.concat());
+
+        let mut corrupted = chunks;
+        corrupted.last_mut().unwrap()[0] ^= 1;
+        assert!(tokio_block_on(async {
+            let mut transform = DecryptTransform::new(&source, identity.to_str().unwrap()).unwrap();
+            run(&mut transform, corrupted).await
+        }).is_err());
+        std::fs::remove_file(&identity).unwrap();
+    }
+
+    #[test]
+    fn test_plaintext_progress() {
+        let key = age::x25519::Identity::generate();
+        let directory = std::env::temp_dir().join(format!("pcp-age-progress-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("identity"),
+                       format!("{}\n", age::secrecy::ExposeSecret::expose_secret(&key.to_string()))).unwrap();
+        let plaintext: Vec<u8> = (0..200_000u32).map(|index| (index % 251) as u8).collect();
+        std::fs::write(directory.join("plain"), &plaintext).unwrap();
+        let encrypted = crate::CopyJob::new(directory.join("secret.age"))
+            .source(directory.join("plain"))
+            .options(crate::Args { encrypt_to: vec![key.to_public().to_string()], no_progress: true,
+                                   ..crate::Args::default() })
+            .run();
+        let progress = Arc::new(Mutex::new((0, 0)));
+        let reported = progress.clone();
+        let decrypted = crate::CopyJob::new(directory.join("restored"))
+            .source(directory.join("secret.age"))
+            .options(crate::Args { decrypt: Some(directory.join("identity").display().to_string()),
+                                   ..crate::Args::default() })
+            .on_progress(move |_, bytes_out, bytes_total| *reported.lock().unwrap() = (bytes_out, bytes_total))
+            .run();
+        let restored = std::fs::read(directory.join("restored")).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(encrypted.is_success() && decrypted.is_success());
+        assert_eq!(restored, plaintext);
+        let (bytes_out, bytes_total) = *progress.lock().unwrap();
+        assert_eq!(bytes_out, plaintext.len());
+        // Estimate counts header of file as plaintext
+        assert!(bytes_total >= plaintext.len() && bytes_total - plaintext.len() < 1024);
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+use crate::copy::DynBuffer;
+use crate::error::PcpResult;
+use crate::manifest::hex_digest;
+use crate::transform::Transform;
+
+/// Hex SHA256 of stream, which appears when stream is finished
+pub type SharedDigest = Arc<Mutex<Option<String>>>;
+
+///
+/// Computes SHA256 of data passing through, e.g. of written data for manifest and
+/// verification of moved files
+///
+pub struct HashTransform {
+    hasher: Sha256,
+    digest: SharedDigest,
+}
+
+impl HashTransform {
+    ///
+    /// Makes hashing transform
+    ///
+    /// returns: (HashTransform, SharedDigest): transform and place where its digest appears
+    ///
+    pub fn new() -> (HashTransform, SharedDigest) {
+        let digest = SharedDigest::default();
+        (HashTransform { hasher: Sha256::new(), digest: digest.clone() }, digest)
+    }
+}
+
+#[async_trait]
+impl Transform for HashTransform {
+    async fn process(&mut self, chunk: DynBuffer) -> PcpResult<Vec<DynBuffer>> {
+        self.hasher.update(&chunk);
+        Ok(vec![chunk])
+    }
+
+    async fn finish(&mut self) -> PcpResult<Vec<DynBuffer>> {
+        *self.digest.lock().unwrap() = Some(hex_digest(std::mem::take(&mut self.hasher)));
+        Ok(Vec::new())
+    }
+
+    #[inline]
+    fn output_size(&self, size: usize) -> Option<usize> {
+        Some(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::runtime::{init_tokio, tokio_block_on};
+
+    #[test]
+    fn test_hash_transform() {
+        init_tokio();
+        let (mut transform, digest) = HashTransform::new();
+        let output = tokio_block_on(async {
+            let mut output = transform.process(b"hello ".to_vec()).await.unwrap();
+            output.extend(transform.process(b"world".to_vec()).await.unwrap());
+            output.extend(transform.finish().await.unwrap());
+            output
+        });
+        assert_eq!(output.concat(), b"hello world");
+        assert_eq!(digest.lock().unwrap().as_deref(),
+                   Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"));
+    }
+}
@@ -3,6 +3,9 @@ use std::time::{SystemTime, UNIX_EPOCH};
 pub mod runtime;
 pub mod generic_iterator;
 pub mod term;
+pub mod stat;
+#[cfg(any(feature = "compression", feature = "encryption"))]
+pub(crate) mod shared_buffer;
 
 ///
 /// Gets precise time in milliseconds
@@ -63,10 +66,81 @@ pub fn safe_string_trim_right(s: String, n: usize) -> String{
     result
 }
 
+///
+/// Formats amount of bytes with binary units
+///
+/// # Arguments
+///
+/// * `bytes`: amount of bytes
+///
+/// returns: String: human-readable size, e.g. `1.5 MiB`
+///
+pub fn format_size(bytes: usize) -> String{
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes < 1024{
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1{
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+///
+/// Parses amount of bytes with optional suffix: `K`, `M`, `G`, `T` and their `KiB` forms are
+/// powers of 1024, while `KB`, `MB`, `GB` and `TB` are powers of 1000
+///
+/// # Arguments
+///
+/// * `size`: size like `4096`, `50M` or `1GB`
+///
+/// returns: Result<usize, String>: amount of bytes or description of bad size
+///
+pub fn parse_size(size: &str) -> Result<usize, String>{
+    let size = size.trim();
+    let digits = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let number: usize = size[..digits].parse().map_err(|_| format!("Bad size: {}", size))?;
+    let multiplier: usize = match size[digits..].to_ascii_uppercase().as_str(){
+        "" | "B" => 1,
+        "K" | "KIB" => 1 << 10,
+        "M" | "MIB" => 1 << 20,
+        "G" | "GIB" => 1 << 30,
+        "T" | "TIB" => 1 << 40,
+        "KB" => 1000,
+        "MB" => 1000 * 1000,
+        "GB" => 1000 * 1000 * 1000,
+        "TB" => 1000 * 1000 * 1000 * 1000,
+        suffix => return Err(format!("Unknown size suffix: {}", suffix)),
+    };
+    number.checked_mul(multiplier).ok_or_else(|| format!("Size is too large: {}", size))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("50M"), Ok(50 * 1024 * 1024));
+        assert_eq!(parse_size("2kib"), Ok(2048));
+        assert_eq!(parse_size("1GB"), Ok(1_000_000_000));
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
     #[test]
     fn test_safe_string_trim_left_basic() {
         let input = String::from("Hello, World!");
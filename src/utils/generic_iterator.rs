@@ -1,3 +1,5 @@
+use crate::error::PcpError;
+
 ///
 /// Simplified iterator for primitive types
 ///
@@ -11,6 +13,20 @@ pub trait GenericIterator<T>{
     ///    * None if there is no more elements in sequence
     /// 
     fn internal_next(&mut self) -> Option<T>;
+
+    ///
+    /// Tells hierarchical iterator not to descend into directory which it produced last.
+    /// Flat iterators may leave this as is.
+    ///
+    fn skip_directory(&mut self) {}
+
+    ///
+    /// Takes errors which happened since last call, e.g. directories which could not be listed.
+    /// Iterator keeps going after them.
+    ///
+    fn take_errors(&mut self) -> Vec<PcpError> {
+        Vec::new()
+    }
 }
 
 impl<T> Iterator for dyn GenericIterator<T> {
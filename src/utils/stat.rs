@@ -0,0 +1,28 @@
+use std::os::unix::fs::PermissionsExt;
+use std::time::SystemTime;
+
+///
+/// Backend-independent metadata of file or directory
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectStat {
+    /// Size of object in bytes
+    pub size: usize,
+    /// Time of last modification, if backend knows it
+    pub modified: Option<SystemTime>,
+    /// Whether object is directory
+    pub is_directory: bool,
+    /// Unix permission bits, if backend supports them
+    pub permissions: Option<u32>,
+}
+
+impl From<std::fs::Metadata> for ObjectStat {
+    fn from(metadata: std::fs::Metadata) -> ObjectStat {
+        ObjectStat {
+            size: metadata.len() as usize,
+            modified: metadata.modified().ok(),
+            is_directory: metadata.is_dir(),
+            permissions: Some(metadata.permissions().mode()),
+        }
+    }
+}
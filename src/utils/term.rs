@@ -1,13 +1,62 @@
-use termion::terminal_size;
+use std::io::Write;
 
 /// Cleans last line of terminal
+#[cfg(feature = "tui")]
 #[inline]
 pub fn flush() {
-    let (size_rows, _) = terminal_size()
+    let (size_rows, _) = termion::terminal_size()
         .expect("Can not read terminal size");
     print!("\r");
     for _ in 0..size_rows - 1{
         print!(" ")
     }
     print!("\r");
-}
\ No newline at end of file
+}
+///
+/// Asks user a yes/no question on the terminal
+///
+/// # Arguments
+///
+/// * `question`: question to print before reading the answer
+///
+/// returns: bool: whether user answered yes
+///
+pub fn confirm(question: &str) -> bool {
+    print!("{} ", question);
+    if std::io::stdout().flush().is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+///
+/// Asks user for secret like passphrase on the terminal, without echoing it when console
+/// display is compiled in
+///
+/// # Arguments
+///
+/// * `prompt`: text to print before reading the secret
+///
+/// returns: std::io::Result<String>: secret without trailing newline
+///
+pub fn read_secret(prompt: &str) -> std::io::Result<String> {
+    print!("{} ", prompt);
+    std::io::stdout().flush()?;
+    #[cfg(feature = "tui")]
+    {
+        use termion::input::TermRead;
+        let secret = std::io::stdin().read_passwd(&mut std::io::stdout())?;
+        println!();
+        secret.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "No secret was entered"))
+    }
+    #[cfg(not(feature = "tui"))]
+    {
+        let mut secret = String::new();
+        std::io::stdin().read_line(&mut secret)?;
+        Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
@@ -1,45 +1,169 @@
 pub mod file;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "archive")]
+pub mod zip;
+#[cfg(feature = "block")]
+pub mod block;
+
+use std::ffi::OsString;
+use std::path::Path;
 
 use async_trait::async_trait;
 use crate::copy::DynBuffer;
-use crate::factories::WRITER_FACTORY;
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
+use crate::utils::stat::ObjectStat;
 
 #[async_trait]
 pub trait Writer{
-    /// Creates writer instance for URL
-    fn new(url: &str) -> Self where Self: Sized;
+    /// Creates writer instance for URL, error if object can not be created
+    fn new(url: &Location) -> PcpResult<Self> where Self: Sized;
+
+    ///
+    /// Opens existing object without truncating it, so that writing starts at given offset
+    /// and the rest of object is kept. Object is created if it does not exist.
+    ///
+    /// # Arguments
+    /// * url: &Location: URL of object
+    /// * offset: usize: offset of the first written byte
+    ///
+    /// # Returns
+    /// PcpResult<Self>: error if object can not be opened or backend can not write at offset
+    fn open_at(url: &Location, _offset: usize) -> PcpResult<Self> where Self: Sized {
+        Err(PcpError::Unsupported(url.to_string(), String::from("Writing at offset is not supported")))
+    }
 
     ///
     /// Checks that Writer can write by URL
     ///
     /// # Arguments
-    /// * url: &str: URL to check
+    /// * url: &Location: URL to check
     ///
     /// # Returns
     /// bool:
     ///   * true if URL is writable
     ///   * false otherwise
-    fn can_write(url: &str) -> bool where Self: Sized;
+    fn can_write(url: &Location) -> bool where Self: Sized;
     
     ///
     /// Checks that given URL is directory
     /// 
     /// # Arguments
-    /// * url: &str: URL to check
+    /// * url: &Location: URL to check
     /// 
     /// # Returns
     /// bool:
     ///   * true if URL is directory
     ///   * false otherwise
-    fn is_directory(url: &str) -> bool where Self: Sized;
+    fn is_directory(url: &Location) -> bool where Self: Sized;
     
     ///
     /// Creates given path(equivalent to mkdir -p)
     /// 
     /// # Arguments
-    /// * url: &str: path to create
+    /// * url: &Location: path to create
     /// 
-    fn make_directory(url: &str) where Self: Sized;
+    /// # Returns
+    /// PcpResult<()>: error if directory can not be created
+    fn make_directory(url: &Location) -> PcpResult<()> where Self: Sized;
+
+    ///
+    /// Creates directory which receives contents of source directory. Formats which store
+    /// metadata along with directory, like archives, take it from source.
+    ///
+    /// # Arguments
+    /// * url: &Location: path to create
+    /// * source: &ObjectStat: metadata of source directory
+    ///
+    /// # Returns
+    /// PcpResult<()>: error if directory can not be created
+    fn make_directory_like(url: &Location, _source: &ObjectStat) -> PcpResult<()> where Self: Sized {
+        Self::make_directory(url)
+    }
+
+    ///
+    /// Gets metadata of existing object at given URL
+    ///
+    /// # Arguments
+    /// * url: &Location: URL to check
+    ///
+    /// # Returns
+    /// Option<ObjectStat>:
+    ///   * Some(ObjectStat) if object exists
+    ///   * None otherwise
+    fn stat(url: &Location) -> Option<ObjectStat> where Self: Sized;
+
+    ///
+    /// Renames object within the same backend, replacing target if it exists
+    ///
+    /// # Arguments
+    /// * from: &Location: URL of existing object
+    /// * to: &Location: new URL of object
+    ///
+    /// # Returns
+    /// std::io::Result<()>: whether object was renamed
+    fn rename(from: &Location, to: &Location) -> std::io::Result<()> where Self: Sized;
+
+    ///
+    /// Tells how files moved to object are verified before their sources are removed
+    ///
+    /// # Arguments
+    /// * url: &Location: URL of destination
+    ///
+    /// # Returns
+    /// PcpResult<bool>: true if writer reads data back itself when it is closed, false if
+    /// destination is read again; error if moved data can not be verified
+    fn verifies_on_close(_url: &Location) -> PcpResult<bool> where Self: Sized {
+        Ok(false)
+    }
+
+    ///
+    /// Gets object which holds given URL and is replaced as a whole when anything is first
+    /// written under it, like archive which is not open yet. Overwrite policy and backups are
+    /// applied to such object before it is replaced.
+    ///
+    /// # Arguments
+    /// * url: &Location: URL of destination
+    ///
+    /// # Returns
+    /// Option<Location>: URL of container, None if objects under URL are written one by one
+    fn container(_url: &Location) -> Option<Location> where Self: Sized {
+        None
+    }
+
+    ///
+    /// Lists names of objects directly in directory, e.g. to find numbered backups
+    ///
+    /// # Arguments
+    /// * url: &Location: URL of directory
+    ///
+    /// # Returns
+    /// std::io::Result<Vec<OsString>>: names of objects, Unsupported error if backend can not list them
+    fn list_directory(_url: &Location) -> std::io::Result<Vec<OsString>> where Self: Sized {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Listing directories is not supported"))
+    }
+
+    ///
+    /// Removes file or empty directory
+    ///
+    /// # Arguments
+    /// * url: &Location: URL of object to remove
+    ///
+    /// # Returns
+    /// std::io::Result<()>: whether object was removed
+    fn remove(url: &Location) -> std::io::Result<()> where Self: Sized;
+
+    ///
+    /// Applies modification time and permissions of source to written object
+    ///
+    /// # Arguments
+    /// * url: &Location: URL of written object
+    /// * stat: &ObjectStat: metadata of source, fields which are None are left intact
+    ///
+    /// # Returns
+    /// std::io::Result<()>: whether metadata was applied
+    fn set_metadata(url: &Location, stat: &ObjectStat) -> std::io::Result<()> where Self: Sized;
     
     ///
     /// Joins base path with relative path
@@ -49,21 +173,70 @@ pub trait Writer{
     /// * `path` relative path in directory
     /// 
     /// # Examples
-    /// ```
-    /// let base = "foo/bar";
-    /// let relative = "folder/file";
+    /// ```ignore
+    /// let base = Location::parse("foo/bar");
+    /// let relative = Path::new("folder/file");
     /// 
-    /// assert_eq!(MyWriter::join_path(base, relative), "foo/bar/folder/file");
+    /// assert_eq!(MyWriter::join_path(&base, relative).to_string(), "foo/bar/folder/file");
     /// ```
     ///
-    /// ```
-    /// let base = "ftp://my-server/foo/bar";
-    /// let relative = "folder/file";
+    /// ```ignore
+    /// let base = Location::parse("ftp://my-server/foo/bar");
+    /// let relative = Path::new("folder/file");
     ///
-    /// assert_eq!(MyWriter::join_path(base, relative), "ftp://my-server/foo/bar/folder/file");
+    /// assert_eq!(MyWriter::join_path(&base, relative).to_string(), "ftp://my-server/foo/bar/folder/file");
     /// ```
-    fn join_path(base: &str, path: &str) -> String where Self: Sized;
+    fn join_path(base: &Location, path: &Path) -> Location where Self: Sized;
+
+    ///
+    /// Completes everything written under destination, e.g. trailer of archive. Called once
+    /// after all sources are copied.
+    ///
+    /// # Arguments
+    /// * url: &Location: destination given by user
+    ///
+    /// # Returns
+    /// PcpResult<()>: error if written data can not be completed
+    fn finish(_url: &Location) -> PcpResult<()> where Self: Sized {
+        Ok(())
+    }
+
+    ///
+    /// Receives metadata of source before size and the first chunk of object. Formats which
+    /// store metadata ahead of data, like tar, keep it for header of object.
+    ///
+    /// # Arguments
+    /// * stat: &ObjectStat: metadata of source
+    ///
+    /// # Returns
+    /// PcpResult<()>: error if metadata can not be stored
+    fn set_source_metadata(&mut self, _stat: &ObjectStat) -> PcpResult<()> {
+        Ok(())
+    }
+
+    ///
+    /// Receives size of object before its first chunk is written. Formats which store size
+    /// ahead of data, like tar, can not write anything without it.
+    ///
+    /// # Arguments
+    /// * size: usize: size reported by `Reader::get_size`
+    ///
+    /// # Returns
+    /// PcpResult<()>: error if object of this size can not be written
+    fn set_size(&mut self, _size: usize) -> PcpResult<()> {
+        Ok(())
+    }
     
+    ///
+    /// Completes object after its last chunk is written, e.g. writes data which writer
+    /// buffers. Not called if copying fails.
+    ///
+    /// # Returns
+    /// PcpResult<()>: error if object can not be completed
+    fn close(&mut self) -> PcpResult<()> {
+        Ok(())
+    }
+
     ///
     /// Writes chunk of given size and returns whether write was successful
     /// 
@@ -73,17 +246,11 @@ pub trait Writer{
     /// * size: usize: number of bytes to write from chunk
     /// 
     /// # Example
-    /// ```
-    /// let mut writer = MyWriter("scheme://path/to/file");
+    /// ```ignore
+    /// let mut writer = MyWriter::new("scheme://path/to/file")?;
     /// let buffer = DynBuffer::make_buffer(42);
     /// 
-    /// writer.write_chunk(&buffer, 42);
+    /// writer.write_chunk(&buffer, 42).await?;
     /// ```
-    async fn write_chunk(&mut self, chunk: &DynBuffer, size: usize) -> std::io::Result<usize>;
+    async fn write_chunk(&mut self, chunk: &DynBuffer, size: usize) -> PcpResult<usize>;
 }
-
-//FUTURE: refactor this to be done via macros
-pub fn register_writers(){
-    let mut factory = WRITER_FACTORY.lock().unwrap();
-    factory.add_writer::<crate::writer::file::FileWriter>("file");
-}
\ No newline at end of file
@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+
+use crate::copy::DynBuffer;
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
+use crate::reader::archive::{split_archive_name, ArchiveFormat, ArchivePath};
+use crate::utils::stat::ObjectStat;
+use crate::writer::file::FileWriter;
+use crate::writer::Writer;
+
+/// Size of tar blocks, data of members is padded to it
+const BLOCK_SIZE: usize = 512;
+/// Permissions of members which sources have none
+pub(crate) const FILE_MODE: u32 = 0o644;
+pub(crate) const DIRECTORY_MODE: u32 = 0o755;
+
+///
+/// Tarball which is being written, it stays open until `finish` is called for destination
+///
+struct OpenArchive {
+    builder: tar::Builder<ArchiveOutput>,
+    /// Members written so far
+    members: HashMap<PathBuf, ObjectStat>,
+}
+
+enum ArchiveOutput {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "compression")]
+    Compressed(Box<dyn crate::compression::CodecWriter>),
+}
+
+lazy_static! {
+    static ref OPEN_ARCHIVES: Mutex<HashMap<PathBuf, OpenArchive>> = Mutex::new(HashMap::new());
+}
+
+impl ArchiveOutput {
+    /// Creates archive file, compressed according to its extension
+    fn create(archive: &Path) -> std::io::Result<ArchiveOutput> {
+        let file = File::create(archive)?;
+        #[cfg(feature = "compression")]
+        if let Some(codec) = crate::compression::Codec::from_file_name(archive.as_os_str()) {
+            return Ok(ArchiveOutput::Compressed(codec.encoder(BufWriter::new(file))?));
+        }
+        // Without compression such archive would get misleading name
+        #[cfg(not(feature = "compression"))]
+        if archive.extension().is_some_and(|extension| extension != "tar") {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported,
+                                           "Compressed archives need compression feature"));
+        }
+        Ok(ArchiveOutput::Plain(BufWriter::new(file)))
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            ArchiveOutput::Plain(mut output) => output.flush(),
+            #[cfg(feature = "compression")]
+            ArchiveOutput::Compressed(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl Write for ArchiveOutput {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ArchiveOutput::Plain(output) => output.write(buffer),
+            #[cfg(feature = "compression")]
+            ArchiveOutput::Compressed(encoder) => encoder.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ArchiveOutput::Plain(output) => output.flush(),
+            #[cfg(feature = "compression")]
+            ArchiveOutput::Compressed(encoder) => encoder.flush(),
+        }
+    }
+}
+
+///
+/// Splits destination into archive and member. Plain path named like archive, e.g. `out.tar.zst`,
+/// has no member: it is archive root while archive is written and ordinary file otherwise.
+///
+/// # Arguments
+///
+/// * `url`: destination
+/// * `format`: format which writer produces, destinations of other formats are rejected
+///
+/// returns: Option<(PathBuf, Option<PathBuf>)>: path of archive and member
+///
+pub(crate) fn split_destination(url: &Location, format: ArchiveFormat) -> Option<(PathBuf, Option<PathBuf>)> {
+    if let Some(path) = ArchivePath::parse(url) {
+        return (path.format == format).then_some((path.archive, Some(path.member)));
+    }
+    let path = url.local_path().filter(|_| url.scheme().is_none())?;
+    let (name_format, _) = split_archive_name(path.file_name()?)?;
+    (name_format == format).then(|| (path.to_path_buf(), None))
+}
+
+///
+/// Makes location of member in archive destination, members of plain archive names are
+/// separated with `//`
+///
+pub(crate) fn join_member_path(base: &Location, path: &Path) -> Location {
+    match ArchivePath::parse(base) {
+        Some(archive_path) => archive_path.member_location(base, &archive_path.member.join(path)),
+        None => {
+            let mut member = OsString::from(base.path());
+            member.push("//");
+            member.push(path);
+            base.with_path(member)
+        }
+    }
+}
+
+/// Runs operation on open archive, creating it first if it is not open yet
+fn with_archive<T>(archive: &Path, operation: impl FnOnce(&mut OpenArchive) -> std::io::Result<T>)
+                   -> std::io::Result<T> {
+    let mut archives = OPEN_ARCHIVES.lock().unwrap();
+    if !archives.contains_key(archive) {
+        archives.insert(archive.to_path_buf(), OpenArchive {
+            builder: tar::Builder::new(ArchiveOutput::create(archive)?),
+            members: HashMap::new(),
+        });
+    }
+    operation(archives.get_mut(archive).unwrap())
+}
+
+#[inline]
+fn is_open(archive: &Path) -> bool {
+    OPEN_ARCHIVES.lock().unwrap().contains_key(archive)
+}
+
+/// Creates temporary file for data of member, which is removed as soon as it is closed
+fn spool_file() -> std::io::Result<File> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!("pcp-spool-{}-{}", std::process::id(),
+                                                 COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let file = File::options().read(true).write(true).create_new(true).open(&path)?;
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+///
+/// Appends header of member. Data of regular files is written after it by `TarWriter`.
+///
+fn append_header(archive: &mut OpenArchive, member: &Path, stat: ObjectStat) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    if stat.is_directory {
+        header.set_entry_type(tar::EntryType::Directory);
+    }
+    header.set_size(stat.size as u64);
+    header.set_mode(stat.permissions.unwrap_or(FILE_MODE));
+    let modified = stat.modified.and_then(|time| time.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+    header.set_mtime(modified.as_secs());
+    // Names of directories end with separator, as tar does it
+    let name = if stat.is_directory { member.join("") } else { member.to_path_buf() };
+    // Builder writes long names as separate entries and then header itself, data is provided later
+    archive.builder.append_data(&mut header, name, std::io::empty())?;
+    archive.members.insert(member.to_path_buf(), stat);
+    Ok(())
+}
+
+///
+/// Gets metadata for header of member, taking what is known from source
+///
+/// # Arguments
+///
+/// * `source`: metadata of source, if there is one
+/// * `size`: size of member data
+/// * `is_directory`: whether member is directory
+///
+/// returns: ObjectStat
+///
+pub(crate) fn member_stat(source: Option<&ObjectStat>, size: usize, is_directory: bool) -> ObjectStat {
+    let default_mode = if is_directory { DIRECTORY_MODE } else { FILE_MODE };
+    ObjectStat {
+        size,
+        modified: source.and_then(|stat| stat.modified).or_else(|| Some(SystemTime::now())),
+        is_directory,
+        // File type bits are not part of permissions in archives
+        permissions: Some(source.and_then(|stat| stat.permissions).map_or(default_mode, |mode| mode & 0o7777)),
+    }
+}
+
+/// Adds directory member, unless archive already has it
+fn add_directory(url: &Location, source: Option<&ObjectStat>) -> PcpResult<()> {
+    let (archive, member) = split_destination(url, ArchiveFormat::Tar)
+        .ok_or_else(|| PcpError::Unsupported(url.to_string(), String::from("Not an archive")))?;
+    with_archive(&archive, |open_archive| match member {
+        Some(member) if !member.as_os_str().is_empty() && !open_archive.members.contains_key(&member) => {
+            append_header(open_archive, &member, member_stat(source, 0, true))
+        }
+        _ => Ok(()),
+    }).map_err(|error| PcpError::from_io(url, error))
+}
+
+/// Error of operations which archive being written does not support
+pub(crate) fn unsupported_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, "Archive members can not be changed")
+}
+
+///
+/// Writes tarballs, which are compressed according to their extension when `compression` feature
+/// is enabled. Members are appended in order they are copied, archive is completed by `finish`.
+/// Headers need size before data, so it comes via `set_size`. Data of unknown size, e.g. compressed
+/// on the way, is kept in temporary file until member is closed. Headers keep permissions and
+/// modification time of sources.
+///
+pub struct TarWriter {
+    target: TarTarget,
+}
+
+enum TarTarget {
+    /// Destination named like tarball, which is copied as a whole
+    Whole(FileWriter),
+    Member {
+        url: Location,
+        archive: PathBuf,
+        member: PathBuf,
+        /// None until header is written
+        size: Option<usize>,
+        /// Metadata of source for header
+        source: Option<ObjectStat>,
+        written: usize,
+        /// Data written before size is known
+        spool: Option<File>,
+    },
+}
+
+// Probed before local files, so that tarball names are not taken for plain files
+crate::register_writer!(TarWriter, "tar", 10);
+
+impl Drop for TarWriter {
+    ///
+    /// Pads data of member to the size from its header and to the end of block,
+    /// so that archive stays readable even if copying of member failed
+    ///
+    fn drop(&mut self) {
+        if let TarTarget::Member { archive, size: Some(size), written, .. } = &self.target {
+            let padding = size.saturating_sub(*written) + (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE;
+            if let Some(open_archive) = OPEN_ARCHIVES.lock().unwrap().get_mut(archive) {
+                let _ = open_archive.builder.get_mut().write_all(&vec![0; padding]);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Writer for TarWriter {
+    fn new(url: &Location) -> PcpResult<Self> where Self: Sized {
+        let (archive, member) = split_destination(url, ArchiveFormat::Tar)
+            .ok_or_else(|| PcpError::Unsupported(url.to_string(), String::from("Not an archive")))?;
+        let target = match member {
+            None if !is_open(&archive) => TarTarget::Whole(FileWriter::new(url)?),
+            Some(member) if !member.as_os_str().is_empty() => {
+                with_archive(&archive, |_| Ok(())).map_err(|error| PcpError::from_io(archive.display(), error))?;
+                TarTarget::Member {
+                    url: url.clone(),
+                    archive,
+                    member,
+                    size: None,
+                    source: None,
+                    written: 0,
+                    spool: None,
+                }
+            }
+            _ => return Err(PcpError::other(url, "Is a directory")),
+        };
+        Ok(TarWriter { target })
+    }
+
+    fn can_write(url: &Location) -> bool where Self: Sized {
+        split_destination(url, ArchiveFormat::Tar).is_some_and(|(archive, _)| {
+            let parent = archive.parent().unwrap_or(Path::new(""));
+            !archive.is_dir() && (parent.as_os_str().is_empty() || parent.is_dir())
+        })
+    }
+
+    fn is_directory(url: &Location) -> bool where Self: Sized {
+        match split_destination(url, ArchiveFormat::Tar) {
+            Some((_, Some(member))) if member.as_os_str().is_empty() => true,
+            Some((archive, Some(member))) => OPEN_ARCHIVES.lock().unwrap().get(&archive)
+                .and_then(|open_archive| open_archive.members.get(&member))
+                .is_some_and(|stat| stat.is_directory),
+            Some((archive, None)) => is_open(&archive),
+            None => false,
+        }
+    }
+
+    #[inline]
+    fn make_directory(url: &Location) -> PcpResult<()> where Self: Sized {
+        add_directory(url, None)
+    }
+
+    #[inline]
+    fn make_directory_like(url: &Location, source: &ObjectStat) -> PcpResult<()> where Self: Sized {
+        add_directory(url, Some(source))
+    }
+
+    fn stat(url: &Location) -> Option<ObjectStat> where Self: Sized {
+        let (archive, member) = split_destination(url, ArchiveFormat::Tar)?;
+        let archives = OPEN_ARCHIVES.lock().unwrap();
+        let root = ObjectStat {
+            size: 0,
+            modified: None,
+            is_directory: true,
+            permissions: None,
+        };
+        match (archives.get(&archive), member) {
+            (Some(_), None) => Some(root),
+            (Some(_), Some(member)) if member.as_os_str().is_empty() => Some(root),
+            (Some(open_archive), Some(member)) => open_archive.members.get(&member).cloned(),
+            // Existing archive is replaced as a whole, so its members are not looked at
+            (None, None) => FileWriter::stat(url),
+            (None, Some(_)) => None,
+        }
+    }
+
+    fn rename(from: &Location, to: &Location) -> std::io::Result<()> where Self: Sized {
+        match (split_destination(from, ArchiveFormat::Tar), split_destination(to, ArchiveFormat::Tar)) {
+            // Backups of archives are named like `out.tar~`, which is not an archive name
+            (Some((archive, None)), None | Some((_, None))) if !is_open(&archive) => FileWriter::rename(from, to),
+            _ => Err(unsupported_error()),
+        }
+    }
+
+    fn container(url: &Location) -> Option<Location> where Self: Sized {
+        let (archive, _) = split_destination(url, ArchiveFormat::Tar)?;
+        (!is_open(&archive)).then(|| Location::from_path(archive))
+    }
+
+    fn list_directory(url: &Location) -> std::io::Result<Vec<OsString>> where Self: Sized {
+        // Backups of replaced archives are looked for next to them
+        match split_destination(url, ArchiveFormat::Tar) {
+            None => FileWriter::list_directory(url),
+            Some(_) => Err(unsupported_error()),
+        }
+    }
+
+    fn remove(url: &Location) -> std::io::Result<()> where Self: Sized {
+        match split_destination(url, ArchiveFormat::Tar) {
+            Some((archive, None)) if !is_open(&archive) => FileWriter::remove(url),
+            _ => Err(unsupported_error()),
+        }
+    }
+
+    fn set_metadata(url: &Location, stat: &ObjectStat) -> std::io::Result<()> where Self: Sized {
+        match split_destination(url, ArchiveFormat::Tar) {
+            Some((archive, None)) if !is_open(&archive) => FileWriter::set_metadata(url, stat),
+            // Headers of members are already written
+            _ => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn join_path(base: &Location, path: &Path) -> Location where Self: Sized {
+        join_member_path(base, path)
+    }
+
+    fn finish(url: &Location) -> PcpResult<()> where Self: Sized {
+        let archive = match split_destination(url, ArchiveFormat::Tar) {
+            Some((archive, _)) => archive,
+            None => return Ok(()),
+        };
+        let open_archive = match OPEN_ARCHIVES.lock().unwrap().remove(&archive) {
+            Some(open_archive) => open_archive,
+            None => return Ok(()),
+        };
+        open_archive.builder.into_inner()
+            .and_then(ArchiveOutput::finish)
+            .map_err(|error| PcpError::from_io(archive.display(), error))
+    }
+
+    fn set_source_metadata(&mut self, stat: &ObjectStat) -> PcpResult<()> {
+        if let TarTarget::Member { source, .. } = &mut self.target {
+            *source = Some(stat.clone());
+        }
+        Ok(())
+    }
+
+    fn set_size(&mut self, new_size: usize) -> PcpResult<()> {
+        let (url, archive, member, size, source) = match &mut self.target {
+            TarTarget::Whole(writer) => return writer.set_size(new_size),
+            TarTarget::Member { size: Some(_), url, .. } => {
+                return Err(PcpError::other(url, "Size of archive member is already written"));
+            }
+            TarTarget::Member { url, archive, member, size, source, .. } => (url, archive, member, size, source),
+        };
+        with_archive(archive, |open_archive| {
+            append_header(open_archive, member, member_stat(source.as_ref(), new_size, false))
+        }).map_err(|error| PcpError::from_io(&*url, error))?;
+        *size = Some(new_size);
+        Ok(())
+    }
+
+    fn close(&mut self) -> PcpResult<()> {
+        let (url, archive, written, spool) = match &mut self.target {
+            TarTarget::Whole(writer) => return writer.close(),
+            TarTarget::Member { size: Some(_), .. } => return Ok(()),
+            TarTarget::Member { url, archive, written, spool, .. } => (url.clone(), archive.clone(), *written, spool.take()),
+        };
+        self.set_size(written)?;
+        if let Some(mut spool) = spool {
+            let mut archives = OPEN_ARCHIVES.lock().unwrap();
+            let open_archive = archives.get_mut(&archive)
+                .ok_or_else(|| PcpError::other(&url, "Archive is already completed"))?;
+            spool.seek(SeekFrom::Start(0))
+                .and_then(|_| std::io::copy(&mut spool, open_archive.builder.get_mut()))
+                .map_err(|error| PcpError::from_io(&url, error))?;
+        }
+        Ok(())
+    }
+
+    async fn write_chunk(&mut self, chunk: &DynBuffer, size: usize) -> PcpResult<usize> {
+        let (url, archive, expected, written, spool) = match &mut self.target {
+            TarTarget::Whole(writer) => return writer.write_chunk(chunk, size).await,
+            TarTarget::Member { url, archive, size: expected, written, spool, .. } => {
+                (url, archive, expected, written, spool)
+            }
+        };
+        let expected = match expected {
+            Some(expected) => *expected,
+            None => {
+                let spool = match spool {
+                    Some(spool) => spool,
+                    None => spool.insert(spool_file().map_err(|error| PcpError::from_io(&*url, error))?),
+                };
+                spool.write_all(&chunk[..size]).map_err(|error| PcpError::from_io(&*url, error))?;
+                *written += size;
+                return Ok(size);
+            }
+        };
+        if *written + size > expected {
+            return Err(PcpError::other(&*url, "Source grew while it was copied"));
+        }
+        let mut archives = OPEN_ARCHIVES.lock().unwrap();
+        let open_archive = archives.get_mut(archive)
+            .ok_or_else(|| PcpError::other(&*url, "Archive is already completed"))?;
+        open_archive.builder.get_mut().write_all(&chunk[..size]).map_err(|error| PcpError::from_io(&*url, error))?;
+        *written += size;
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::runtime::{init_tokio, tokio_block_on};
+
+    #[test]
+    fn test_destinations() {
+        let tar = ArchiveFormat::Tar;
+        assert_eq!(split_destination(&Location::parse("out.tar.zst"), tar), Some((PathBuf::from("out.tar.zst"), None)));
+        assert_eq!(split_destination(&Location::parse("tar://out.tar!/a"), tar),
+                   Some((PathBuf::from("out.tar"), Some(PathBuf::from("a")))));
+        assert_eq!(split_destination(&Location::parse("out.txt"), tar), None);
+        assert_eq!(split_destination(&Location::parse("out.zip//a"), tar), None);
+        let member = TarWriter::join_path(&Location::parse("out.tar"), Path::new("dir/file"));
+        assert_eq!(member.to_string(), "out.tar//dir/file");
+        assert_eq!(TarWriter::join_path(&member, Path::new("x")).to_string(), "out.tar//dir/file/x");
+    }
+
+    #[test]
+    fn test_write_archive() {
+        let archive = std::env::temp_dir().join(format!("pcp-tar-writer-{}.tar", std::process::id()));
+        let root = Location::from_path(&archive);
+        init_tokio();
+        TarWriter::make_directory(&root).unwrap();
+        assert!(TarWriter::is_directory(&root));
+        let directory = TarWriter::join_path(&root, Path::new("dir"));
+        TarWriter::make_directory(&directory).unwrap();
+        for (name, content) in [("dir/a", "first"), ("b", "")] {
+            let mut writer = TarWriter::new(&TarWriter::join_path(&root, Path::new(name))).unwrap();
+            writer.set_size(content.len()).unwrap();
+            let chunk = content.as_bytes().to_vec();
+            if !chunk.is_empty() {
+                tokio_block_on(writer.write_chunk(&chunk, chunk.len())).unwrap();
+            }
+        }
+        TarWriter::finish(&root).unwrap();
+        assert!(!TarWriter::is_directory(&root));
+
+        let mut entries = Vec::new();
+        for entry in tar::Archive::new(File::open(&archive).unwrap()).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+            entries.push((entry.path().unwrap().display().to_string(), content));
+        }
+        std::fs::remove_file(&archive).unwrap();
+        assert_eq!(entries, [(String::from("dir/"), String::new()), (String::from("dir/a"), String::from("first")),
+                             (String::from("b"), String::new())]);
+    }
+
+    #[test]
+    fn test_write_member_of_unknown_size() {
+        let archive = std::env::temp_dir().join(format!("pcp-tar-spool-{}.tar", std::process::id()));
+        let root = Location::from_path(&archive);
+        init_tokio();
+        let mut writer = TarWriter::new(&TarWriter::join_path(&root, Path::new("a"))).unwrap();
+        for chunk in ["spooled ", "data"] {
+            tokio_block_on(writer.write_chunk(&chunk.as_bytes().to_vec(), chunk.len())).unwrap();
+        }
+        writer.close().unwrap();
+        drop(writer);
+        TarWriter::finish(&root).unwrap();
+
+        let mut entries = tar::Archive::new(File::open(&archive).unwrap());
+        let mut entry = entries.entries().unwrap().next().unwrap().unwrap();
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+        std::fs::remove_file(&archive).unwrap();
+        assert_eq!(content, "spooled data");
+    }
+
+    #[test]
+    fn test_existing_archive_policy() {
+        use crate::arguments::{Args, BackupMode};
+        let directory = std::env::temp_dir().join(format!("pcp-tar-policy-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("build/sub")).unwrap();
+        std::fs::write(directory.join("build/sub/file"), b"new").unwrap();
+        std::fs::write(directory.join("out.tar"), b"old").unwrap();
+        let copy = |args: Args| crate::CopyJob::new(directory.join("out.tar"))
+            .source(directory.join("build"))
+            .options(Args { recursive: true, no_progress: true, ..args })
+            .run();
+
+        let report = copy(Args { no_clobber: true, ..Args::default() });
+        assert!(report.is_success());
+        assert_eq!((report.copied, report.skipped), (0, 1));
+        assert_eq!(std::fs::read(directory.join("out.tar")).unwrap(), b"old");
+
+        let report = copy(Args { backup: Some(Some(BackupMode::Numbered)), ..Args::default() });
+        assert!(report.is_success());
+        assert_eq!(std::fs::read(directory.join("out.tar.~1~")).unwrap(), b"old");
+        let members: Vec<PathBuf> = tar::Archive::new(File::open(directory.join("out.tar")).unwrap())
+            .entries().unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(members, [PathBuf::from("sub/"), PathBuf::from("sub/file")]);
+    }
+
+    #[test]
+    fn test_keep_source_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+        let directory = std::env::temp_dir().join(format!("pcp-tar-metadata-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("bin/sub")).unwrap();
+        std::fs::write(directory.join("bin/sub/tool"), b"#!/bin/sh").unwrap();
+        std::fs::set_permissions(directory.join("bin/sub/tool"), std::fs::Permissions::from_mode(0o750)).unwrap();
+        std::fs::set_permissions(directory.join("bin/sub"), std::fs::Permissions::from_mode(0o700)).unwrap();
+        let modified = UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        File::options().write(true).open(directory.join("bin/sub/tool")).unwrap().set_modified(modified).unwrap();
+        let report = crate::CopyJob::new(format!("{}//", directory.join("x.tar").display()))
+            .source(directory.join("bin"))
+            .options(crate::arguments::Args { recursive: true, no_progress: true, ..Default::default() })
+            .run();
+        assert!(report.is_success());
+
+        tar::Archive::new(File::open(directory.join("x.tar")).unwrap()).unpack(directory.join("out")).unwrap();
+        let tool = std::fs::metadata(directory.join("out/bin/sub/tool")).unwrap();
+        let sub = std::fs::metadata(directory.join("out/bin/sub")).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(tool.permissions().mode() & 0o7777, 0o750);
+        assert_eq!(tool.modified().unwrap(), modified);
+        assert_eq!(sub.permissions().mode() & 0o7777, 0o700);
+    }
+}
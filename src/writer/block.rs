@@ -0,0 +1,449 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+use crate::copy::DynBuffer;
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
+use crate::utils::format_size;
+use crate::utils::stat::ObjectStat;
+use crate::utils::term::confirm;
+use crate::writer::Writer;
+
+/// Scheme which makes any local file written like block device, e.g. image file or loop device
+const BLOCK_SCHEME: &str = "block";
+
+/// Block size used when device does not tell its own, multiple of every logical block size
+const DEFAULT_BLOCK_SIZE: usize = 4096;
+
+/// Amount of data collected before it is written with a single request
+const BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+lazy_static::lazy_static! {
+    /// Devices which user agreed to overwrite, so that they are not asked about for every file
+    static ref CONFIRMED_DEVICES: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+///
+/// Gets path of device which URL points at: `block://` URLs point at any local file,
+/// while plain paths are written by this writer only if they are block devices
+///
+fn device_path(url: &Location) -> Option<&Path> {
+    match (url.scheme(), url.authority()) {
+        (Some(BLOCK_SCHEME), Some("" | "localhost")) => Some(Path::new(url.path())),
+        (Some(BLOCK_SCHEME), _) => None,
+        _ => url.local_path().filter(|path| is_block_device(path)),
+    }
+}
+
+#[inline]
+fn is_block_device(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_block_device())
+}
+
+/// Gets directory of device in sysfs, e.g. `/sys/devices/.../block/sda`
+fn sysfs_directory(metadata: &std::fs::Metadata) -> Option<PathBuf> {
+    let rdev = metadata.rdev();
+    let link = format!("/sys/dev/block/{}:{}", libc::major(rdev), libc::minor(rdev));
+    std::fs::canonicalize(link).ok()
+}
+
+///
+/// Gets logical block size of device, which offsets and sizes of direct writes are aligned to
+///
+fn logical_block_size(metadata: &std::fs::Metadata) -> usize {
+    let directory = match sysfs_directory(metadata) {
+        Some(directory) if metadata.file_type().is_block_device() => directory,
+        _ => return DEFAULT_BLOCK_SIZE,
+    };
+    // Partitions share queue of their disk
+    [directory.join("queue"), directory.join("../queue")].iter()
+        .find_map(|queue| std::fs::read_to_string(queue.join("logical_block_size")).ok())
+        .and_then(|size| size.trim().parse().ok())
+        .filter(|size: &usize| size.is_power_of_two())
+        .unwrap_or(DEFAULT_BLOCK_SIZE)
+}
+
+///
+/// Checks that neither device nor its partitions are mounted, used as swap or held
+/// by device mapper
+///
+/// returns: Result<(), String>: description of user of device
+///
+fn check_unused(metadata: &std::fs::Metadata) -> Result<(), String> {
+    let directory = match sysfs_directory(metadata) {
+        Some(directory) => directory,
+        None => return Ok(()),
+    };
+    let mut devices = vec![directory.clone()];
+    for entry in std::fs::read_dir(&directory).into_iter().flatten().flatten() {
+        if entry.path().join("partition").exists() {
+            devices.push(entry.path());
+        }
+    }
+    let mut names = HashSet::new();
+    for device in &devices {
+        let name = device.file_name().unwrap_or_default();
+        if std::fs::read_dir(device.join("holders")).is_ok_and(|mut holders| holders.next().is_some()) {
+            return Err(format!("{} is used by device mapper", name.to_string_lossy()));
+        }
+        names.insert(name.to_os_string());
+    }
+    for (table, what) in [("/proc/mounts", "mounted"), ("/proc/swaps", "used as swap")] {
+        let content = std::fs::read_to_string(table).unwrap_or_default();
+        for source in content.lines().filter_map(|line| line.split_whitespace().next()) {
+            let used = std::fs::canonicalize(source).ok()
+                .and_then(|source| source.file_name().map(|name| name.to_os_string()))
+                .is_some_and(|name| names.contains(&name));
+            if used {
+                return Err(format!("{} is {}", source, what));
+            }
+        }
+    }
+    Ok(())
+}
+
+///
+/// Asks user whether device may be overwritten, once per device
+///
+fn confirm_device(device: &Path, metadata: &std::fs::Metadata) -> bool {
+    let mut confirmed = CONFIRMED_DEVICES.lock().unwrap();
+    if confirmed.contains(device) {
+        return true;
+    }
+    // Size in sysfs is counted in 512-byte sectors regardless of block size
+    let size = sysfs_directory(metadata)
+        .and_then(|directory| std::fs::read_to_string(directory.join("size")).ok())
+        .and_then(|size| size.trim().parse::<usize>().ok())
+        .map(|sectors| format!(" ({})", format_size(sectors * 512)))
+        .unwrap_or_default();
+    let agreed = confirm(&format!("Everything on {}{} will be overwritten, continue? [y/N]", device.display(), size));
+    if agreed {
+        confirmed.insert(device.to_path_buf());
+    }
+    agreed
+}
+
+///
+/// Opens device for writing, bypassing page cache when device supports it, e.g. tmpfs does not
+///
+/// returns: std::io::Result<(File, bool)>: device and whether it is opened for direct access
+///
+fn open_device(device: &Path) -> std::io::Result<(File, bool)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(!is_block_device(device));
+    match options.clone().custom_flags(libc::O_DIRECT | libc::O_SYNC).open(device) {
+        Err(error) if error.raw_os_error() == Some(libc::EINVAL) => {
+            Ok((options.custom_flags(libc::O_SYNC).open(device)?, false))
+        }
+        result => result.map(|file| (file, true)),
+    }
+}
+
+///
+/// Buffer which starts at address aligned to block size, as direct access requires
+///
+struct AlignedBuffer {
+    storage: Vec<u8>,
+    offset: usize,
+    capacity: usize,
+}
+
+impl AlignedBuffer {
+    fn new(capacity: usize, alignment: usize) -> AlignedBuffer {
+        let storage = vec![0; capacity + alignment];
+        let offset = storage.as_ptr().align_offset(alignment);
+        AlignedBuffer { storage, offset, capacity }
+    }
+
+    #[inline]
+    fn as_slice(&self) -> &[u8] {
+        &self.storage[self.offset..self.offset + self.capacity]
+    }
+
+    #[inline]
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.storage[self.offset..self.offset + self.capacity]
+    }
+}
+
+///
+/// Parses options of block device URL
+///
+/// returns: PcpResult<bool>: whether written data is read back and verified
+///
+fn verify_option(url: &Location) -> PcpResult<bool> {
+    let mut verify = false;
+    for option in url.query().unwrap_or("").split('&').filter(|option| !option.is_empty()) {
+        match option {
+            "verify" => verify = true,
+            _ => return Err(PcpError::Unsupported(url.to_string(), format!("Unknown block device option {}", option))),
+        }
+    }
+    Ok(verify)
+}
+
+///
+/// Writes images straight to block devices like `/dev/sdb`, e.g. to burn installation media.
+/// Data is written with direct synchronous access in blocks of device, so that nothing stays
+/// in cache when copying is over. Mounted devices are refused and user is asked before anything
+/// is written. `block:///path` writes any local file the same way, and `?verify` query reads
+/// written data back and compares it.
+///
+pub struct BlockWriter {
+    path: Location,
+    device: PathBuf,
+    file: File,
+    direct: bool,
+    block_size: usize,
+    buffer: AlignedBuffer,
+    filled: usize,
+    /// Offset which writing has started at
+    start: u64,
+    /// Offset up to which data is written to device
+    position: u64,
+    /// Whether image file is cut after written data
+    truncate: bool,
+    /// Checksum of written data if it is verified
+    hasher: Option<Sha256>,
+}
+
+// Block devices are local paths too, so this writer is probed before file writer
+crate::register_writer!(BlockWriter, "block", 10);
+
+impl BlockWriter {
+    ///
+    /// Opens device, checking that it is not used and asking user about it
+    ///
+    /// # Arguments
+    ///
+    /// * `url`: URL of device
+    /// * `offset`: offset to write at, None to write from the beginning and truncate image files
+    ///
+    /// returns: PcpResult<BlockWriter>
+    ///
+    fn open(url: &Location, offset: Option<usize>) -> PcpResult<BlockWriter> {
+        let device = device_path(url)
+            .ok_or_else(|| PcpError::Unsupported(url.to_string(), String::from("Not a local path")))?
+            .to_path_buf();
+        let verify = verify_option(url)?;
+        if let Some(metadata) = std::fs::metadata(&device).ok().filter(|metadata| metadata.file_type().is_block_device()) {
+            check_unused(&metadata).map_err(|error| PcpError::other(url, error))?;
+            if !confirm_device(&device, &metadata) {
+                return Err(PcpError::other(url, "Device was not confirmed"));
+            }
+        }
+        let start = offset.unwrap_or(0) as u64;
+        let (mut file, mut direct) = open_device(&device).map_err(|error| PcpError::from_io(url, error))?;
+        let block_size = file.metadata().map(|metadata| logical_block_size(&metadata)).unwrap_or(DEFAULT_BLOCK_SIZE);
+        if direct && !start.is_multiple_of(block_size as u64) {
+            // Direct access can not start in the middle of block
+            file = OpenOptions::new().write(true).custom_flags(libc::O_SYNC).open(&device)
+                .map_err(|error| PcpError::from_io(url, error))?;
+            direct = false;
+        }
+        file.seek(SeekFrom::Start(start)).map_err(|error| PcpError::from_io(url, error))?;
+        Ok(BlockWriter {
+            path: url.clone(),
+            device,
+            file,
+            direct,
+            block_size,
+            buffer: AlignedBuffer::new(BUFFER_SIZE, block_size.max(DEFAULT_BLOCK_SIZE)),
+            filled: 0,
+            start,
+            position: start,
+            truncate: offset.is_none(),
+            hasher: verify.then(Sha256::new),
+        })
+    }
+
+    fn io_error(&self, error: std::io::Error) -> PcpError {
+        PcpError::from_io(&self.path, error)
+    }
+
+    /// Writes whole blocks collected in buffer, keeping the rest
+    fn write_blocks(&mut self) -> std::io::Result<()> {
+        let size = self.filled - self.filled % self.block_size;
+        if size == 0 {
+            return Ok(());
+        }
+        self.file.write_all(&self.buffer.as_slice()[..size])?;
+        self.position += size as u64;
+        let rest = self.filled - size;
+        self.buffer.as_mut_slice().copy_within(size..self.filled, 0);
+        self.filled = rest;
+        Ok(())
+    }
+
+    /// Writes incomplete last block, which direct access can not write
+    fn write_tail(&mut self) -> std::io::Result<()> {
+        if self.filled == 0 {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new().write(true).custom_flags(libc::O_SYNC).open(&self.device)?;
+        file.seek(SeekFrom::Start(self.position))?;
+        file.write_all(&self.buffer.as_slice()[..self.filled])?;
+        self.position += self.filled as u64;
+        self.filled = 0;
+        Ok(())
+    }
+
+    /// Reads written data back and computes its checksum
+    fn read_back(&mut self) -> std::io::Result<Vec<u8>> {
+        let flags = if self.direct { libc::O_DIRECT } else { 0 };
+        let mut file = OpenOptions::new().read(true).custom_flags(flags).open(&self.device)?;
+        file.seek(SeekFrom::Start(self.start))?;
+        let mut hasher = Sha256::new();
+        let mut remaining = self.position - self.start;
+        while remaining > 0 {
+            let buffer = self.buffer.as_mut_slice();
+            let size = file.read(buffer)?;
+            if size == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Device is shorter than written data"));
+            }
+            let size = size.min(remaining as usize);
+            hasher.update(&buffer[..size]);
+            remaining -= size as u64;
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+}
+
+#[async_trait]
+impl Writer for BlockWriter {
+    #[inline]
+    fn new(url: &Location) -> PcpResult<Self> where Self: Sized {
+        BlockWriter::open(url, None)
+    }
+
+    #[inline]
+    fn open_at(url: &Location, offset: usize) -> PcpResult<Self> where Self: Sized {
+        BlockWriter::open(url, Some(offset))
+    }
+
+    fn can_write(url: &Location) -> bool where Self: Sized {
+        device_path(url).is_some_and(|device| device.parent().is_some_and(Path::is_dir))
+    }
+
+    #[inline]
+    fn is_directory(_url: &Location) -> bool where Self: Sized {
+        false
+    }
+
+    fn make_directory(url: &Location) -> PcpResult<()> where Self: Sized {
+        Err(PcpError::Unsupported(url.to_string(), String::from("Block devices can not contain directories")))
+    }
+
+    #[inline]
+    fn stat(url: &Location) -> Option<ObjectStat> where Self: Sized {
+        std::fs::metadata(device_path(url)?).ok().map(ObjectStat::from)
+    }
+
+    fn rename(_from: &Location, _to: &Location) -> std::io::Result<()> where Self: Sized {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Block devices can not be renamed"))
+    }
+
+    fn remove(_url: &Location) -> std::io::Result<()> where Self: Sized {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Block devices can not be removed"))
+    }
+
+    fn verifies_on_close(url: &Location) -> PcpResult<bool> where Self: Sized {
+        // Size of device is not size of image, so device can not be read again like file
+        if !verify_option(url)? {
+            return Err(PcpError::Unsupported(url.to_string(),
+                                             String::from("Moving to block device needs ?verify option")));
+        }
+        Ok(true)
+    }
+
+    #[inline]
+    fn set_metadata(_url: &Location, _stat: &ObjectStat) -> std::io::Result<()> where Self: Sized {
+        // Metadata of source file means nothing for device
+        Ok(())
+    }
+
+    #[inline]
+    fn join_path(base: &Location, path: &Path) -> Location where Self: Sized {
+        base.join(path)
+    }
+
+    fn close(&mut self) -> PcpResult<()> {
+        self.write_blocks().and_then(|_| self.write_tail()).map_err(|error| self.io_error(error))?;
+        if self.truncate && self.file.metadata().is_ok_and(|metadata| metadata.is_file()) {
+            // Image files are replaced like regular files, devices keep data after image
+            self.file.set_len(self.position).map_err(|error| self.io_error(error))?;
+        }
+        if let Some(hasher) = self.hasher.take() {
+            let written = hasher.finalize().to_vec();
+            if self.read_back().map_err(|error| self.io_error(error))? != written {
+                return Err(PcpError::other(&self.path, "Verification failed, data read back differs from written"));
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_chunk(&mut self, chunk: &DynBuffer, size: usize) -> PcpResult<usize> {
+        let mut data = &chunk[..size];
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(data);
+        }
+        while !data.is_empty() {
+            let free = BUFFER_SIZE - self.filled;
+            let amount = free.min(data.len());
+            self.buffer.as_mut_slice()[self.filled..self.filled + amount].copy_from_slice(&data[..amount]);
+            self.filled += amount;
+            data = &data[amount..];
+            if self.filled == BUFFER_SIZE {
+                self.write_blocks().map_err(|error| self.io_error(error))?;
+            }
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::runtime::{init_tokio, tokio_block_on};
+
+    #[test]
+    fn test_aligned_buffer() {
+        let buffer = AlignedBuffer::new(8192, 4096);
+        assert_eq!(buffer.as_slice().as_ptr() as usize % 4096, 0);
+        assert_eq!(buffer.as_slice().len(), 8192);
+    }
+
+    #[test]
+    fn test_write_image() {
+        let image = std::env::temp_dir().join(format!("pcp-block-{}.img", std::process::id()));
+        std::fs::write(&image, vec![0xff; 3 * BUFFER_SIZE]).unwrap();
+        let url = Location::parse(&format!("block://{}?verify", image.display()));
+        assert!(BlockWriter::can_write(&url) && !BlockWriter::can_write(&Location::from_path(&image)));
+
+        init_tokio();
+        // Unaligned chunks leave incomplete block at the end
+        let data: Vec<u8> = (0..BUFFER_SIZE + 12345).map(|index| (index % 251) as u8).collect();
+        tokio_block_on(async {
+            let mut writer = BlockWriter::new(&url).unwrap();
+            for chunk in data.chunks(100_000) {
+                writer.write_chunk(&chunk.to_vec(), chunk.len()).await.unwrap();
+            }
+            writer.close().unwrap();
+        });
+        assert_eq!(std::fs::read(&image).unwrap(), data);
+        std::fs::remove_file(&image).unwrap();
+    }
+
+    #[test]
+    fn test_move_needs_verification() {
+        assert!(BlockWriter::verifies_on_close(&Location::parse("block:///dev/sdz?verify")).unwrap());
+        assert!(BlockWriter::verifies_on_close(&Location::parse("block:///dev/sdz")).is_err());
+    }
+}
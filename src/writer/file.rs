@@ -1,108 +1,145 @@
-use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::fs::Permissions;
+use std::io::SeekFrom;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use async_trait::async_trait;
-use colored::Colorize;
-use regex::Regex;
 use tokio::fs::{File, OpenOptions};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use crate::copy::DynBuffer;
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
+use crate::reader::file::{local_io_path, local_path};
 use crate::utils::runtime::tokio_block_on;
+use crate::utils::stat::ObjectStat;
 use crate::writer::Writer;
 
 ///
 /// Implements standard writing for files in local FS
 /// 
 pub struct FileWriter{
-    _path: String,
+    path: Location,
     file: File,
 }
 
-#[inline]
-fn check_valid_url(url: &str) -> bool {
-    let re = Regex::new(r"^(/?[\s\w'.-]+)+(/)?$").unwrap();
-    re.is_match(url)
-}
+// Local paths are accepted by file writer as is, so it is probed last
+crate::register_writer!(FileWriter, "file", 0);
 
 #[async_trait]
 impl Writer for FileWriter{
-    fn new(url: &str) -> Self where Self: Sized {
-        if !Self::can_write(url){
-            //panic!("Can not write url {url}");
-        }
+    fn new(url: &Location) -> PcpResult<Self> where Self: Sized {
+        let path = local_path(url)?;
         let open_coroutine = async {
-            if Path::new(url).exists(){
+            if path.exists(){
                 OpenOptions::new()
                     .write(true)
                     .create(true)
                     .truncate(true)
-                    .open(url).await
+                    .open(path).await
 
             } else {
                 OpenOptions::new()
                     .write(true)
-                    .create(true)
                     .create_new(true)
-                    .open(url).await
+                    .open(path).await
             }
         };
 
-       FileWriter {
-            _path: String::from(url),
-            file: tokio_block_on(open_coroutine).expect("Can not open file"),
-        }
+        Ok(FileWriter {
+            path: url.clone(),
+            file: tokio_block_on(open_coroutine).map_err(|error| PcpError::from_io(url, error))?,
+        })
     }
     
-    fn can_write(url: &str) -> bool where Self: Sized {
-        if !check_valid_url(url){
-            return false;
-        }
-        let path = PathBuf::from(url);
-        if path.is_dir() || url.bytes().last().unwrap() == '/' as u8{
-            if !path.exists(){
-                println!("{}{}: No such directory", url.bold().red(), "".clear());
-                return false;
-            }
-            return true;
-        }
-        let dir = path.parent().unwrap();
-        if dir.is_dir() || dir.to_str().unwrap() == ""{
-            true
-        } else {
-            println!("{}{}: No such directory", url.bold().red(), "".clear());
-            false
+    fn open_at(url: &Location, offset: usize) -> PcpResult<Self> where Self: Sized {
+        let path = local_path(url)?;
+        let open_coroutine = async {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path).await?;
+            file.seek(SeekFrom::Start(offset as u64)).await?;
+            Ok(file)
+        };
+        Ok(FileWriter {
+            path: url.clone(),
+            file: tokio_block_on(open_coroutine).map_err(|error| PcpError::from_io(url, error))?,
+        })
+    }
+
+    fn can_write(url: &Location) -> bool where Self: Sized {
+        let path = match url.local_path(){
+            Some(path) => path,
+            None => return false,
+        };
+        if path.is_dir() || url.has_trailing_separator(){
+            return path.exists();
         }
+        let dir = path.parent().unwrap_or(Path::new(""));
+        dir.is_dir() || dir.as_os_str().is_empty()
     }
 
     #[inline]
-    fn is_directory(url: &str) -> bool where Self: Sized {
-        Path::new(url).is_dir()
+    fn is_directory(url: &Location) -> bool where Self: Sized {
+        url.local_path().is_some_and(Path::is_dir)
     }
     
     #[inline]
-    fn make_directory(url: &str) where Self: Sized {
+    fn make_directory(url: &Location) -> PcpResult<()> where Self: Sized {
         //println!("mkdir {:?}", url);
-        std::fs::create_dir_all(Path::new(url)).expect("Can not create directory")
+        std::fs::create_dir_all(local_path(url)?).map_err(|error| PcpError::from_io(url, error))
     }
     
-    fn join_path(base: &str, path: &str) -> String where Self: Sized {
-        let mut base_path = PathBuf::from(base);
-        let relative_path = PathBuf::from(path);
-        base_path.extend(relative_path.iter());
-        base_path.into_os_string().into_string().unwrap()
+    #[inline]
+    fn stat(url: &Location) -> Option<ObjectStat> where Self: Sized {
+        std::fs::metadata(url.local_path()?).ok().map(ObjectStat::from)
     }
 
-    async fn write_chunk(&mut self, chunk: &DynBuffer, size: usize) -> std::io::Result<usize> {
-        let write_result = if chunk.len() == size {
-            self.file.write(chunk).await
-        } else { //chunk.len() > size
-            self.file.write(&chunk[0..size]).await
-        };
-        if write_result.is_err(){
-            return write_result;
+    #[inline]
+    fn rename(from: &Location, to: &Location) -> std::io::Result<()> where Self: Sized {
+        std::fs::rename(local_io_path(from)?, local_io_path(to)?)
+    }
+
+    fn list_directory(url: &Location) -> std::io::Result<Vec<OsString>> where Self: Sized {
+        std::fs::read_dir(local_io_path(url)?)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect()
+    }
+
+    fn remove(url: &Location) -> std::io::Result<()> where Self: Sized {
+        let path = local_io_path(url)?;
+        if path.is_dir() {
+            std::fs::remove_dir(path)
+        } else {
+            std::fs::remove_file(path)
+        }
+    }
+
+    fn set_metadata(url: &Location, stat: &ObjectStat) -> std::io::Result<()> where Self: Sized {
+        let path = local_io_path(url)?;
+        if let Some(modified) = stat.modified {
+            std::fs::File::options().write(true).open(path)?.set_modified(modified)?;
         }
-        let sync_result = self.file.sync_all().await;
-        if sync_result.is_err(){
-            return Err(std::io::Error::last_os_error());
+        if let Some(permissions) = stat.permissions {
+            std::fs::set_permissions(path, Permissions::from_mode(permissions))?;
         }
-        write_result
+        Ok(())
+    }
+
+    #[inline]
+    fn join_path(base: &Location, path: &Path) -> Location where Self: Sized {
+        base.join(path)
+    }
+
+    fn close(&mut self) -> PcpResult<()> {
+        // Called from copy coroutine, while runtime is already blocked on
+        futures::executor::block_on(self.file.sync_all()).map_err(|error| PcpError::from_io(&self.path, error))
+    }
+
+    async fn write_chunk(&mut self, chunk: &DynBuffer, size: usize) -> PcpResult<usize> {
+        // Single write of tokio file takes only part of large chunks
+        self.file.write_all(&chunk[0..size]).await.map_err(|error| PcpError::from_io(&self.path, error))?;
+        Ok(size)
     }
 }
\ No newline at end of file
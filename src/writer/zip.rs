@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
+
+use crate::copy::DynBuffer;
+use crate::error::{PcpError, PcpResult};
+use crate::location::Location;
+use crate::reader::archive::ArchiveFormat;
+use crate::reader::zip::from_system_time;
+use crate::utils::stat::ObjectStat;
+use crate::writer::archive::{join_member_path, member_stat, split_destination, unsupported_error};
+use crate::writer::file::FileWriter;
+use crate::writer::Writer;
+
+/// Compression levels which deflate accepts
+const LEVELS: std::ops::RangeInclusive<i64> = 0..=9;
+
+///
+/// Zip file which is being written, it stays open until `finish` is called for destination
+///
+struct OpenZip {
+    writer: zip::ZipWriter<BufWriter<File>>,
+    /// Members written so far
+    members: HashMap<PathBuf, ObjectStat>,
+    /// File member which data is written now, zip entries are written one after another
+    current: Option<PathBuf>,
+}
+
+lazy_static! {
+    static ref OPEN_ZIPS: Mutex<HashMap<PathBuf, OpenZip>> = Mutex::new(HashMap::new());
+}
+
+/// Runs operation on open zip file, creating it first if it is not open yet
+fn with_zip<T>(archive: &Path, operation: impl FnOnce(&mut OpenZip) -> PcpResult<T>) -> PcpResult<T> {
+    let mut archives = OPEN_ZIPS.lock().unwrap();
+    if !archives.contains_key(archive) {
+        let file = File::create(archive).map_err(|error| PcpError::from_io(archive.display(), error))?;
+        archives.insert(archive.to_path_buf(), OpenZip {
+            writer: zip::ZipWriter::new(BufWriter::new(file)).set_auto_large_file(),
+            members: HashMap::new(),
+            current: None,
+        });
+    }
+    operation(archives.get_mut(archive).unwrap())
+}
+
+#[inline]
+fn is_open(archive: &Path) -> bool {
+    OPEN_ZIPS.lock().unwrap().contains_key(archive)
+}
+
+///
+/// Gets options of entry from query of its location, e.g. `zip://out.zip!/?level=9` or
+/// `zip://out.zip!/?method=stored`. Entries are deflated with default level otherwise.
+///
+/// # Arguments
+///
+/// * `url`: location of entry
+/// * `stat`: metadata of entry, see `member_stat`
+///
+/// returns: PcpResult<SimpleFileOptions>
+///
+fn entry_options(url: &Location, stat: &ObjectStat) -> PcpResult<SimpleFileOptions> {
+    let mut method = CompressionMethod::Deflated;
+    let mut level = None;
+    for option in url.query().unwrap_or("").split('&').filter(|option| !option.is_empty()) {
+        match option.split_once('=') {
+            Some(("method", "stored")) => method = CompressionMethod::Stored,
+            Some(("method", "deflated")) => method = CompressionMethod::Deflated,
+            Some(("level", value)) => {
+                level = Some(value.parse().ok().filter(|level| LEVELS.contains(level)).ok_or_else(|| {
+                    PcpError::Unsupported(url.to_string(), format!("Compression level {} is not in 0-9", value))
+                })?);
+            }
+            _ => return Err(PcpError::Unsupported(url.to_string(), format!("Unknown zip option {}", option))),
+        }
+    }
+    let mut options = SimpleFileOptions::default()
+        .compression_method(method)
+        .unix_permissions(stat.permissions.unwrap_or_default());
+    // Stored entries accept no level
+    if method == CompressionMethod::Deflated {
+        options = options.compression_level(level);
+    }
+    if let Some(modified) = stat.modified.and_then(from_system_time) {
+        options = options.last_modified_time(modified);
+    }
+    Ok(options)
+}
+
+/// Gets name of entry, which zip stores as UTF-8
+fn entry_name(url: &Location, member: &Path) -> PcpResult<String> {
+    member.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| PcpError::Unsupported(url.to_string(), String::from("Zip entry names have to be UTF-8")))
+}
+
+///
+/// Writes zip files, entries of which are deflated unless options in query of destination say
+/// otherwise. Entries are appended in order they are copied, zip file is completed by `finish`.
+/// Entry is started with its first chunk, so that it gets permissions and modification time of source.
+///
+pub struct ZipWriter {
+    target: ZipTarget,
+}
+
+enum ZipTarget {
+    /// Destination named like zip file, which is copied as a whole
+    Whole(FileWriter),
+    Member {
+        url: Location,
+        archive: PathBuf,
+        member: PathBuf,
+        /// Metadata of entry, taken from source if it is known
+        stat: ObjectStat,
+        started: bool,
+    },
+}
+
+impl ZipWriter {
+    /// Starts entry in zip file, unless it is already started
+    fn start_entry(&mut self) -> PcpResult<()> {
+        let (url, archive, member, stat, started) = match &mut self.target {
+            ZipTarget::Member { url, archive, member, stat, started: started @ false } => {
+                (url, archive, member, stat, started)
+            }
+            _ => return Ok(()),
+        };
+        let options = entry_options(url, stat)?;
+        let name = entry_name(url, member)?;
+        with_zip(archive, |open_zip| {
+            open_zip.writer.start_file(name, options).map_err(|error| PcpError::from_io(&*url, error.into()))?;
+            open_zip.members.insert(member.clone(), stat.clone());
+            open_zip.current = Some(member.clone());
+            Ok(())
+        })?;
+        *started = true;
+        Ok(())
+    }
+}
+
+/// Adds directory entry, unless zip file already has it
+fn add_directory(url: &Location, source: Option<&ObjectStat>) -> PcpResult<()> {
+    let (archive, member) = split_destination(url, ArchiveFormat::Zip)
+        .ok_or_else(|| PcpError::Unsupported(url.to_string(), String::from("Not an archive")))?;
+    with_zip(&archive, |open_zip| match member {
+        Some(member) if !member.as_os_str().is_empty() && !open_zip.members.contains_key(&member) => {
+            let stat = member_stat(source, 0, true);
+            open_zip.writer.add_directory(entry_name(url, &member)?, entry_options(url, &stat)?)
+                .map_err(|error| PcpError::from_io(url, error.into()))?;
+            open_zip.members.insert(member, stat);
+            open_zip.current = None;
+            Ok(())
+        }
+        _ => Ok(()),
+    })
+}
+
+// Probed before local files like tarballs
+crate::register_writer!(ZipWriter, "zip", 10);
+
+#[async_trait]
+impl Writer for ZipWriter {
+    fn new(url: &Location) -> PcpResult<Self> where Self: Sized {
+        let (archive, member) = split_destination(url, ArchiveFormat::Zip)
+            .ok_or_else(|| PcpError::Unsupported(url.to_string(), String::from("Not an archive")))?;
+        let target = match member {
+            None if !is_open(&archive) => ZipTarget::Whole(FileWriter::new(url)?),
+            Some(member) if !member.as_os_str().is_empty() => {
+                let stat = member_stat(None, 0, false);
+                // Bad names and options are reported before anything is copied
+                entry_name(url, &member)?;
+                entry_options(url, &stat)?;
+                ZipTarget::Member {
+                    url: url.clone(),
+                    archive,
+                    member,
+                    stat,
+                    started: false,
+                }
+            }
+            _ => return Err(PcpError::other(url, "Is a directory")),
+        };
+        Ok(ZipWriter { target })
+    }
+
+    fn can_write(url: &Location) -> bool where Self: Sized {
+        split_destination(url, ArchiveFormat::Zip).is_some_and(|(archive, _)| {
+            let parent = archive.parent().unwrap_or(Path::new(""));
+            !archive.is_dir() && (parent.as_os_str().is_empty() || parent.is_dir())
+        })
+    }
+
+    fn is_directory(url: &Location) -> bool where Self: Sized {
+        match split_destination(url, ArchiveFormat::Zip) {
+            Some((_, Some(member))) if member.as_os_str().is_empty() => true,
+            Some((archive, Some(member))) => OPEN_ZIPS.lock().unwrap().get(&archive)
+                .and_then(|open_zip| open_zip.members.get(&member))
+                .is_some_and(|stat| stat.is_directory),
+            Some((archive, None)) => is_open(&archive),
+            None => false,
+        }
+    }
+
+    #[inline]
+    fn make_directory(url: &Location) -> PcpResult<()> where Self: Sized {
+        add_directory(url, None)
+    }
+
+    #[inline]
+    fn make_directory_like(url: &Location, source: &ObjectStat) -> PcpResult<()> where Self: Sized {
+        add_directory(url, Some(source))
+    }
+
+    fn stat(url: &Location) -> Option<ObjectStat> where Self: Sized {
+        let (archive, member) = split_destination(url, ArchiveFormat::Zip)?;
+        let archives = OPEN_ZIPS.lock().unwrap();
+        let root = ObjectStat {
+            size: 0,
+            modified: None,
+            is_directory: true,
+            permissions: None,
+        };
+        match (archives.get(&archive), member) {
+            (Some(_), None) => Some(root),
+            (Some(_), Some(member)) if member.as_os_str().is_empty() => Some(root),
+            (Some(open_zip), Some(member)) => open_zip.members.get(&member).cloned(),
+            // Existing zip file is replaced as a whole, so its entries are not looked at
+            (None, None) => FileWriter::stat(url),
+            (None, Some(_)) => None,
+        }
+    }
+
+    fn rename(from: &Location, to: &Location) -> std::io::Result<()> where Self: Sized {
+        match (split_destination(from, ArchiveFormat::Zip), split_destination(to, ArchiveFormat::Zip)) {
+            // Backups of archives are named like `out.zip~`, which is not an archive name
+            (Some((archive, None)), None | Some((_, None))) if !is_open(&archive) => FileWriter::rename(from, to),
+            _ => Err(unsupported_error()),
+        }
+    }
+
+    fn container(url: &Location) -> Option<Location> where Self: Sized {
+        let (archive, _) = split_destination(url, ArchiveFormat::Zip)?;
+        (!is_open(&archive)).then(|| Location::from_path(archive))
+    }
+
+    fn list_directory(url: &Location) -> std::io::Result<Vec<OsString>> where Self: Sized {
+        // Backups of replaced archives are looked for next to them
+        match split_destination(url, ArchiveFormat::Zip) {
+            None => FileWriter::list_directory(url),
+            Some(_) => Err(unsupported_error()),
+        }
+    }
+
+    fn remove(url: &Location) -> std::io::Result<()> where Self: Sized {
+        match split_destination(url, ArchiveFormat::Zip) {
+            Some((archive, None)) if !is_open(&archive) => FileWriter::remove(url),
+            _ => Err(unsupported_error()),
+        }
+    }
+
+    fn set_metadata(url: &Location, stat: &ObjectStat) -> std::io::Result<()> where Self: Sized {
+        match split_destination(url, ArchiveFormat::Zip) {
+            Some((archive, None)) if !is_open(&archive) => FileWriter::set_metadata(url, stat),
+            // Headers of entries are already written
+            _ => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn join_path(base: &Location, path: &Path) -> Location where Self: Sized {
+        join_member_path(base, path)
+    }
+
+    fn finish(url: &Location) -> PcpResult<()> where Self: Sized {
+        let archive = match split_destination(url, ArchiveFormat::Zip) {
+            Some((archive, _)) => archive,
+            None => return Ok(()),
+        };
+        let open_zip = match OPEN_ZIPS.lock().unwrap().remove(&archive) {
+            Some(open_zip) => open_zip,
+            None => return Ok(()),
+        };
+        open_zip.writer.finish()
+            .map_err(std::io::Error::from)
+            .and_then(|mut output| output.flush())
+            .map_err(|error| PcpError::from_io(archive.display(), error))
+    }
+
+    fn set_source_metadata(&mut self, source: &ObjectStat) -> PcpResult<()> {
+        if let ZipTarget::Member { stat, started: false, .. } = &mut self.target {
+            *stat = member_stat(Some(source), 0, false);
+        }
+        Ok(())
+    }
+
+    fn set_size(&mut self, size: usize) -> PcpResult<()> {
+        match &mut self.target {
+            ZipTarget::Whole(writer) => writer.set_size(size),
+            // Sizes of entries are written after their data
+            ZipTarget::Member { .. } => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> PcpResult<()> {
+        match &mut self.target {
+            ZipTarget::Whole(writer) => writer.close(),
+            // Empty entries get no chunks
+            ZipTarget::Member { .. } => self.start_entry(),
+        }
+    }
+
+    async fn write_chunk(&mut self, chunk: &DynBuffer, size: usize) -> PcpResult<usize> {
+        self.start_entry()?;
+        let (url, archive, member) = match &mut self.target {
+            ZipTarget::Whole(writer) => return writer.write_chunk(chunk, size).await,
+            ZipTarget::Member { url, archive, member, .. } => (url, archive, member),
+        };
+        let mut archives = OPEN_ZIPS.lock().unwrap();
+        let open_zip = archives.get_mut(archive)
+            .filter(|open_zip| open_zip.current.as_ref() == Some(member))
+            .ok_or_else(|| PcpError::other(&*url, "Entry is already completed"))?;
+        open_zip.writer.write_all(&chunk[..size]).map_err(|error| PcpError::from_io(&*url, error))?;
+        if let Some(stat) = open_zip.members.get_mut(member) {
+            stat.size += size;
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::utils::runtime::{init_tokio, tokio_block_on};
+
+    #[test]
+    fn test_entry_options() {
+        let stat = member_stat(None, 0, false);
+        let options = entry_options(&Location::parse("zip://out.zip!/a?level=9"), &stat).unwrap();
+        assert_eq!(options.get_compression_level(), Some(9));
+        assert!(entry_options(&Location::parse("zip://out.zip!/a?method=stored&level=1"), &stat).is_ok());
+        assert!(entry_options(&Location::parse("zip://out.zip!/a?level=10"), &stat).is_err());
+        assert!(entry_options(&Location::parse("zip://out.zip!/a?method=lzma"), &stat).is_err());
+        assert_eq!(split_destination(&Location::parse("out.zip"), ArchiveFormat::Zip),
+                   Some((PathBuf::from("out.zip"), None)));
+        assert_eq!(split_destination(&Location::parse("out.tar//a"), ArchiveFormat::Zip), None);
+    }
+
+    #[test]
+    fn test_write_zip() {
+        let archive = std::env::temp_dir().join(format!("pcp-zip-writer-{}.zip", std::process::id()));
+        let root = Location::parse(&format!("zip://{}!/?method=stored", archive.display()));
+        init_tokio();
+        ZipWriter::make_directory(&root).unwrap();
+        assert!(ZipWriter::is_directory(&root));
+        ZipWriter::make_directory(&ZipWriter::join_path(&root, Path::new("dir"))).unwrap();
+        for (name, content) in [("dir/a", "first"), ("b", "")] {
+            let mut writer = ZipWriter::new(&ZipWriter::join_path(&root, Path::new(name))).unwrap();
+            writer.set_size(content.len()).unwrap();
+            let chunk = content.as_bytes().to_vec();
+            if !chunk.is_empty() {
+                tokio_block_on(writer.write_chunk(&chunk, chunk.len())).unwrap();
+            }
+            writer.close().unwrap();
+        }
+        ZipWriter::finish(&root).unwrap();
+        assert!(!ZipWriter::is_directory(&Location::from_path(&archive)));
+
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let mut entries = Vec::new();
+        for index in 0..zip.len() {
+            let mut entry = zip.by_index(index).unwrap();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            entries.push((entry.name().unwrap().to_string(), entry.compression(), content));
+        }
+        std::fs::remove_file(&archive).unwrap();
+        assert_eq!(entries, [(String::from("dir/"), CompressionMethod::Stored, String::new()),
+                             (String::from("dir/a"), CompressionMethod::Stored, String::from("first")),
+                             (String::from("b"), CompressionMethod::Stored, String::new())]);
+    }
+
+    #[test]
+    fn test_existing_zip_policy() {
+        use crate::arguments::{Args, BackupMode};
+        let directory = std::env::temp_dir().join(format!("pcp-zip-policy-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("build")).unwrap();
+        std::fs::write(directory.join("build/file"), b"new").unwrap();
+        std::fs::write(directory.join("out.zip"), b"old").unwrap();
+        let copy = |args: Args| crate::CopyJob::new(directory.join("out.zip"))
+            .source(directory.join("build"))
+            .options(Args { recursive: true, no_progress: true, ..args })
+            .run();
+
+        let report = copy(Args { no_clobber: true, ..Args::default() });
+        assert!(report.is_success());
+        assert_eq!((report.copied, report.skipped), (0, 1));
+        assert_eq!(std::fs::read(directory.join("out.zip")).unwrap(), b"old");
+
+        let report = copy(Args { backup: Some(Some(BackupMode::Simple)), ..Args::default() });
+        assert!(report.is_success());
+        assert_eq!(std::fs::read(directory.join("out.zip~")).unwrap(), b"old");
+        let zip = zip::ZipArchive::new(File::open(directory.join("out.zip")).unwrap()).unwrap();
+        let names: Vec<String> = zip.file_names().map(|name| name.unwrap().into_owned()).collect();
+        assert_eq!(names, ["file"]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_keep_source_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+        let directory = std::env::temp_dir().join(format!("pcp-zip-metadata-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("bin")).unwrap();
+        std::fs::write(directory.join("bin/tool"), b"#!/bin/sh").unwrap();
+        std::fs::set_permissions(directory.join("bin/tool"), std::fs::Permissions::from_mode(0o750)).unwrap();
+        std::fs::set_permissions(directory.join("bin"), std::fs::Permissions::from_mode(0o700)).unwrap();
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        File::options().write(true).open(directory.join("bin/tool")).unwrap().set_modified(modified).unwrap();
+        let report = crate::CopyJob::new(format!("{}//", directory.join("x.zip").display()))
+            .source(directory.join("bin"))
+            .options(crate::arguments::Args { recursive: true, no_progress: true, ..Default::default() })
+            .run();
+        assert!(report.is_success());
+
+        let mut zip = zip::ZipArchive::new(File::open(directory.join("x.zip")).unwrap()).unwrap();
+        let tool = zip.by_name("bin/tool").unwrap();
+        assert_eq!(tool.unix_mode().map(|mode| mode & 0o7777), Some(0o750));
+        assert_eq!(tool.last_modified(), from_system_time(modified));
+        drop(tool);
+        assert_eq!(zip.by_name("bin/").unwrap().unix_mode().map(|mode| mode & 0o7777), Some(0o700));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}